@@ -0,0 +1,28 @@
+#![no_main]
+
+use std::time::{Duration, Instant};
+
+use coconut::{
+    differential::differential,
+    evaluator::{Bytecode, TreeWalk},
+    limits::Limits,
+    parser::parse_str_strict,
+};
+use coconut_fuzz::Program;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|program: Program| {
+    let input = program.to_string();
+    if let Err(errs) = parse_str_strict(&input) {
+        panic!("generated program does not parse: {:?}\n{}", errs, input);
+    }
+    // Generated loops end, but bound the work as `eval_str` does.
+    let limits = Limits {
+        fuel: Some(100_000),
+        deadline: Some(Instant::now() + Duration::from_secs(1)),
+        ..Limits::default()
+    };
+    if let Err(divergence) = differential(&input, &limits, &Bytecode, &TreeWalk) {
+        panic!("{}\n{}", divergence, input);
+    }
+});
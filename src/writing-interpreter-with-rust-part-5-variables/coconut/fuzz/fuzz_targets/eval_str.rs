@@ -0,0 +1,22 @@
+#![no_main]
+
+use std::time::{Duration, Instant};
+
+use coconut::{
+    differential::differential,
+    evaluator::{Bytecode, TreeWalk},
+    limits::Limits,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: &str| {
+    // Loops and recursion can run forever, so bound the work.
+    let limits = Limits {
+        fuel: Some(100_000),
+        deadline: Some(Instant::now() + Duration::from_secs(1)),
+        ..Limits::default()
+    };
+    if let Err(divergence) = differential(input, &limits, &Bytecode, &TreeWalk) {
+        panic!("{}", divergence);
+    }
+});
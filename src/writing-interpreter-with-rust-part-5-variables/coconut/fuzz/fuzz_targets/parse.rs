@@ -0,0 +1,8 @@
+#![no_main]
+
+use coconut::parser::parse_str;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: &str| {
+    let _ = parse_str(input);
+});
@@ -0,0 +1,16 @@
+#![no_main]
+
+use std::io::sink;
+
+use coconut::{
+    bytecode::{run, Op},
+    context::Context,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|ops: Vec<Op>| {
+    // Jumps can loop forever, so bound the work.
+    let mut ctx = Context::new(Box::new(sink()));
+    ctx.limits.fuel = Some(100_000);
+    let _ = run(&ops, &mut ctx);
+});
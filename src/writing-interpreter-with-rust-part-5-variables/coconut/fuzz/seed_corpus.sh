@@ -0,0 +1,9 @@
+#!/usr/bin/env sh
+# Seeds the local corpora of the source-level fuzz targets with the sample
+# programs from `prog/`, so `cargo fuzz run <target>` has no network needs.
+set -e
+cd "$(dirname "$0")"
+for target in parse eval_str; do
+    mkdir -p "corpus/$target"
+    cp ../prog/*.cnt "corpus/$target/"
+done
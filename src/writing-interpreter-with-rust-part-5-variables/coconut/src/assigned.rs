@@ -0,0 +1,302 @@
+//! Definite assignment: a variable declared with `let x;` may only be read
+//! where every path to the read has assigned it.
+
+use std::collections::HashMap;
+
+use crate::{
+    ast::{Node, Span},
+    error::Error,
+    stack,
+};
+
+/// Refuses reads of variables declared without a value that may not have
+/// been assigned yet, pointing at the read.
+///
+/// A function body counts as running where the function is defined, so it
+/// may only read variables that are assigned by then, and what it assigns
+/// doesn't count after it. Names the program doesn't declare, such as those
+/// left by an earlier `Interpreter::eval`, are not checked; an unassigned
+/// one of those reads as nil.
+pub fn check(ast: &[Node]) -> Result<(), Error> {
+    let mut checker = Checker {
+        flow: Flow {
+            scopes: vec![HashMap::new()],
+            live: true,
+        },
+    };
+    checker.block(ast)
+}
+
+/// What is known at one point of the program: for each name in scope,
+/// whether it is assigned on every path there.
+#[derive(Clone)]
+struct Flow {
+    scopes: Vec<HashMap<String, bool>>,
+    /// False after a `return` or `throw`, where nothing runs.
+    live: bool,
+}
+
+impl Flow {
+    /// What is known where two paths meet: what each of them knows, or
+    /// what one knows if nothing runs after the other.
+    fn join(self, other: Flow) -> Flow {
+        match (self.live, other.live) {
+            (false, _) => other,
+            (_, false) => self,
+            _ => {
+                let scopes = self
+                    .scopes
+                    .into_iter()
+                    .zip(&other.scopes)
+                    .map(|(scope, theirs)| {
+                        scope
+                            .into_iter()
+                            .map(|(id, assigned)| {
+                                let assigned = assigned && theirs.get(&id) == Some(&true);
+                                (id, assigned)
+                            })
+                            .collect()
+                    })
+                    .collect();
+                Flow { scopes, live: true }
+            }
+        }
+    }
+}
+
+struct Checker {
+    flow: Flow,
+}
+
+impl Checker {
+    fn block(&mut self, body: &[Node]) -> Result<(), Error> {
+        body.iter().try_for_each(|node| self.node(node))
+    }
+
+    /// Runs `walk` in a new scope in which `names` are assigned.
+    fn scoped<'a>(
+        &mut self,
+        names: impl IntoIterator<Item = &'a str>,
+        walk: impl FnOnce(&mut Self) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let scope = names.into_iter().map(|id| (id.to_string(), true));
+        self.flow.scopes.push(scope.collect());
+        let result = walk(self);
+        self.flow.scopes.pop();
+        result
+    }
+
+    fn bind(&mut self, id: &str, assigned: bool) {
+        if let Some(scope) = self.flow.scopes.last_mut() {
+            scope.insert(id.to_string(), assigned);
+        }
+    }
+
+    fn read(&self, id: &str, span: Span) -> Result<(), Error> {
+        let assigned = self
+            .flow
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(id));
+        match assigned {
+            Some(false) if self.flow.live => Err(Error::Compile {
+                message: format!("Cannot read '{}' before it is assigned", id),
+                span,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn assign(&mut self, id: &str) {
+        let assigned = self
+            .flow
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(id));
+        if let Some(assigned) = assigned {
+            *assigned = true;
+        }
+    }
+
+    fn node(&mut self, node: &Node) -> Result<(), Error> {
+        stack::protect(|| self.visit(node))
+    }
+
+    fn visit(&mut self, node: &Node) -> Result<(), Error> {
+        match node {
+            Node::Id { value, span } => self.read(value, *span)?,
+            Node::Call { id, args, span } => {
+                self.block(args)?;
+                self.read(id, *span)?;
+            }
+            Node::Assign { id, op, rhs, span } => {
+                if op.is_some() {
+                    self.read(id, *span)?;
+                }
+                self.node(rhs)?;
+                self.assign(id);
+            }
+            Node::Declare { id, rhs } => {
+                if let Some(rhs) = rhs {
+                    self.node(rhs)?;
+                }
+                self.bind(id, rhs.is_some());
+            }
+            Node::Const { id, rhs, .. } => {
+                self.node(rhs)?;
+                self.bind(id, true);
+            }
+            Node::Import { name, .. } => self.bind(name, true),
+            Node::Function { name, params, body } => {
+                if let Some(name) = name {
+                    self.bind(name, true);
+                }
+                let outside = self.flow.clone();
+                let names = params.iter().chain(name).map(String::as_str);
+                self.scoped(names, |checker| checker.block(body))?;
+                self.flow = outside;
+            }
+            // The body may run no times, so what it assigns doesn't count
+            // after the loop.
+            Node::For { id, iterable, body } => {
+                self.node(iterable)?;
+                let before = self.flow.clone();
+                self.scoped([id.as_str()], |checker| checker.block(body))?;
+                self.flow = before;
+            }
+            // The handler may run after any part of the body, so it only
+            // knows what was assigned before the `try`.
+            Node::Try { body, id, handler } => {
+                let before = self.flow.clone();
+                self.scoped([], |checker| checker.block(body))?;
+                let after_body = std::mem::replace(&mut self.flow, before);
+                self.scoped([id.as_str()], |checker| checker.block(handler))?;
+                self.flow = after_body.join(self.flow.clone());
+            }
+            Node::Match {
+                scrutinee, arms, ..
+            } => {
+                self.node(scrutinee)?;
+                let before = self.flow.clone();
+                let mut after: Option<Flow> = None;
+                for arm in arms {
+                    self.flow = before.clone();
+                    self.scoped(arm.pattern.bindings(), |checker| {
+                        checker.block(&arm.body)?;
+                        arm.tail.iter().try_for_each(|tail| checker.node(tail))
+                    })?;
+                    let arm = self.flow.clone();
+                    after = Some(match after {
+                        Some(after) => after.join(arm),
+                        None => arm,
+                    });
+                }
+                self.flow = after.unwrap_or(before);
+            }
+            Node::Return { rhs } => {
+                if let Some(rhs) = rhs {
+                    self.node(rhs)?;
+                }
+                self.flow.live = false;
+            }
+            Node::Throw { rhs } => {
+                self.node(rhs)?;
+                self.flow.live = false;
+            }
+            _ => node
+                .children()
+                .into_iter()
+                .try_for_each(|child| self.node(child))?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod assigned_tests {
+    use super::*;
+    use crate::parser::parse_str_strict;
+
+    fn check_str(source: &str) -> Result<(), Error> {
+        check(&parse_str_strict(source).unwrap())
+    }
+
+    fn read_error(id: &str, line: usize, column: usize) -> Result<(), Error> {
+        Err(Error::Compile {
+            message: format!("Cannot read '{}' before it is assigned", id),
+            span: Span { line, column },
+        })
+    }
+
+    #[test]
+    fn reads_before_any_assignment_are_refused() {
+        assert_eq!(check_str("let x;\nprintln(x);"), read_error("x", 2, 9));
+        assert_eq!(check_str("let f;\nf(1);"), read_error("f", 2, 1));
+        assert_eq!(check_str("let x;\nx = x + 1;"), read_error("x", 2, 5));
+        assert_eq!(check_str("let x; x = 1; x;"), Ok(()));
+    }
+
+    #[test]
+    fn every_arm_of_a_match_must_assign() {
+        let one_arm = "let x;\nmatch true {\n  true => { x = 1; },\n  false => nil\n};\nx;";
+        assert_eq!(check_str(one_arm), read_error("x", 6, 1));
+        for source in [
+            "let x; match true { true => { x = 1; }, false => { x = 2; } }; x;",
+            "fn f(c) { let x; match c { true => { x = 1; }, false => { return 0; } }; x }",
+            "let x; match 1 { 1 => { throw 1; }, _ => { x = 2; } }; x;",
+        ] {
+            assert_eq!(check_str(source), Ok(()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn loops_and_handlers_may_not_have_assigned() {
+        assert_eq!(
+            check_str("let x;\nfor i in 0..3 {\n  x = i;\n}\nx;"),
+            read_error("x", 5, 1)
+        );
+        assert_eq!(check_str("let x; for i in 0..3 { x = i; x; }"), Ok(()));
+        assert_eq!(
+            check_str("let x;\ntry {\n  x = 1;\n} catch (e) {\n  x;\n}"),
+            read_error("x", 5, 3)
+        );
+        assert_eq!(
+            check_str("let x; try { x = [][0]; } catch (e) { x = e; } x;"),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn functions_read_what_is_assigned_where_they_are_defined() {
+        assert_eq!(
+            check_str("let x;\nfn f() {\n  x\n}\nx = 1;"),
+            read_error("x", 3, 3)
+        );
+        assert_eq!(
+            check_str("let x;\nfn f() { x = 1; }\nf();\nx;"),
+            read_error("x", 4, 1)
+        );
+        assert_eq!(check_str("let x = 1; fn f() { let y; y = x; y }"), Ok(()));
+    }
+
+    #[test]
+    fn shadowing_and_unreachable_reads_are_allowed() {
+        for source in [
+            "let x; fn f(x) { x }",
+            "let x; match 1 { x => x };",
+            "let x; for x in [1] { x; }",
+            "let x; let x = 2; x;",
+            "fn f() { let x; return 1; x }",
+            "y;",
+        ] {
+            assert_eq!(check_str(source), Ok(()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn interpolated_names_point_at_themselves() {
+        assert_eq!(check_str("let x;\n  \"a${x}\";"), read_error("x", 2, 7));
+    }
+}
@@ -1,11 +1,442 @@
+use std::{fmt, rc::Rc};
+
+#[cfg(feature = "bigint")]
+use crate::bigint::BigUint;
+use crate::value::Value;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Node {
-    Add { lhs: Box<Node>, rhs: Box<Node> },
-    Mul { lhs: Box<Node>, rhs: Box<Node> },
-    Number { value: u64 },
-    Id { value: String },
-    PrintLn { rhs: Box<Node> },
-    Assign { id: String, rhs: Box<Node> },
-    Declare { id: String, rhs: Option<Box<Node>> },
+    Add {
+        lhs: Box<Node>,
+        rhs: Box<Node>,
+    },
+    Sub {
+        lhs: Box<Node>,
+        rhs: Box<Node>,
+    },
+    Mul {
+        lhs: Box<Node>,
+        rhs: Box<Node>,
+    },
+    Div {
+        lhs: Box<Node>,
+        rhs: Box<Node>,
+    },
+    Rem {
+        lhs: Box<Node>,
+        rhs: Box<Node>,
+    },
+    Eq {
+        lhs: Box<Node>,
+        rhs: Box<Node>,
+    },
+    Ne {
+        lhs: Box<Node>,
+        rhs: Box<Node>,
+    },
+    Lt {
+        lhs: Box<Node>,
+        rhs: Box<Node>,
+    },
+    Le {
+        lhs: Box<Node>,
+        rhs: Box<Node>,
+    },
+    Gt {
+        lhs: Box<Node>,
+        rhs: Box<Node>,
+    },
+    Ge {
+        lhs: Box<Node>,
+        rhs: Box<Node>,
+    },
+    In {
+        lhs: Box<Node>,
+        rhs: Box<Node>,
+    },
+    /// `lhs..rhs`, the ints from `lhs` up to but excluding `rhs`.
+    Range {
+        lhs: Box<Node>,
+        rhs: Box<Node>,
+    },
+    Number {
+        value: u64,
+    },
+    /// An int literal too large for a `u64`.
+    #[cfg(feature = "bigint")]
+    BigNumber {
+        value: BigUint,
+    },
+    Float {
+        value: f64,
+    },
+    Str {
+        value: String,
+    },
+    /// A string literal with `${...}` in it: what each part displays as,
+    /// joined.
+    Interpolate {
+        parts: Vec<Node>,
+    },
+    Bool {
+        value: bool,
+    },
+    /// A variable read; `span` is where the name is.
+    Id {
+        value: String,
+        span: Span,
+    },
+    Array {
+        items: Vec<Node>,
+    },
+    Map {
+        entries: Vec<(Node, Node)>,
+    },
+    Index {
+        target: Box<Node>,
+        index: Box<Node>,
+    },
+    /// `target[index] = rhs`, or `target[index] op= rhs`.
+    SetIndex {
+        target: Box<Node>,
+        index: Box<Node>,
+        op: Option<Operator>,
+        rhs: Box<Node>,
+    },
+    PrintLn {
+        rhs: Box<Node>,
+    },
+    Nil,
+    /// `fn name(params) { body }` declares `name`; without a name it's an
+    /// expression. A tail expression ends the body as a `Return`. The body
+    /// is shared with the functions the tree walker makes of it rather than
+    /// copied, which would recurse as deep as it nests.
+    Function {
+        name: Option<String>,
+        params: Vec<String>,
+        body: Rc<Vec<Node>>,
+    },
+    Return {
+        rhs: Option<Box<Node>>,
+    },
+    /// `for id in iterable { body }`, with `id` declared afresh in each
+    /// iteration's scope.
+    For {
+        id: String,
+        iterable: Box<Node>,
+        body: Vec<Node>,
+    },
+    /// `struct name { fields }`.
+    Struct {
+        name: String,
+        fields: Vec<String>,
+        span: Span,
+    },
+    /// `name { field: value, ... }`.
+    StructLiteral {
+        name: String,
+        fields: Vec<FieldValue>,
+        span: Span,
+    },
+    /// `target.field`; `span` is where `field` is.
+    Field {
+        target: Box<Node>,
+        field: String,
+        span: Span,
+    },
+    SetField {
+        target: Box<Node>,
+        field: String,
+        op: Option<Operator>,
+        rhs: Box<Node>,
+        span: Span,
+    },
+    /// `throw rhs;`
+    Throw {
+        rhs: Box<Node>,
+    },
+    /// `try { body } catch (id) { handler }`
+    Try {
+        body: Vec<Node>,
+        id: String,
+        handler: Vec<Node>,
+    },
+    /// `enum name { variants }`, each variant with the number of values it
+    /// carries.
+    Enum {
+        name: String,
+        variants: Vec<(String, usize)>,
+        span: Span,
+    },
+    /// `ty::variant(args)`, or just `ty::variant` without values.
+    Variant {
+        ty: String,
+        variant: String,
+        args: Vec<Node>,
+        span: Span,
+    },
+    /// `match scrutinee { pattern => value, ... }`; `span` is where the
+    /// `match` keyword is.
+    Match {
+        scrutinee: Box<Node>,
+        arms: Vec<Arm>,
+        span: Span,
+    },
+    /// Calls the value of `callee`; calls by plain name are `Call`.
+    Apply {
+        callee: Box<Node>,
+        args: Vec<Node>,
+    },
+    Call {
+        id: String,
+        args: Vec<Node>,
+        span: Span,
+    },
+    /// `id = rhs`, or `id op= rhs`, which assigns `id op rhs`; `span` is
+    /// where the statement starts.
+    Assign {
+        id: String,
+        op: Option<Operator>,
+        rhs: Box<Node>,
+        span: Span,
+    },
+    /// `let id = rhs;`, or `let id;`, which declares `id` as nil until it
+    /// is assigned. Reads before then are refused by `assigned::check`.
+    Declare {
+        id: String,
+        rhs: Option<Box<Node>>,
+    },
+    /// `const id = rhs;`, which declares `id` for good: it can't be
+    /// assigned or declared again in the same scope.
+    Const {
+        id: String,
+        rhs: Box<Node>,
+        span: Span,
+    },
+    /// `import "path.cnt";`, or `import path;` when `search` is set, which
+    /// binds the module to `name`.
+    Import {
+        path: String,
+        search: bool,
+        name: String,
+    },
+    /// `export` before a top-level `let` or `fn`, which makes the name it
+    /// declares visible to importers.
+    Export {
+        decl: Box<Node>,
+        span: Span,
+    },
     Empty,
+    /// Marks where the statement after it starts, for stack traces.
+    Line {
+        line: usize,
+    },
+}
+
+impl Node {
+    /// The nodes directly below this one, in evaluation order.
+    pub fn children(&self) -> Vec<&Node> {
+        match self {
+            Node::Add { lhs, rhs }
+            | Node::Sub { lhs, rhs }
+            | Node::Mul { lhs, rhs }
+            | Node::Div { lhs, rhs }
+            | Node::Rem { lhs, rhs }
+            | Node::Eq { lhs, rhs }
+            | Node::Ne { lhs, rhs }
+            | Node::Lt { lhs, rhs }
+            | Node::Le { lhs, rhs }
+            | Node::Gt { lhs, rhs }
+            | Node::Ge { lhs, rhs }
+            | Node::In { lhs, rhs }
+            | Node::Range { lhs, rhs } => vec![lhs, rhs],
+            #[cfg(feature = "bigint")]
+            Node::BigNumber { .. } => vec![],
+            Node::Number { .. }
+            | Node::Float { .. }
+            | Node::Str { .. }
+            | Node::Bool { .. }
+            | Node::Id { .. }
+            | Node::Nil
+            | Node::Struct { .. }
+            | Node::Enum { .. }
+            | Node::Import { .. }
+            | Node::Empty
+            | Node::Line { .. } => vec![],
+            Node::Array { items } | Node::Interpolate { parts: items } => items.iter().collect(),
+            Node::Map { entries } => entries.iter().flat_map(|(k, v)| [k, v]).collect(),
+            Node::Index { target, index } => vec![target, index],
+            Node::SetIndex {
+                target, index, rhs, ..
+            } => vec![target, index, rhs],
+            Node::PrintLn { rhs }
+            | Node::Assign { rhs, .. }
+            | Node::Const { rhs, .. }
+            | Node::Throw { rhs } => vec![rhs],
+            Node::Try { body, handler, .. } => body.iter().chain(handler).collect(),
+            Node::Declare { rhs, .. } | Node::Return { rhs } => {
+                rhs.iter().map(|rhs| &**rhs).collect()
+            }
+            Node::Function { body, .. } => body.iter().collect(),
+            Node::For { iterable, body, .. } => std::iter::once(&**iterable).chain(body).collect(),
+            Node::StructLiteral { fields, .. } => fields.iter().map(|field| &field.value).collect(),
+            Node::Field { target, .. } | Node::Export { decl: target, .. } => vec![target],
+            Node::SetField { target, rhs, .. } => vec![target, rhs],
+            Node::Call { args, .. } | Node::Variant { args, .. } => args.iter().collect(),
+            Node::Match {
+                scrutinee, arms, ..
+            } => std::iter::once(&**scrutinee)
+                .chain(arms.iter().flat_map(|arm| arm.body.iter().chain(&arm.tail)))
+                .collect(),
+            Node::Apply { callee, args } => std::iter::once(&**callee).chain(args).collect(),
+        }
+    }
+
+    /// Like `children`, for rewriting them.
+    pub fn children_mut(&mut self) -> Vec<&mut Node> {
+        match self {
+            Node::Add { lhs, rhs }
+            | Node::Sub { lhs, rhs }
+            | Node::Mul { lhs, rhs }
+            | Node::Div { lhs, rhs }
+            | Node::Rem { lhs, rhs }
+            | Node::Eq { lhs, rhs }
+            | Node::Ne { lhs, rhs }
+            | Node::Lt { lhs, rhs }
+            | Node::Le { lhs, rhs }
+            | Node::Gt { lhs, rhs }
+            | Node::Ge { lhs, rhs }
+            | Node::In { lhs, rhs }
+            | Node::Range { lhs, rhs } => vec![lhs, rhs],
+            #[cfg(feature = "bigint")]
+            Node::BigNumber { .. } => vec![],
+            Node::Number { .. }
+            | Node::Float { .. }
+            | Node::Str { .. }
+            | Node::Bool { .. }
+            | Node::Id { .. }
+            | Node::Nil
+            | Node::Struct { .. }
+            | Node::Enum { .. }
+            | Node::Import { .. }
+            | Node::Empty
+            | Node::Line { .. } => vec![],
+            Node::Array { items } | Node::Interpolate { parts: items } => {
+                items.iter_mut().collect()
+            }
+            Node::Map { entries } => entries.iter_mut().flat_map(|(k, v)| [k, v]).collect(),
+            Node::Index { target, index } => vec![target, index],
+            Node::SetIndex {
+                target, index, rhs, ..
+            } => vec![target, index, rhs],
+            Node::PrintLn { rhs }
+            | Node::Assign { rhs, .. }
+            | Node::Const { rhs, .. }
+            | Node::Throw { rhs } => vec![rhs],
+            Node::Try { body, handler, .. } => body.iter_mut().chain(handler).collect(),
+            Node::Declare { rhs, .. } | Node::Return { rhs } => {
+                rhs.iter_mut().map(|rhs| &mut **rhs).collect()
+            }
+            Node::Function { body, .. } => Rc::make_mut(body).iter_mut().collect(),
+            Node::For { iterable, body, .. } => {
+                std::iter::once(&mut **iterable).chain(body).collect()
+            }
+            Node::StructLiteral { fields, .. } => {
+                fields.iter_mut().map(|field| &mut field.value).collect()
+            }
+            Node::Field { target, .. } | Node::Export { decl: target, .. } => vec![target],
+            Node::SetField { target, rhs, .. } => vec![target, rhs],
+            Node::Call { args, .. } | Node::Variant { args, .. } => args.iter_mut().collect(),
+            Node::Match {
+                scrutinee, arms, ..
+            } => std::iter::once(&mut **scrutinee)
+                .chain(
+                    arms.iter_mut()
+                        .flat_map(|arm| arm.body.iter_mut().chain(&mut arm.tail)),
+                )
+                .collect(),
+            Node::Apply { callee, args } => std::iter::once(&mut **callee).chain(args).collect(),
+        }
+    }
+}
+
+/// The arithmetic of a compound assignment such as `+=`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl Operator {
+    pub fn apply(self, lhs: &Value, rhs: &Value) -> Result<Value, String> {
+        match self {
+            Operator::Add => lhs.add(rhs),
+            Operator::Sub => lhs.sub(rhs),
+            Operator::Mul => lhs.mul(rhs),
+            Operator::Div => lhs.div(rhs),
+            Operator::Rem => lhs.rem(rhs),
+        }
+    }
+}
+
+/// One `pattern => body` of a match. Its value is that of `tail`, or nil
+/// if there is none or it doesn't produce one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Arm {
+    pub pattern: Pattern,
+    pub body: Vec<Node>,
+    pub tail: Option<Node>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    /// `_`, which matches anything.
+    Wildcard,
+    /// A name, which matches anything and binds it in the arm.
+    Bind(String),
+    /// A number, string, bool or nil, which matches values equal to it.
+    Literal(Value),
+    /// `ty::variant(patterns)`.
+    Variant {
+        ty: String,
+        variant: String,
+        args: Vec<Pattern>,
+        span: Span,
+    },
+}
+
+impl Pattern {
+    /// The names the pattern binds, in order.
+    pub fn bindings(&self) -> Vec<&str> {
+        match self {
+            Pattern::Bind(name) => vec![name],
+            Pattern::Variant { args, .. } => args.iter().flat_map(Pattern::bindings).collect(),
+            Pattern::Wildcard | Pattern::Literal(_) => vec![],
+        }
+    }
+}
+
+/// One `field: value` of a struct literal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldValue {
+    pub name: String,
+    pub value: Node,
+    pub span: Span,
+}
+
+/// Where something starts in the source, for errors that point at it.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
 }
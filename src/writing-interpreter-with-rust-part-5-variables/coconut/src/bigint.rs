@@ -0,0 +1,308 @@
+//! A small arbitrary-precision unsigned integer, enough for the interpreter's
+//! arithmetic without pulling in a dependency.
+
+use std::{cmp::Ordering, fmt};
+
+/// Little-endian base 2^32 limbs with no trailing zero limbs, so every value
+/// has exactly one representation and zero has no limbs at all.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct BigUint {
+    limbs: Vec<u32>,
+}
+
+impl BigUint {
+    fn normalized(mut limbs: Vec<u32>) -> Self {
+        while limbs.last() == Some(&0) {
+            limbs.pop();
+        }
+        BigUint { limbs }
+    }
+
+    /// Parses a non-empty string of decimal digits.
+    pub fn parse(s: &str) -> Option<Self> {
+        if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let mut n = BigUint::default();
+        for chunk in s.as_bytes().chunks(9) {
+            let digits: u64 = std::str::from_utf8(chunk).ok()?.parse().ok()?;
+            n = n
+                .mul(&BigUint::from(10u64.pow(chunk.len() as u32)))
+                .add(&BigUint::from(digits));
+        }
+        Some(n)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+
+    /// How many 32-bit limbs the value takes.
+    pub fn limbs(&self) -> usize {
+        self.limbs.len()
+    }
+
+    pub fn to_u64(&self) -> Option<u64> {
+        match self.limbs[..] {
+            [] => Some(0),
+            [low] => Some(low as u64),
+            [low, high] => Some(low as u64 | (high as u64) << 32),
+            _ => None,
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.limbs
+            .iter()
+            .rev()
+            .fold(0.0, |acc, &limb| acc * 4294967296.0 + limb as f64)
+    }
+
+    /// The integer `x` is exactly, if it is a whole non-negative number.
+    pub fn from_f64(x: f64) -> Option<Self> {
+        if !x.is_finite() || x < 0.0 || x.fract() != 0.0 {
+            return None;
+        }
+        if x < 18446744073709551616.0 {
+            return Some(BigUint::from(x as u64));
+        }
+        // At 2^64 and above the mantissa is shifted left by a whole number
+        // of bits: whole limbs of zeros, then the rest within three limbs.
+        let bits = x.to_bits();
+        let mantissa = (bits & ((1 << 52) - 1)) | (1 << 52);
+        let exponent = ((bits >> 52) & 0x7ff) as usize - 1075;
+        let shifted = (mantissa as u128) << (exponent % 32);
+        let mut limbs = vec![0; exponent / 32];
+        limbs.extend((0..3).map(|i| (shifted >> (32 * i)) as u32));
+        Some(BigUint::normalized(limbs))
+    }
+
+    pub fn add(&self, rhs: &BigUint) -> BigUint {
+        let (long, short) = if self.limbs.len() >= rhs.limbs.len() {
+            (self, rhs)
+        } else {
+            (rhs, self)
+        };
+        let mut limbs = Vec::with_capacity(long.limbs.len() + 1);
+        let mut carry = 0;
+        for (i, &limb) in long.limbs.iter().enumerate() {
+            let sum = limb as u64 + short.limb(i) as u64 + carry;
+            limbs.push(sum as u32);
+            carry = sum >> 32;
+        }
+        limbs.push(carry as u32);
+        BigUint::normalized(limbs)
+    }
+
+    /// `None` if the result would be negative.
+    pub fn checked_sub(&self, rhs: &BigUint) -> Option<BigUint> {
+        if self < rhs {
+            return None;
+        }
+        let mut limbs = Vec::with_capacity(self.limbs.len());
+        let mut borrow = 0;
+        for (i, &limb) in self.limbs.iter().enumerate() {
+            let diff = limb as i64 - rhs.limb(i) as i64 - borrow;
+            borrow = (diff < 0) as i64;
+            limbs.push((diff + (borrow << 32)) as u32);
+        }
+        Some(BigUint::normalized(limbs))
+    }
+
+    pub fn mul(&self, rhs: &BigUint) -> BigUint {
+        let mut limbs = vec![0u32; self.limbs.len() + rhs.limbs.len()];
+        for (i, &a) in self.limbs.iter().enumerate() {
+            let mut carry = 0;
+            for (j, &b) in rhs.limbs.iter().enumerate() {
+                let product = a as u64 * b as u64 + limbs[i + j] as u64 + carry;
+                limbs[i + j] = product as u32;
+                carry = product >> 32;
+            }
+            limbs[i + rhs.limbs.len()] = carry as u32;
+        }
+        BigUint::normalized(limbs)
+    }
+
+    /// Quotient and remainder, or `None` when dividing by zero. Plain binary
+    /// long division: slow, but the values we see are small.
+    pub fn checked_div_rem(&self, rhs: &BigUint) -> Option<(BigUint, BigUint)> {
+        if rhs.is_zero() {
+            return None;
+        }
+        let mut quotient = vec![0u32; self.limbs.len()];
+        let mut rem = BigUint::default();
+        for i in (0..self.bits()).rev() {
+            rem = rem.shl1(self.bit(i));
+            if rem >= *rhs {
+                rem = rem.checked_sub(rhs)?;
+                quotient[i / 32] |= 1 << (i % 32);
+            }
+        }
+        Some((BigUint::normalized(quotient), rem))
+    }
+
+    fn limb(&self, i: usize) -> u32 {
+        self.limbs.get(i).copied().unwrap_or(0)
+    }
+
+    fn bits(&self) -> usize {
+        match self.limbs.last() {
+            Some(top) => self.limbs.len() * 32 - top.leading_zeros() as usize,
+            None => 0,
+        }
+    }
+
+    fn bit(&self, i: usize) -> bool {
+        self.limb(i / 32) >> (i % 32) & 1 == 1
+    }
+
+    /// `self * 2 + low_bit`.
+    fn shl1(&self, low_bit: bool) -> BigUint {
+        let mut limbs = Vec::with_capacity(self.limbs.len() + 1);
+        let mut carry = low_bit as u32;
+        for &limb in &self.limbs {
+            limbs.push(limb << 1 | carry);
+            carry = limb >> 31;
+        }
+        limbs.push(carry);
+        BigUint::normalized(limbs)
+    }
+
+    fn div_rem_small(&self, divisor: u32) -> (BigUint, u32) {
+        let mut limbs = vec![0u32; self.limbs.len()];
+        let mut rem = 0u64;
+        for (i, &limb) in self.limbs.iter().enumerate().rev() {
+            let current = rem << 32 | limb as u64;
+            limbs[i] = (current / divisor as u64) as u32;
+            rem = current % divisor as u64;
+        }
+        (BigUint::normalized(limbs), rem as u32)
+    }
+}
+
+impl From<u64> for BigUint {
+    fn from(n: u64) -> Self {
+        BigUint::normalized(vec![n as u32, (n >> 32) as u32])
+    }
+}
+
+impl Ord for BigUint {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.limbs
+            .len()
+            .cmp(&other.limbs.len())
+            .then_with(|| self.limbs.iter().rev().cmp(other.limbs.iter().rev()))
+    }
+}
+
+impl PartialOrd for BigUint {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for BigUint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Peel off nine decimal digits at a time, least significant first.
+        let mut chunks = vec![];
+        let mut rest = self.clone();
+        loop {
+            let (quotient, chunk) = rest.div_rem_small(1_000_000_000);
+            chunks.push(chunk);
+            if quotient.is_zero() {
+                break;
+            }
+            rest = quotient;
+        }
+        let mut chunks = chunks.iter().rev();
+        if let Some(first) = chunks.next() {
+            write!(f, "{}", first)?;
+        }
+        for chunk in chunks {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+/// Always larger than `u64::MAX`, the only values `Value::Big` holds.
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for BigUint {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let mut limbs: Vec<u32> = u.arbitrary()?;
+        limbs.resize(limbs.len().max(2), 0);
+        limbs.push(u.int_in_range(1..=u32::MAX)?);
+        Ok(BigUint { limbs })
+    }
+}
+
+#[cfg(test)]
+mod bigint_tests {
+    use super::*;
+
+    fn big(s: &str) -> BigUint {
+        BigUint::parse(s).unwrap()
+    }
+
+    #[test]
+    fn parse_and_display_round_trip() {
+        for s in [
+            "0",
+            "7",
+            "4294967296",
+            "18446744073709551616",
+            "1000000000000000000000000000",
+        ] {
+            assert_eq!(big(s).to_string(), s);
+        }
+        assert_eq!(big("007").to_string(), "7");
+        assert_eq!(BigUint::parse(""), None);
+        assert_eq!(BigUint::parse("12a"), None);
+    }
+
+    #[test]
+    fn converts_to_machine_numbers() {
+        assert_eq!(big("18446744073709551615").to_u64(), Some(u64::MAX));
+        assert_eq!(big("18446744073709551616").to_u64(), None);
+        assert_eq!(big("18446744073709551616").to_f64(), 18446744073709551616.0);
+        assert_eq!(BigUint::from_f64(3.0), Some(3.into()));
+        assert_eq!(
+            BigUint::from_f64(2f64.powi(100)),
+            Some(big("1267650600228229401496703205376"))
+        );
+        assert_eq!(BigUint::from_f64(0.5), None);
+        assert_eq!(BigUint::from_f64(-1.0), None);
+        assert_eq!(BigUint::from_f64(f64::NAN), None);
+    }
+
+    #[test]
+    fn arithmetic_carries_across_limbs() {
+        let max = BigUint::from(u64::MAX);
+        assert_eq!(max.add(&1.into()).to_string(), "18446744073709551616");
+        assert_eq!(
+            max.mul(&max).to_string(),
+            "340282366920938463426481119284349108225"
+        );
+        assert_eq!(
+            big("18446744073709551616").checked_sub(&1.into()),
+            Some(max.clone())
+        );
+        assert_eq!(BigUint::from(1).checked_sub(&2.into()), None);
+    }
+
+    #[test]
+    fn division_returns_quotient_and_remainder() {
+        let (q, r) = big("340282366920938463426481119284349108226")
+            .checked_div_rem(&u64::MAX.into())
+            .unwrap();
+        assert_eq!((q.to_u64(), r.to_u64()), (Some(u64::MAX), Some(1)));
+        assert_eq!(BigUint::from(1).checked_div_rem(&BigUint::default()), None);
+    }
+
+    #[test]
+    fn ordering_compares_magnitude() {
+        assert!(big("18446744073709551616") > BigUint::from(u64::MAX));
+        assert!(big("4294967296") < big("4294967297"));
+        assert_eq!(big("12").cmp(&12.into()), Ordering::Equal);
+    }
+}
@@ -0,0 +1,235 @@
+#[cfg(feature = "bigint")]
+use crate::bigint::BigUint;
+use crate::{context::Context, error::Error, format, value::Value};
+
+/// Calls the builtin `name` if there is one. Builtins, unlike host
+/// functions, get the whole context, e.g. to read from its input.
+pub fn call(ctx: &mut Context, name: &str, args: &[Value]) -> Option<Result<Value, Error>> {
+    let result = match name {
+        "readln" => arity(name, args, 0).and_then(|_| readln(ctx)),
+        "parse_int" => arity(name, args, 1).and_then(|_| parse_int(&args[0])),
+        "len" => arity(name, args, 1).and_then(|_| len(&args[0])),
+        "push" => arity(name, args, 2).and_then(|_| args[0].push(args[1].clone())),
+        "pop" => arity(name, args, 1).and_then(|_| args[0].pop()),
+        "get" => arity(name, args, 3).and_then(|_| args[0].get(&args[1], args[2].clone())),
+        "keys" => arity(name, args, 1).and_then(|_| args[0].keys()),
+        "int" => arity(name, args, 1).and_then(|_| int(&args[0])),
+        "float" => arity(name, args, 1).and_then(|_| float(&args[0])),
+        "str" => arity(name, args, 1).map(|_| Value::Str(args[0].to_string())),
+        "format" => format(args),
+        _ => return None,
+    };
+    Some(result)
+}
+
+pub(crate) fn arity(name: &str, args: &[Value], expected: usize) -> Result<(), Error> {
+    if args.len() != expected {
+        return Err(format!(
+            "Function '{}' expects {} argument(s), got {}",
+            name,
+            expected,
+            args.len()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn readln(ctx: &mut Context) -> Result<Value, Error> {
+    match ctx.input.read_line() {
+        Ok(Some(line)) => Ok(Value::Str(line)),
+        Ok(None) => Err("readln: end of input".to_string().into()),
+        Err(e) => Err(format!("readln: {}", e).into()),
+    }
+}
+
+fn parse_int(arg: &Value) -> Result<Value, Error> {
+    let s = arg.as_str().map_err(|e| format!("parse_int: {}", e))?;
+    if let Ok(n) = s.trim().parse::<u64>() {
+        return Ok(Value::Int(n));
+    }
+    #[cfg(feature = "bigint")]
+    if let Some(n) = BigUint::parse(s.trim()) {
+        return Ok(Value::from(n));
+    }
+    Err(format!("parse_int: '{}' is not an integer", s).into())
+}
+
+fn len(arg: &Value) -> Result<Value, Error> {
+    match arg {
+        Value::Str(s) => Ok(Value::Int(s.chars().count() as u64)),
+        Value::Array(items) => Ok(Value::Int(items.borrow().len() as u64)),
+        Value::Map(entries) => Ok(Value::Int(entries.borrow().len() as u64)),
+        Value::Range(start, end) => Ok(Value::Int(end.saturating_sub(*start))),
+        _ => Err(format!("len: unsupported type {}", arg.type_name()).into()),
+    }
+}
+
+/// Truncates floats towards zero; ints are unsigned, so negative and
+/// non-finite floats are rejected.
+fn int(arg: &Value) -> Result<Value, Error> {
+    match arg {
+        _ if arg.is_int() => Ok(arg.clone()),
+        Value::Float(f) if f.is_finite() && *f > -1.0 && *f < u64::MAX as f64 => {
+            Ok(Value::Int(*f as u64))
+        }
+        Value::Float(f) => Err(format!("int: {:?} cannot be represented as an int", f).into()),
+        _ => Err(format!("int: Expected number, got {}", arg.type_name()).into()),
+    }
+}
+
+fn float(arg: &Value) -> Result<Value, Error> {
+    match arg {
+        Value::Int(n) => Ok(Value::Float(*n as f64)),
+        #[cfg(feature = "bigint")]
+        Value::Big(n) => Ok(Value::Float(n.to_f64())),
+        Value::Float(f) => Ok(Value::Float(*f)),
+        _ => Err(format!("float: Expected number, got {}", arg.type_name()).into()),
+    }
+}
+
+fn format(args: &[Value]) -> Result<Value, Error> {
+    let (template, args) = args
+        .split_first()
+        .ok_or("Function 'format' expects at least 1 argument(s), got 0".to_string())?;
+    let template = template.as_str().map_err(|e| format!("format: {}", e))?;
+    match format::format(template, args) {
+        Ok(s) => Ok(Value::Str(s)),
+        Err(e) => Err(format!("format: {}", e).into()),
+    }
+}
+
+#[cfg(test)]
+mod builtins_tests {
+    use super::*;
+    use std::io::sink;
+
+    fn ctx(input: &'static str) -> Context<'static> {
+        let mut ctx = Context::new(Box::new(sink()));
+        ctx.input = Box::new(input.as_bytes());
+        ctx
+    }
+
+    #[test]
+    fn readln_reads_lines() {
+        let mut ctx = ctx("12\n");
+        assert_eq!(call(&mut ctx, "readln", &[]), Some(Ok("12".into())));
+        assert_eq!(
+            call(&mut ctx, "readln", &[]),
+            Some(Err(Error::Runtime("readln: end of input".to_string())))
+        );
+    }
+
+    #[test]
+    fn parse_int_parses() {
+        let mut ctx = ctx("");
+        assert_eq!(
+            call(&mut ctx, "parse_int", &[" 42 ".into()]),
+            Some(Ok(Value::Int(42)))
+        );
+        assert_eq!(
+            call(&mut ctx, "parse_int", &["4x".into()]),
+            Some(Err(Error::Runtime(
+                "parse_int: '4x' is not an integer".to_string()
+            )))
+        );
+        assert_eq!(
+            call(&mut ctx, "parse_int", &[Value::Int(1)]),
+            Some(Err(Error::Runtime(
+                "parse_int: Expected string, got int".to_string()
+            )))
+        );
+    }
+
+    #[test]
+    fn len_counts_characters() {
+        let mut ctx = ctx("");
+        assert_eq!(
+            call(&mut ctx, "len", &["héllo".into()]),
+            Some(Ok(Value::Int(5)))
+        );
+        assert_eq!(
+            call(&mut ctx, "len", &[Value::Int(5)]),
+            Some(Err(Error::Runtime("len: unsupported type int".to_string())))
+        );
+    }
+
+    #[test]
+    fn int_truncates_floats() {
+        let mut ctx = ctx("");
+        assert_eq!(
+            call(&mut ctx, "int", &[Value::Float(2.9)]),
+            Some(Ok(Value::Int(2)))
+        );
+        assert_eq!(
+            call(&mut ctx, "int", &[Value::Float(-0.5)]),
+            Some(Ok(Value::Int(0)))
+        );
+        assert_eq!(
+            call(&mut ctx, "int", &[Value::Float(-1.0)]),
+            Some(Err(Error::Runtime(
+                "int: -1.0 cannot be represented as an int".to_string()
+            )))
+        );
+        assert_eq!(
+            call(&mut ctx, "int", &[Value::Float(f64::NAN)]),
+            Some(Err(Error::Runtime(
+                "int: NaN cannot be represented as an int".to_string()
+            )))
+        );
+    }
+
+    #[test]
+    fn float_converts_numbers() {
+        let mut ctx = ctx("");
+        assert_eq!(
+            call(&mut ctx, "float", &[Value::Int(2)]),
+            Some(Ok(Value::Float(2.0)))
+        );
+        assert_eq!(
+            call(&mut ctx, "float", &["2".into()]),
+            Some(Err(Error::Runtime(
+                "float: Expected number, got string".to_string()
+            )))
+        );
+    }
+
+    #[test]
+    fn str_converts_any_value() {
+        let mut ctx = ctx("");
+        assert_eq!(
+            call(&mut ctx, "str", &[Value::Int(5)]),
+            Some(Ok("5".into()))
+        );
+        assert_eq!(
+            call(&mut ctx, "str", &[Value::Bool(true)]),
+            Some(Ok("true".into()))
+        );
+    }
+
+    #[test]
+    fn format_checks_its_template() {
+        let mut ctx = ctx("");
+        assert_eq!(
+            call(&mut ctx, "format", &["{:>3}|".into(), Value::Int(5)]),
+            Some(Ok("  5|".into()))
+        );
+        assert_eq!(
+            call(&mut ctx, "format", &[Value::Int(5)]),
+            Some(Err(Error::Runtime(
+                "format: Expected string, got int".to_string()
+            )))
+        );
+        assert_eq!(
+            call(&mut ctx, "format", &["{}".into()]),
+            Some(Err(Error::Runtime(
+                "format: missing argument for placeholder 1".to_string()
+            )))
+        );
+    }
+
+    #[test]
+    fn unknown_names_are_not_builtins() {
+        assert_eq!(call(&mut ctx(""), "nope", &[]), None);
+    }
+}
@@ -1,4 +1,19 @@
-use crate::{ast::Node, scope::Scope};
+use std::{
+    collections::BTreeMap,
+    ops::{Index, IndexMut},
+    rc::Rc,
+};
+
+use crate::{
+    assigned,
+    ast::{Arm, Node, Operator, Pattern, Span},
+    constant,
+    context::{Caller, Context, Resolved},
+    error::{Error, Resource},
+    function::{self, Closure, Code, Prototype},
+    module, stack,
+    value::Value,
+};
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum BinaryOp {
@@ -9,87 +24,1385 @@ pub enum BinaryOp {
 }
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum Op {
-    Add,                 // Addition operation
-    Mull,                // Multiplication operation
-    Push { value: u64 }, // Load numeric value onto stack
-    Assign { name: String },
-    Declare { name: String },
+    Add, // Addition operation
+    Sub,
+    Mull, // Multiplication operation
+    Div,
+    Rem,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    In,
+    Range,
+    Push {
+        value: Value,
+    }, // Load a constant onto the stack
+    Assign {
+        name: String,
+    },
+    Declare {
+        name: String,
+    },
+    Const {
+        name: String,
+    }, // Pop a value and declare it as a constant
     PrintLn,
-    Load { id: String },
+    Load {
+        id: String,
+    },
+    Dup,  // Push a copy of the top value
+    Dup2, // Push copies of the top two values
+    Tuck {
+        depth: usize,
+    }, // Copy the top value below the `depth` under it
+    // Call the variable, host function, builtin or prelude function `name`
+    // with `argc` arguments from the stack; compiled functions run in a new
+    // VM frame
+    Call {
+        name: String,
+        argc: usize,
+    },
+    MakeArray {
+        len: usize,
+    }, // Collect the top `len` values into a new array
+    MakeMap {
+        len: usize,
+    }, // Collect `len` key/value pairs into a new map
+    Interpolate {
+        len: usize,
+    }, // Join what the top `len` values display as
+    Index,
+    SetIndex, // Pops value, index and array, in that order
+    Closure {
+        proto: Rc<Prototype>,
+    }, // Create a closure capturing the current locals
+    CallValue {
+        argc: usize,
+    }, // Call the function value below `argc` arguments
+    Return,   // Leave the running function with the top value
+    Pop,      // Discard the value of an expression statement
+    Jump {
+        target: usize,
+    }, // Continue at the op with index `target`
+    Iter,     // Replace the top value with what `Next` iterates over, and a counter
+    Next {
+        exit: usize,
+    }, // Push the next item and count it, or pop both and jump to `exit`
+    PushBlock,
+    PopBlock,
+    MakeStruct {
+        name: String,
+        fields: Vec<String>,
+    }, // Build a record from one value per field
+    GetField {
+        field: String,
+        span: Span,
+    },
+    SetField {
+        field: String,
+        span: Span,
+    }, // Pops value and record, in that order
+    MakeVariant {
+        ty: String,
+        variant: String,
+        argc: usize,
+    }, // Build a variant from `argc` values
+    MatchLiteral {
+        value: Value,
+        fail: usize,
+    }, // Pop a value; jump to `fail` unless it equals `value`
+    // Pop a value; push what it carries, first on top, or jump to `fail`
+    MatchVariant {
+        ty: String,
+        variant: String,
+        fail: usize,
+    },
+    NoMatch, // Fail with the value that no match arm matched
+    Try {
+        catch: usize,
+    }, // Until `EndTry`, catchable errors jump to `catch` with their value
+    EndTry,
+    Throw, // Raise the top value as an exception
+    // Load the module `path`, or find `path.cnt` on the search path, and
+    // push it
+    Import {
+        path: String,
+        search: bool,
+    },
+}
+
+/// Compiled code: its ops and a line table mapping them back to the source.
+#[derive(Debug, PartialEq, Default)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct Chunk {
+    pub ops: Vec<Op>,
+    /// `(op, line)` for the first op of each line, in order of the ops.
+    pub lines: Vec<(usize, usize)>,
+}
+
+impl Chunk {
+    pub fn push(&mut self, op: Op) {
+        self.ops.push(op);
+    }
+
+    pub fn extend(&mut self, ops: impl IntoIterator<Item = Op>) {
+        self.ops.extend(ops);
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Records that the ops from here on come from `line`.
+    fn mark(&mut self, line: usize) {
+        match self.lines.last_mut() {
+            Some((op, last)) if *op == self.ops.len() => *last = line,
+            Some((_, last)) if *last == line => {}
+            _ => self.lines.push((self.ops.len(), line)),
+        }
+    }
+}
+
+impl Index<usize> for Chunk {
+    type Output = Op;
+
+    fn index(&self, index: usize) -> &Op {
+        &self.ops[index]
+    }
+}
+
+impl IndexMut<usize> for Chunk {
+    fn index_mut(&mut self, index: usize) -> &mut Op {
+        &mut self.ops[index]
+    }
+}
+
+/// The line the op at `pc` was compiled from, 0 if `lines` doesn't say.
+fn line_at(lines: &[(usize, usize)], pc: usize) -> usize {
+    match lines.partition_point(|&(op, _)| op <= pc) {
+        0 => 0,
+        i => lines[i - 1].1,
+    }
+}
+
+/// Where a `try` that is running catches errors: the op that starts its
+/// `catch` block, and the stack height and scope depth to go back to.
+struct Handler {
+    catch: usize,
+    height: usize,
+    depth: usize,
 }
 
-pub fn eval(ast: Vec<Node>, scope: &mut Scope) -> Result<Option<u64>, String> {
-    let ops = &mut vec![];
+/// How a sequence of ops finished.
+enum Exit {
+    /// Ran off the end, leaving this on top of the stack.
+    End(Option<Value>),
+    Return(Value),
+}
+
+/// A call the VM is running: the compiled function whose ops it runs, or
+/// `None` for the ops `execute` was given, with its own value stack and
+/// handlers, and where it was called from.
+struct CallFrame {
+    closure: Option<Rc<Closure>>,
+    caller: Option<Caller>,
+    pc: usize,
+    stack: Vec<Value>,
+    handlers: Vec<Handler>,
+}
+
+impl CallFrame {
+    fn new(closure: Option<Rc<Closure>>, caller: Option<Caller>) -> Self {
+        CallFrame {
+            closure,
+            caller,
+            pc: 0,
+            stack: vec![],
+            handlers: vec![],
+        }
+    }
+}
+
+/// Why a frame stopped running its ops.
+enum Run {
+    Exit(Exit),
+    /// To call a compiled function with these arguments, in a new frame.
+    Call(Rc<Closure>, Vec<Value>),
+}
+
+pub fn eval(mut ast: Vec<Node>, ctx: &mut Context) -> Result<Option<Value>, Error> {
+    ctx.types.check(&ast)?;
+    module::exports(&ast)?;
+    assigned::check(&ast)?;
+    constant::inline(&mut ast)?;
+    let chunk = &mut Chunk::default();
     for a in ast {
-        ast_to_bytecode(a, ops);
-    }
-    let mut stack: Vec<u64> = vec![];
-
-    for instruction in ops {
-        match instruction {
-            Op::Push { value } => stack.push(*value),
-            Op::Add => {
-                let rhs = stack.pop().unwrap();
-                let lhs = stack.pop().unwrap();
-                stack.push(lhs + rhs);
-            }
-            Op::Mull {} => {
-                let rhs = stack.pop().unwrap();
-                let lhs = stack.pop().unwrap();
-                stack.push(lhs * rhs);
-            }
-            Op::Assign { name } => {
-                let val = stack.pop().unwrap();
-                scope.set_var(name.clone(), val);
-            }
-            Op::Declare { name } => {
-                let val = stack.pop().unwrap();
-                scope.dec_var(name.clone(), val);
-            }
-            Op::PrintLn => {
-                println!("{}", stack.pop().unwrap());
-            }
-            Op::Load { id } => {
-                if let Some(value) = scope.get_var(id.clone()) {
-                    stack.push(value.clone());
-                } else {
-                    return Err(format!("Variable '{}' not found", id.clone()));
+        ast_to_bytecode(a, chunk);
+    }
+    run_lines(&chunk.ops, &chunk.lines, ctx)
+}
+
+/// Executes already compiled `ops`. Malformed sequences, such as an operator
+/// with too few operands on the stack, are reported as errors, not panics.
+/// Every instruction is charged against `ctx.limits` before it runs.
+pub fn run(ops: &[Op], ctx: &mut Context) -> Result<Option<Value>, Error> {
+    run_lines(ops, &[], ctx)
+}
+
+fn run_lines(
+    ops: &[Op],
+    lines: &[(usize, usize)],
+    ctx: &mut Context,
+) -> Result<Option<Value>, Error> {
+    let depth = ctx.scope.depth();
+    let exit = execute(ops, lines, ctx);
+    ctx.scope.unwind_to(depth);
+    match exit? {
+        Exit::End(value) => Ok(value),
+        Exit::Return(value) => Ok(Some(value)),
+    }
+}
+
+/// Runs the body of a function whose frame `ctx` has already entered. A
+/// body that ends without `Op::Return` returns nil.
+pub fn call_function(chunk: &Chunk, ctx: &mut Context) -> Result<Value, Error> {
+    match execute(&chunk.ops, &chunk.lines, ctx)? {
+        Exit::End(_) => Ok(Value::Nil),
+        Exit::Return(value) => Ok(value),
+    }
+}
+
+/// Runs `ops` until they end or return. Calls of compiled functions run
+/// here too, each in a frame of its own rather than a native call, so their
+/// depth is only bounded by `Limits::max_call_depth`. An error that leaves
+/// `ops` sets `ctx.line` to the line of the op that failed.
+fn execute(ops: &[Op], lines: &[(usize, usize)], ctx: &mut Context) -> Result<Exit, Error> {
+    let mut frames = vec![CallFrame::new(None, None)];
+    loop {
+        let frame = frames.last_mut().expect("the frame of `ops` is never left");
+        let closure = frame.closure.clone();
+        let (ops, _) = code(closure.as_deref(), ops, lines);
+        let error = match run_frame(ops, frame, ctx) {
+            Ok(Run::Call(closure, args)) => match ctx.enter_function(&closure, &args) {
+                Ok(caller) => {
+                    frames.push(CallFrame::new(Some(closure), Some(caller)));
+                    continue;
+                }
+                Err(error) => error,
+            },
+            Ok(Run::Exit(exit)) => {
+                let callee = frames.pop().expect("a frame just ran");
+                let Some(caller) = callee.caller else {
+                    return Ok(exit);
+                };
+                ctx.leave_function(caller);
+                let value = match exit {
+                    Exit::End(_) => Value::Nil,
+                    Exit::Return(value) => value,
+                };
+                let frame = frames.last_mut().expect("a called frame has a caller");
+                match push(&mut frame.stack, value, ctx) {
+                    Ok(()) => continue,
+                    Err(error) => error,
                 }
             }
+            Err(error) => error,
+        };
+        unwind(&mut frames, error, ops, lines, ctx)?;
+    }
+}
+
+/// The ops and line table a frame runs: its function's, or the given ones.
+fn code<'a>(
+    closure: Option<&'a Closure>,
+    ops: &'a [Op],
+    lines: &'a [(usize, usize)],
+) -> (&'a [Op], &'a [(usize, usize)]) {
+    match closure.map(|closure| &closure.proto.code) {
+        Some(Code::Ops(chunk)) => (&chunk.ops, &chunk.lines),
+        _ => (ops, lines),
+    }
+}
+
+/// Hands `error` to the innermost handler of the top frame, leaving frames
+/// that have none for their callers the way a native call would unwind.
+/// Fails once it leaves the frame of the ops `execute` was given, so no
+/// call is left entered.
+fn unwind(
+    frames: &mut Vec<CallFrame>,
+    mut error: Error,
+    ops: &[Op],
+    lines: &[(usize, usize)],
+    ctx: &mut Context,
+) -> Result<(), Error> {
+    loop {
+        let frame = frames.last_mut().expect("the frame of `ops` is never left");
+        let closure = frame.closure.clone();
+        let (_, lines) = code(closure.as_deref(), ops, lines);
+        match (error.catch_value(), frame.handlers.pop()) {
+            (Some(value), Some(handler)) => {
+                frame.stack.truncate(handler.height);
+                ctx.scope.unwind_to(handler.depth);
+                match push(&mut frame.stack, value, ctx) {
+                    Ok(()) => {
+                        frame.pc = handler.catch;
+                        return Ok(());
+                    }
+                    Err(failed) => error = failed,
+                }
+            }
+            _ => ctx.line = line_at(lines, frame.pc - 1),
         }
+        let callee = frames.pop().expect("the top frame was just looked at");
+        let (Some(closure), Some(caller)) = (callee.closure, callee.caller) else {
+            return Err(error);
+        };
+        error = error.unwound_through(ctx.frame(closure.name()));
+        ctx.leave_function(caller);
     }
-    return Ok(stack.pop());
 }
 
-pub fn ast_to_bytecode(node: Node, ops: &mut Vec<Op>) {
-    match node {
-        Node::Add { lhs, rhs } => {
-            ast_to_bytecode(*lhs, ops);
-            ast_to_bytecode(*rhs, ops);
-            ops.push(Op::Add {})
+/// Runs the ops of `frame` until it ends, returns, fails or calls a
+/// compiled function.
+fn run_frame(ops: &[Op], frame: &mut CallFrame, ctx: &mut Context) -> Result<Run, Error> {
+    while let Some(instruction) = ops.get(frame.pc) {
+        frame.pc += 1;
+        ctx.limits.tick()?;
+        if let Some(run) = step(instruction, frame, ctx)? {
+            return Ok(run);
+        }
+    }
+    Ok(Run::Exit(Exit::End(frame.stack.pop())))
+}
+
+/// Runs one instruction of `frame`, with its `pc` already pointing past it.
+/// Errors unwind to the innermost handler of the running function, if there
+/// is one; an exit ends the function.
+fn step(instruction: &Op, frame: &mut CallFrame, ctx: &mut Context) -> Result<Option<Run>, Error> {
+    let CallFrame {
+        stack,
+        handlers,
+        pc,
+        ..
+    } = frame;
+    match instruction {
+        Op::Push { value } => push(stack, value.clone(), ctx)?,
+        Op::Add => binary(stack, ctx, Value::add)?,
+        Op::Sub => binary(stack, ctx, Value::sub)?,
+        Op::Mull => binary(stack, ctx, Value::mul)?,
+        Op::Div => binary(stack, ctx, Value::div)?,
+        Op::Rem => binary(stack, ctx, Value::rem)?,
+        Op::Eq => binary(stack, ctx, |l, r| Ok(l.equals(r)))?,
+        Op::Ne => binary(stack, ctx, |l, r| Ok(l.not_equals(r)))?,
+        Op::Lt => binary(stack, ctx, Value::lt)?,
+        Op::Le => binary(stack, ctx, Value::le)?,
+        Op::Gt => binary(stack, ctx, Value::gt)?,
+        Op::Ge => binary(stack, ctx, Value::ge)?,
+        Op::In => binary(stack, ctx, |l, r| r.has(l))?,
+        Op::Range => binary(stack, ctx, Value::range)?,
+        Op::Assign { name } => {
+            let val = pop(stack)?;
+            ctx.set_var(name.clone(), val)?;
+        }
+        Op::Declare { name } => {
+            let val = pop(stack)?;
+            ctx.dec_var(name.clone(), val)?;
+        }
+        Op::Const { name } => {
+            let val = pop(stack)?;
+            ctx.dec_const(name.clone(), val)?;
+        }
+        Op::PrintLn => {
+            writeln!(ctx.out, "{}", pop(stack)?).map_err(|e| e.to_string())?;
+        }
+        Op::Call { name, argc } => {
+            let args = split_off(stack, *argc)?;
+            match ctx.resolve(name, &args)? {
+                Resolved::Returned(result) => push(stack, result, ctx)?,
+                Resolved::Function(callee) => return call(callee, args, stack, ctx),
+            }
+        }
+        Op::MakeArray { len } => {
+            let items = split_off(stack, *len)?;
+            push(stack, Value::array(items), ctx)?;
+        }
+        Op::Interpolate { len } => {
+            let parts = split_off(stack, *len)?;
+            let text = parts.iter().map(Value::to_string).collect();
+            push(stack, Value::Str(text), ctx)?;
+        }
+        Op::MakeMap { len } => {
+            let items = split_off(stack, len.saturating_mul(2))?;
+            let map = Value::map(BTreeMap::new());
+            for pair in items.chunks(2) {
+                map.set_index(&pair[0], pair[1].clone())?;
+            }
+            push(stack, map, ctx)?;
+        }
+        Op::Index => {
+            let index = pop(stack)?;
+            let target = pop(stack)?;
+            push(stack, target.index(&index)?, ctx)?;
+        }
+        Op::SetIndex => {
+            let value = pop(stack)?;
+            let index = pop(stack)?;
+            pop(stack)?.set_index(&index, value)?;
+        }
+        Op::Load { id } => {
+            if let Some(value) = ctx.scope.get_var(id) {
+                push(stack, value, ctx)?;
+            } else {
+                return Err(format!("Variable '{}' not found", id.clone()).into());
+            }
+        }
+        Op::Dup => {
+            let top = stack.last().ok_or("Stack underflow".to_string())?.clone();
+            push(stack, top, ctx)?;
+        }
+        Op::Dup2 => {
+            let below = stack
+                .len()
+                .checked_sub(2)
+                .ok_or("Stack underflow".to_string())?;
+            for i in below..below + 2 {
+                let value = stack[i].clone();
+                push(stack, value, ctx)?;
+            }
+        }
+        Op::Tuck { depth } => {
+            let top = stack.last().ok_or("Stack underflow".to_string())?.clone();
+            let at = depth
+                .checked_add(1)
+                .and_then(|below| stack.len().checked_sub(below))
+                .ok_or("Stack underflow".to_string())?;
+            ctx.limits.check(Resource::Stack, stack.len() + 1)?;
+            stack.insert(at, top);
+        }
+        Op::Closure { proto } => {
+            let closure = function::close(proto.clone(), ctx);
+            push(stack, closure, ctx)?;
+        }
+        Op::CallValue { argc } => {
+            let args = split_off(stack, *argc)?;
+            let callee = pop(stack)?;
+            return call(callee, args, stack, ctx);
+        }
+        Op::Return => return Ok(Some(Run::Exit(Exit::Return(pop(stack)?)))),
+        Op::Pop => {
+            pop(stack)?;
+        }
+        Op::Jump { target } => *pc = *target,
+        Op::Iter => {
+            let iterable = pop(stack)?.iterable()?;
+            push(stack, iterable, ctx)?;
+            push(stack, Value::Int(0), ctx)?;
         }
-        Node::Mul { lhs, rhs } => {
-            ast_to_bytecode(*lhs, ops);
-            ast_to_bytecode(*rhs, ops);
-            ops.push(Op::Mull {})
+        Op::Next { exit } => {
+            let n = pop(stack)?.as_int()?;
+            match stack.last().ok_or("Stack underflow".to_string())?.nth(n) {
+                Some(item) => {
+                    push(stack, Value::Int(n + 1), ctx)?;
+                    push(stack, item, ctx)?;
+                }
+                None => {
+                    pop(stack)?;
+                    *pc = *exit;
+                }
+            }
+        }
+        Op::MakeStruct { name, fields } => {
+            let values = split_off(stack, fields.len())?;
+            let record = ctx.types.construct(name, fields, values)?;
+            push(stack, record, ctx)?;
+        }
+        Op::GetField { field, span } => {
+            let value = pop(stack)?.field(field, *span)?;
+            push(stack, value, ctx)?;
+        }
+        Op::SetField { field, span } => {
+            let value = pop(stack)?;
+            pop(stack)?.set_field(field, value, *span)?;
+        }
+        Op::MakeVariant { ty, variant, argc } => {
+            let values = split_off(stack, *argc)?;
+            let value = ctx.types.variant(ty, variant, values)?;
+            push(stack, value, ctx)?;
+        }
+        Op::MatchLiteral { value, fail } => {
+            if pop(stack)?.equals(value) != Value::Bool(true) {
+                *pc = *fail;
+            }
+        }
+        Op::MatchVariant { ty, variant, fail } => {
+            let value = pop(stack)?;
+            match value.variant_values(ty, variant) {
+                Some(values) => {
+                    for value in values.iter().rev() {
+                        push(stack, value.clone(), ctx)?;
+                    }
+                }
+                None => *pc = *fail,
+            }
+        }
+        Op::NoMatch => {
+            // Exhaustiveness is only checked for the types the patterns name.
+            return Err(format!("No match arm for {}", pop(stack)?).into());
+        }
+        Op::Try { catch } => handlers.push(Handler {
+            catch: *catch,
+            height: stack.len(),
+            depth: ctx.scope.depth(),
+        }),
+        Op::EndTry => {
+            handlers.pop();
+        }
+        Op::Throw => return Err(Error::Thrown { value: pop(stack)? }),
+        Op::Import { path, search } => {
+            let module = module::import(ctx, path, *search, eval)?;
+            push(stack, module, ctx)?;
+        }
+        Op::PushBlock => ctx.scope.push_block(),
+        Op::PopBlock => ctx.scope.pop_block()?,
+    }
+    Ok(None)
+}
+
+/// Calls `callee`: a compiled function in a new frame of the VM, anything
+/// else through the context.
+fn call(
+    callee: Value,
+    args: Vec<Value>,
+    stack: &mut Vec<Value>,
+    ctx: &mut Context,
+) -> Result<Option<Run>, Error> {
+    if let Value::Function(closure) = &callee {
+        if let Code::Ops(_) = closure.proto.code {
+            return Ok(Some(Run::Call(closure.clone(), args)));
+        }
+    }
+    let result = ctx.call_value(&callee, &args)?;
+    push(stack, result, ctx)?;
+    Ok(None)
+}
+
+fn push(stack: &mut Vec<Value>, value: Value, ctx: &Context) -> Result<(), Error> {
+    ctx.limits.check(Resource::Stack, stack.len() + 1)?;
+    stack.push(value);
+    Ok(())
+}
+
+fn pop(stack: &mut Vec<Value>) -> Result<Value, String> {
+    stack.pop().ok_or("Stack underflow".to_string())
+}
+
+/// Pops the top `n` values, keeping their order.
+fn split_off(stack: &mut Vec<Value>, n: usize) -> Result<Vec<Value>, String> {
+    if n > stack.len() {
+        return Err("Stack underflow".to_string());
+    }
+    Ok(stack.split_off(stack.len() - n))
+}
+
+/// Replaces the top two values with `op` applied to them; the top of the
+/// stack is the right operand.
+fn binary(
+    stack: &mut Vec<Value>,
+    ctx: &mut Context,
+    op: fn(&Value, &Value) -> Result<Value, String>,
+) -> Result<(), Error> {
+    let rhs = pop(stack)?;
+    let lhs = pop(stack)?;
+    ctx.limits.spend(lhs.cost(&rhs))?;
+    push(stack, op(&lhs, &rhs)?, ctx)
+}
+
+fn binary_to_bytecode(lhs: Node, rhs: Node, op: Op, ops: &mut Chunk) {
+    value_to_bytecode(lhs, ops);
+    value_to_bytecode(rhs, ops);
+    ops.push(op);
+}
+
+/// Keeps a copy of the value an assignment is about to store below the
+/// `depth` values the store pops along with it.
+fn keep_to_bytecode(keep: bool, depth: usize, ops: &mut Chunk) {
+    match (keep, depth) {
+        (false, _) => {}
+        (true, 0) => ops.push(Op::Dup),
+        (true, depth) => ops.push(Op::Tuck { depth }),
+    }
+}
+
+/// Compiles what an assignment stores: `rhs`, or for a compound assignment
+/// `op` applied to the current value, which is already on the stack, and
+/// `rhs`.
+fn update_to_bytecode(op: Option<Operator>, rhs: Node, ops: &mut Chunk) {
+    value_to_bytecode(rhs, ops);
+    match op {
+        Some(Operator::Add) => ops.push(Op::Add),
+        Some(Operator::Sub) => ops.push(Op::Sub),
+        Some(Operator::Mul) => ops.push(Op::Mull),
+        Some(Operator::Div) => ops.push(Op::Div),
+        Some(Operator::Rem) => ops.push(Op::Rem),
+        None => {}
+    }
+}
+
+pub fn ast_to_bytecode(node: Node, ops: &mut Chunk) {
+    node_to_bytecode(node, false, ops)
+}
+
+/// Compiles `node` where its value is used. An assignment there leaves the
+/// value it stores, which as a statement it doesn't.
+fn value_to_bytecode(node: Node, ops: &mut Chunk) {
+    node_to_bytecode(node, true, ops)
+}
+
+/// Compiles `node`, leaving what an assignment stores on the stack if
+/// `keep` is set. Expressions nest as deeply as the program does, so each
+/// one compiles under `stack::protect`.
+fn node_to_bytecode(node: Node, keep: bool, ops: &mut Chunk) {
+    stack::protect(|| compile_node(node, keep, ops))
+}
+
+fn compile_node(node: Node, keep: bool, ops: &mut Chunk) {
+    match node {
+        Node::Add { lhs, rhs } => binary_to_bytecode(*lhs, *rhs, Op::Add, ops),
+        Node::Sub { lhs, rhs } => binary_to_bytecode(*lhs, *rhs, Op::Sub, ops),
+        Node::Mul { lhs, rhs } => binary_to_bytecode(*lhs, *rhs, Op::Mull, ops),
+        Node::Div { lhs, rhs } => binary_to_bytecode(*lhs, *rhs, Op::Div, ops),
+        Node::Rem { lhs, rhs } => binary_to_bytecode(*lhs, *rhs, Op::Rem, ops),
+        Node::Eq { lhs, rhs } => binary_to_bytecode(*lhs, *rhs, Op::Eq, ops),
+        Node::Ne { lhs, rhs } => binary_to_bytecode(*lhs, *rhs, Op::Ne, ops),
+        Node::Lt { lhs, rhs } => binary_to_bytecode(*lhs, *rhs, Op::Lt, ops),
+        Node::Le { lhs, rhs } => binary_to_bytecode(*lhs, *rhs, Op::Le, ops),
+        Node::Gt { lhs, rhs } => binary_to_bytecode(*lhs, *rhs, Op::Gt, ops),
+        Node::Ge { lhs, rhs } => binary_to_bytecode(*lhs, *rhs, Op::Ge, ops),
+        Node::In { lhs, rhs } => binary_to_bytecode(*lhs, *rhs, Op::In, ops),
+        Node::Range { lhs, rhs } => binary_to_bytecode(*lhs, *rhs, Op::Range, ops),
+        Node::Number { value } => ops.push(Op::Push {
+            value: Value::Int(value),
+        }),
+        #[cfg(feature = "bigint")]
+        Node::BigNumber { value } => ops.push(Op::Push {
+            value: Value::from(value),
+        }),
+        Node::Float { value } => ops.push(Op::Push {
+            value: Value::Float(value),
+        }),
+        Node::Str { value } => ops.push(Op::Push {
+            value: Value::Str(value),
+        }),
+        Node::Interpolate { parts } => {
+            let len = parts.len();
+            for part in parts {
+                value_to_bytecode(part, ops);
+            }
+            ops.push(Op::Interpolate { len });
         }
-        Node::Number { value } => ops.push(Op::Push { value }),
+        Node::Bool { value } => ops.push(Op::Push {
+            value: Value::Bool(value),
+        }),
         Node::Declare { id, rhs } => {
-            if let Some(val) = rhs {
-                ast_to_bytecode(*val, ops);
+            match rhs {
+                Some(val) => value_to_bytecode(*val, ops),
+                None => ops.push(Op::Push { value: Value::Nil }),
             }
+            keep_to_bytecode(keep, 0, ops);
             ops.push(Op::Declare { name: id.clone() });
         }
-        Node::Assign { id, rhs } => {
-            ast_to_bytecode(*rhs, ops);
-            ops.push(Op::Declare { name: id.clone() });
+        Node::Assign { id, op, rhs, .. } => {
+            if op.is_some() {
+                ops.push(Op::Load { id: id.clone() });
+            }
+            update_to_bytecode(op, *rhs, ops);
+            keep_to_bytecode(keep, 0, ops);
+            ops.push(Op::Assign { name: id });
+        }
+        Node::Const { id, rhs, .. } => {
+            value_to_bytecode(*rhs, ops);
+            ops.push(Op::Const { name: id });
+        }
+        Node::Id { value, .. } => ops.push(Op::Load { id: value }),
+        Node::Call { id, args, .. } => {
+            let argc = args.len();
+            for arg in args {
+                value_to_bytecode(arg, ops);
+            }
+            ops.push(Op::Call { name: id, argc });
+        }
+        Node::Nil => ops.push(Op::Push { value: Value::Nil }),
+        Node::Apply { callee, args } => {
+            let argc = args.len();
+            value_to_bytecode(*callee, ops);
+            for arg in args {
+                value_to_bytecode(arg, ops);
+            }
+            ops.push(Op::CallValue { argc });
+        }
+        Node::Function { name, params, body } => {
+            let captures = function::captures(&params, &body);
+            let mut code = Chunk::default();
+            for node in Rc::unwrap_or_clone(body) {
+                ast_to_bytecode(node, &mut code);
+            }
+            let proto = Rc::new(Prototype {
+                name: name.clone(),
+                params,
+                captures,
+                code: Code::Ops(code),
+            });
+            match name {
+                // Declared before the closure is made, so it can capture itself.
+                Some(name) => ops.extend([
+                    Op::Push { value: Value::Nil },
+                    Op::Declare { name: name.clone() },
+                    Op::Closure { proto },
+                    Op::Assign { name },
+                ]),
+                None => ops.push(Op::Closure { proto }),
+            }
+        }
+        Node::Return { rhs } => {
+            match rhs {
+                Some(rhs) => value_to_bytecode(*rhs, ops),
+                None => ops.push(Op::Push { value: Value::Nil }),
+            }
+            ops.push(Op::Return);
+        }
+        Node::For { id, iterable, body } => {
+            value_to_bytecode(*iterable, ops);
+            ops.push(Op::Iter);
+            let next = ops.len();
+            ops.push(Op::Next { exit: 0 });
+            ops.push(Op::PushBlock);
+            ops.push(Op::Declare { name: id });
+            statements_to_bytecode(body, ops);
+            ops.push(Op::PopBlock);
+            ops.push(Op::Jump { target: next });
+            ops[next] = Op::Next { exit: ops.len() };
+        }
+        Node::Throw { rhs } => {
+            value_to_bytecode(*rhs, ops);
+            ops.push(Op::Throw);
+        }
+        Node::Try { body, id, handler } => {
+            let start = ops.len();
+            ops.push(Op::Try { catch: 0 });
+            ops.push(Op::PushBlock);
+            statements_to_bytecode(body, ops);
+            ops.push(Op::PopBlock);
+            ops.push(Op::EndTry);
+            let jump = ops.len();
+            ops.push(Op::Jump { target: 0 });
+            ops[start] = Op::Try { catch: ops.len() };
+            ops.push(Op::PushBlock);
+            ops.push(Op::Declare { name: id });
+            statements_to_bytecode(handler, ops);
+            ops.push(Op::PopBlock);
+            ops[jump] = Op::Jump { target: ops.len() };
+        }
+        Node::Struct { .. } | Node::Enum { .. } => {}
+        Node::Variant {
+            ty, variant, args, ..
+        } => {
+            let argc = args.len();
+            for arg in args {
+                value_to_bytecode(arg, ops);
+            }
+            ops.push(Op::MakeVariant { ty, variant, argc });
+        }
+        Node::Match {
+            scrutinee, arms, ..
+        } => {
+            value_to_bytecode(*scrutinee, ops);
+            let mut ends = vec![];
+            for arm in arms {
+                ends.push(arm_to_bytecode(arm, ops));
+            }
+            ops.push(Op::NoMatch);
+            for end in ends {
+                ops[end] = Op::Jump { target: ops.len() };
+            }
+        }
+        Node::StructLiteral { name, fields, .. } => {
+            let mut names = Vec::with_capacity(fields.len());
+            for field in fields {
+                names.push(field.name);
+                value_to_bytecode(field.value, ops);
+            }
+            ops.push(Op::MakeStruct {
+                name,
+                fields: names,
+            });
+        }
+        Node::Field {
+            target,
+            field,
+            span,
+        } => {
+            value_to_bytecode(*target, ops);
+            ops.push(Op::GetField { field, span });
+        }
+        Node::SetField {
+            target,
+            field,
+            op,
+            rhs,
+            span,
+        } => {
+            value_to_bytecode(*target, ops);
+            if op.is_some() {
+                ops.extend([
+                    Op::Dup,
+                    Op::GetField {
+                        field: field.clone(),
+                        span,
+                    },
+                ]);
+            }
+            update_to_bytecode(op, *rhs, ops);
+            keep_to_bytecode(keep, 1, ops);
+            ops.push(Op::SetField { field, span });
+        }
+        Node::Array { items } => {
+            let len = items.len();
+            for item in items {
+                value_to_bytecode(item, ops);
+            }
+            ops.push(Op::MakeArray { len });
+        }
+        Node::Map { entries } => {
+            let len = entries.len();
+            for (key, value) in entries {
+                value_to_bytecode(key, ops);
+                value_to_bytecode(value, ops);
+            }
+            ops.push(Op::MakeMap { len });
+        }
+        Node::Index { target, index } => binary_to_bytecode(*target, *index, Op::Index, ops),
+        Node::SetIndex {
+            target,
+            index,
+            op,
+            rhs,
+        } => {
+            value_to_bytecode(*target, ops);
+            value_to_bytecode(*index, ops);
+            if op.is_some() {
+                ops.extend([Op::Dup2, Op::Index]);
+            }
+            update_to_bytecode(op, *rhs, ops);
+            keep_to_bytecode(keep, 2, ops);
+            ops.push(Op::SetIndex);
         }
-        Node::Id { value } => ops.push(Op::Load { id: value }),
         Node::PrintLn { rhs } => {
-            ast_to_bytecode(*rhs, ops);
-            ops.push(Op::PrintLn {})
+            value_to_bytecode(*rhs, ops);
+            ops.push(Op::PrintLn)
+        }
+        Node::Empty => {}
+        Node::Line { line } => ops.mark(line),
+        Node::Import { path, search, name } => {
+            ops.push(Op::Import { path, search });
+            ops.push(Op::Declare { name });
         }
-        Node::Empty {} => {}
+        Node::Export { decl, .. } => ast_to_bytecode(*decl, ops),
+    }
+}
+
+/// Compiles the statements of a block, popping the values they leave.
+fn statements_to_bytecode(body: Vec<Node>, ops: &mut Chunk) {
+    for node in body {
+        let discard = produces_value(&node);
+        ast_to_bytecode(node, ops);
+        if discard {
+            ops.push(Op::Pop);
+        }
+    }
+}
+
+/// Compiles one arm of a match whose scrutinee is on top of the stack. The
+/// arm tests a copy of it and, if the pattern matches, pops it and leaves
+/// the arm's value instead. Otherwise it falls through to the next arm with
+/// the stack and blocks as they were. Returns the index of the jump to the
+/// end of the match, to be patched by the caller.
+fn arm_to_bytecode(arm: Arm, ops: &mut Chunk) -> usize {
+    ops.push(Op::Dup);
+    ops.push(Op::PushBlock);
+    let mut fails = vec![];
+    pattern_to_bytecode(arm.pattern, 1, &mut fails, ops);
+    ops.push(Op::Pop);
+    statements_to_bytecode(arm.body, ops);
+    match arm.tail {
+        Some(tail) if produces_value(&tail) => ast_to_bytecode(tail, ops),
+        Some(tail) => {
+            ast_to_bytecode(tail, ops);
+            ops.push(Op::Push { value: Value::Nil });
+        }
+        None => ops.push(Op::Push { value: Value::Nil }),
+    }
+    ops.push(Op::PopBlock);
+    let end = ops.len();
+    ops.push(Op::Jump { target: 0 });
+    // A failed test leaves some of the values taken apart so far on the
+    // stack. The cleanup pops them, one entry point per count.
+    let most = fails.iter().map(|&(_, pending)| pending).max().unwrap_or(0);
+    let cleanup = ops.len();
+    ops.extend(std::iter::repeat_n(Op::Pop, most));
+    ops.push(Op::PopBlock);
+    for (test, pending) in fails {
+        let target = cleanup + most - pending;
+        match &mut ops[test] {
+            Op::MatchLiteral { fail, .. } | Op::MatchVariant { fail, .. } => *fail = target,
+            _ => unreachable!("only tests can fail"),
+        }
+    }
+    end
+}
+
+/// Compiles a test of the value on top of the stack against `pattern`,
+/// which pops it and declares the bindings. `pending` counts the values the
+/// arm has pushed so far, this one included; each test that can fail is
+/// recorded in `fails` with the number left on the stack when it does.
+fn pattern_to_bytecode(
+    pattern: Pattern,
+    pending: usize,
+    fails: &mut Vec<(usize, usize)>,
+    ops: &mut Chunk,
+) {
+    match pattern {
+        Pattern::Wildcard => ops.push(Op::Pop),
+        Pattern::Bind(name) => ops.push(Op::Declare { name }),
+        Pattern::Literal(value) => {
+            fails.push((ops.len(), pending - 1));
+            ops.push(Op::MatchLiteral { value, fail: 0 });
+        }
+        Pattern::Variant {
+            ty, variant, args, ..
+        } => {
+            fails.push((ops.len(), pending - 1));
+            ops.push(Op::MatchVariant {
+                ty,
+                variant,
+                fail: 0,
+            });
+            // The values are pushed first on top, so `args` pop them in order.
+            let mut pending = pending - 1 + args.len();
+            for arg in args {
+                pattern_to_bytecode(arg, pending, fails, ops);
+                pending -= 1;
+            }
+        }
+    }
+}
+
+/// Whether `node` leaves a value on the stack, which a statement inside a
+/// loop has to pop again.
+fn produces_value(node: &Node) -> bool {
+    !matches!(
+        node,
+        Node::SetIndex { .. }
+            | Node::SetField { .. }
+            | Node::Struct { .. }
+            | Node::Enum { .. }
+            | Node::PrintLn { .. }
+            | Node::Assign { .. }
+            | Node::Declare { .. }
+            | Node::Const { .. }
+            | Node::Function { name: Some(_), .. }
+            | Node::Return { .. }
+            | Node::For { .. }
+            | Node::Throw { .. }
+            | Node::Try { .. }
+            | Node::Empty
+            | Node::Line { .. }
+            | Node::Import { .. }
+            | Node::Export { .. }
+    )
+}
+
+#[cfg(test)]
+mod bytecode_tests {
+    use super::*;
+    use crate::limits::Limits;
+    use std::io::sink;
+
+    #[test]
+    fn run_reports_stack_underflow() {
+        for ops in [
+            vec![Op::Add],
+            vec![
+                Op::Push {
+                    value: Value::Int(1),
+                },
+                Op::Mull,
+            ],
+            vec![Op::Declare {
+                name: "x".to_string(),
+            }],
+            vec![Op::PrintLn],
+            vec![Op::Dup],
+        ] {
+            assert_eq!(
+                run(&ops, &mut Context::new(Box::new(sink()))),
+                Err(Error::Runtime("Stack underflow".to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn run_reports_closing_a_block_that_isnt_open() {
+        let ops = [Op::PushBlock, Op::PopBlock, Op::PopBlock];
+        assert_eq!(
+            run(&ops, &mut Context::new(Box::new(sink()))),
+            Err(Error::Runtime("No block to close".to_string()))
+        );
+    }
+
+    #[test]
+    fn for_compiles_to_a_jump_back() {
+        let mut chunk = Chunk::default();
+        let ast = crate::parser::parse_str_strict("for x in a { x; }").unwrap();
+        for node in ast {
+            ast_to_bytecode(node, &mut chunk);
+        }
+        assert_eq!(
+            chunk.ops,
+            vec![
+                Op::Load {
+                    id: "a".to_string()
+                },
+                Op::Iter,
+                Op::Next { exit: 9 },
+                Op::PushBlock,
+                Op::Declare {
+                    name: "x".to_string()
+                },
+                Op::Load {
+                    id: "x".to_string()
+                },
+                Op::Pop,
+                Op::PopBlock,
+                Op::Jump { target: 2 },
+            ]
+        );
+    }
+
+    #[test]
+    fn declarations_without_a_value_push_nil() {
+        let mut chunk = Chunk::default();
+        for node in crate::parser::parse_str_strict("let x;").unwrap() {
+            ast_to_bytecode(node, &mut chunk);
+        }
+        assert_eq!(
+            chunk.ops,
+            vec![
+                Op::Push { value: Value::Nil },
+                Op::Declare {
+                    name: "x".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn known_constants_compile_to_their_value() {
+        let mut chunk = Chunk::default();
+        let mut ast = crate::parser::parse_str_strict("const a = 2 * 3; a;").unwrap();
+        constant::inline(&mut ast).unwrap();
+        for node in ast {
+            ast_to_bytecode(node, &mut chunk);
+        }
+        assert_eq!(
+            chunk.ops,
+            vec![
+                Op::Push {
+                    value: Value::Int(6)
+                },
+                Op::Const {
+                    name: "a".to_string()
+                },
+                Op::Push {
+                    value: Value::Int(6)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn line_table_maps_ops_to_their_statement() {
+        let mut chunk = Chunk::default();
+        let ast = crate::parser::parse_str_strict("let x = 1;\n\n;\nlet y = [\n  x\n];").unwrap();
+        for node in ast {
+            ast_to_bytecode(node, &mut chunk);
+        }
+        // The empty statement on line 3 compiles to no ops.
+        assert_eq!(chunk.lines, vec![(0, 1), (2, 4)]);
+        assert_eq!(line_at(&chunk.lines, 1), 1);
+        assert_eq!(line_at(&chunk.lines, 4), 4);
+        assert_eq!(line_at(&[], 0), 0);
+    }
+
+    #[test]
+    fn catch_restores_the_stack_and_blocks_of_its_try() {
+        let mut chunk = Chunk::default();
+        let ast = crate::parser::parse_str_strict("try { 1 + [2][3]; } catch (e) { e; }").unwrap();
+        for node in ast {
+            ast_to_bytecode(node, &mut chunk);
+        }
+        assert_eq!(chunk[0], Op::Try { catch: 12 });
+        assert_eq!(
+            chunk.ops[12..],
+            [
+                Op::PushBlock,
+                Op::Declare {
+                    name: "e".to_string()
+                },
+                Op::Load {
+                    id: "e".to_string()
+                },
+                Op::Pop,
+                Op::PopBlock,
+            ]
+        );
+        let mut ctx = Context::new(Box::new(sink()));
+        let depth = ctx.scope.depth();
+        assert_eq!(run(&chunk.ops, &mut ctx), Ok(None));
+        assert_eq!(ctx.scope.depth(), depth);
+    }
+
+    #[test]
+    fn failed_tests_pop_what_the_arm_took_apart() {
+        let mut chunk = Chunk::default();
+        let ast = crate::parser::parse_str_strict("match x { O::S(1, y) => y, _ => 0 };").unwrap();
+        for node in ast {
+            ast_to_bytecode(node, &mut chunk);
+        }
+        let load = |id: &str| Op::Load { id: id.to_string() };
+        assert_eq!(
+            chunk.ops,
+            vec![
+                load("x"),
+                Op::Dup,
+                Op::PushBlock,
+                Op::MatchVariant {
+                    ty: "O".to_string(),
+                    variant: "S".to_string(),
+                    fail: 11,
+                },
+                Op::MatchLiteral {
+                    value: Value::Int(1),
+                    fail: 10,
+                },
+                Op::Declare {
+                    name: "y".to_string()
+                },
+                Op::Pop,
+                load("y"),
+                Op::PopBlock,
+                Op::Jump { target: 21 },
+                // Entry for a failed literal, with `y`'s value still pushed.
+                Op::Pop,
+                Op::PopBlock,
+                Op::Dup,
+                Op::PushBlock,
+                Op::Pop,
+                Op::Pop,
+                Op::Push {
+                    value: Value::Int(0)
+                },
+                Op::PopBlock,
+                Op::Jump { target: 21 },
+                Op::PopBlock,
+                Op::NoMatch,
+            ]
+        );
+    }
+
+    #[cfg(not(feature = "bigint"))]
+    #[test]
+    fn run_reports_overflow() {
+        let ops = vec![
+            Op::Push {
+                value: Value::Int(u64::MAX),
+            },
+            Op::Push {
+                value: Value::Int(2),
+            },
+            Op::Mull,
+        ];
+        assert_eq!(
+            run(&ops, &mut Context::new(Box::new(sink()))),
+            Err(Error::Runtime("overflowed".to_string()))
+        );
+    }
+
+    #[test]
+    fn compound_assignments_read_their_target_once() {
+        let mut chunk = Chunk::default();
+        let ast = crate::parser::parse_str_strict("a[i] += 1; p.x -= 2;").unwrap();
+        for node in ast {
+            ast_to_bytecode(node, &mut chunk);
+        }
+        let load = |id: &str| Op::Load { id: id.to_string() };
+        let push = |n: u64| Op::Push {
+            value: Value::Int(n),
+        };
+        let field = "x".to_string();
+        let span = Span {
+            line: 1,
+            column: 14,
+        };
+        assert_eq!(
+            chunk.ops,
+            vec![
+                load("a"),
+                load("i"),
+                Op::Dup2,
+                Op::Index,
+                push(1),
+                Op::Add,
+                Op::SetIndex,
+                load("p"),
+                Op::Dup,
+                Op::GetField {
+                    field: field.clone(),
+                    span
+                },
+                push(2),
+                Op::Sub,
+                Op::SetField { field, span },
+            ]
+        );
+    }
+
+    #[test]
+    fn assignments_used_as_values_keep_what_they_store() {
+        let mut chunk = Chunk::default();
+        let ast = crate::parser::parse_str_strict("let x = y = 1; a[0] = (b = 2);").unwrap();
+        for node in ast {
+            ast_to_bytecode(node, &mut chunk);
+        }
+        let push = |n: u64| Op::Push {
+            value: Value::Int(n),
+        };
+        let name = |id: &str| id.to_string();
+        assert_eq!(
+            chunk.ops,
+            vec![
+                push(1),
+                Op::Dup,
+                Op::Assign { name: name("y") },
+                Op::Declare { name: name("x") },
+                Op::Load { id: name("a") },
+                push(0),
+                push(2),
+                Op::Dup,
+                Op::Assign { name: name("b") },
+                Op::SetIndex,
+            ]
+        );
+    }
+
+    #[test]
+    fn interpolations_join_their_parts_when_they_run() {
+        let mut chunk = Chunk::default();
+        for node in crate::parser::parse_str_strict("\"a${1}${\"b\"}c\";").unwrap() {
+            ast_to_bytecode(node, &mut chunk);
+        }
+        let push = |value: Value| Op::Push { value };
+        assert_eq!(
+            chunk.ops,
+            vec![
+                push(Value::from("a")),
+                push(Value::Int(1)),
+                push(Value::from("bc")),
+                Op::Interpolate { len: 3 },
+            ]
+        );
+    }
+
+    #[test]
+    fn tuck_copies_the_top_value_under_others() {
+        let ops = [
+            Op::Push {
+                value: Value::Int(1),
+            },
+            Op::Push {
+                value: Value::Int(2),
+            },
+            Op::Tuck { depth: 1 },
+            Op::MakeArray { len: 3 },
+        ];
+        let mut ctx = Context::new(Box::new(sink()));
+        assert_eq!(
+            run(&ops, &mut ctx).unwrap().unwrap().to_string(),
+            "[2, 1, 2]"
+        );
+        let ops = [Op::Tuck { depth: 1 }];
+        assert!(run(&ops, &mut ctx).is_err());
+        let ops = [
+            Op::Push {
+                value: Value::Int(1),
+            },
+            Op::Tuck { depth: usize::MAX },
+        ];
+        assert!(run(&ops, &mut ctx).is_err());
+    }
+
+    #[test]
+    fn run_charges_fuel_per_instruction() {
+        let ops = vec![
+            Op::Push {
+                value: Value::Int(1),
+            },
+            Op::Push {
+                value: Value::Int(2),
+            },
+            Op::Add,
+        ];
+        let mut ctx = Context::new(Box::new(sink()));
+        ctx.limits = Limits {
+            fuel: Some(3),
+            ..Limits::default()
+        };
+        assert_eq!(run(&ops, &mut ctx), Ok(Some(Value::Int(3))));
+        assert_eq!(ctx.limits.fuel, Some(0));
+
+        ctx.limits.fuel = Some(2);
+        assert_eq!(run(&ops, &mut ctx), Err(Error::OutOfFuel));
+    }
+
+    #[test]
+    fn run_caps_stack_depth() {
+        let ops = vec![
+            Op::Push {
+                value: Value::Int(1),
+            },
+            Op::Push {
+                value: Value::Int(2),
+            },
+            Op::Add,
+        ];
+        let mut ctx = Context::new(Box::new(sink()));
+        ctx.limits.max_stack = Some(1);
+        assert_eq!(
+            run(&ops, &mut ctx),
+            Err(Error::ResourceExhausted {
+                resource: Resource::Stack,
+                limit: 1
+            })
+        );
+        ctx.limits.max_stack = Some(2);
+        assert_eq!(run(&ops, &mut ctx), Ok(Some(Value::Int(3))));
     }
 }
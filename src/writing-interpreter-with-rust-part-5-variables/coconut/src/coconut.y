@@ -1,30 +1,154 @@
-%start StatementList
+%start Program
 %%
 
+// Like a block, a program may end in an expression without a semicolon.
+Program -> Result<Vec<Node>, ()>:
+    StatementList { $1 }
+    | StatementList LocatedExpression {
+        let (line, tail) = $2?;
+        append(append($1?, line)?, tail)
+    }
+    ;
+
 StatementList -> Result<Vec<Node>, ()>:
-    StatementList Statement { append($1.map_err(|_| ())?, $2.map_err(|_| ())?)  }
+    StatementList LocatedStatement {
+        let (line, statement) = $2?;
+        append(append($1?, line)?, statement)
+    }
     | { Ok(vec![]) }
     ;
 
+// Statements and block tails, each with a marker of the line it starts on.
+LocatedStatement -> Result<(Node, Node), ()>:
+    Statement { Ok((line($lexer, $span), $1?)) }
+    ;
+
+LocatedExpression -> Result<(Node, Node), ()>:
+    EqualityExpression { Ok((line($lexer, $span), $1?)) }
+    ;
+
 Statement -> Result<Node, ()>:
    ';' { Ok(Node::Empty{}) }
     | Expression ';' { $1 }
     | Builtins { $1 }
+    | 'FN' 'IDENTIFIER' 'LPAR' Parameters 'RPAR' Block {
+        let name = $lexer.span_str(($2.map_err(|_| ())?).span()).to_string();
+        Ok(function(Some(name), $4?, $6?))
+    }
+    | 'LET' 'IDENTIFIER' ';' {
+        Ok(Node::Declare { id: $lexer.span_str(($2.map_err(|_| ())?).span()).to_string(), rhs: None })
+    }
+    | 'CONST' 'IDENTIFIER' 'ASSIGN' EqualityExpression ';' {
+        Ok(Node::Const {
+            id: $lexer.span_str(($2.map_err(|_| ())?).span()).to_string(),
+            rhs: Box::new($4?),
+            span: span($lexer, $span),
+        })
+    }
+    | 'RETURN' EqualityExpression ';' { Ok(Node::Return { rhs: Some(Box::new($2?)) }) }
+    | 'RETURN' ';' { Ok(Node::Return { rhs: None }) }
+    | 'IMPORT' StringLiteral ';' {
+        match $2? {
+            Node::Str { value } => import(value, false),
+            _ => Err(()),
+        }
+    }
+    | 'IMPORT' 'IDENTIFIER' ';' {
+        import($lexer.span_str(($2.map_err(|_| ())?).span()).to_string(), true)
+    }
+    | 'EXPORT' Statement {
+        let export = $1.map_err(|_| ())?;
+        Ok(Node::Export { decl: Box::new($2?), span: span($lexer, export.span()) })
+    }
+    | 'THROW' EqualityExpression ';' { Ok(Node::Throw { rhs: Box::new($2?) }) }
+    | 'TRY' Block 'CATCH' 'LPAR' 'IDENTIFIER' 'RPAR' Block {
+        let (mut body, tail) = $2?;
+        body.extend(tail);
+        let (mut handler, tail) = $7?;
+        handler.extend(tail);
+        let id = $lexer.span_str(($5.map_err(|_| ())?).span()).to_string();
+        Ok(Node::Try { body, id, handler })
+    }
+    | 'ENUM' 'IDENTIFIER' 'LBRACE' Variants 'RBRACE' {
+        let name = $2.map_err(|_| ())?;
+        Ok(Node::Enum {
+            name: $lexer.span_str(name.span()).to_string(),
+            variants: $4?,
+            span: span($lexer, name.span()),
+        })
+    }
+    | 'STRUCT' 'IDENTIFIER' 'LBRACE' Parameters 'RBRACE' {
+        let name = $2.map_err(|_| ())?;
+        Ok(Node::Struct {
+            name: $lexer.span_str(name.span()).to_string(),
+            fields: $4?,
+            span: span($lexer, name.span()),
+        })
+    }
+    | 'FOR' 'IDENTIFIER' 'IN' EqualityNoStruct Block {
+        let id = $lexer.span_str(($2.map_err(|_| ())?).span()).to_string();
+        let (mut body, tail) = $5?;
+        body.extend(tail);
+        Ok(Node::For { id, iterable: Box::new($4?), body })
+    }
+    ;
+
+Block -> Result<(Vec<Node>, Option<Node>), ()>:
+    'LBRACE' StatementList 'RBRACE' { Ok(($2?, None)) }
+    | 'LBRACE' StatementList LocatedExpression 'RBRACE' {
+        let (line, tail) = $3?;
+        let mut body = $2?;
+        body.push(line);
+        Ok((body, Some(tail)))
+    }
+    ;
+
+Variants -> Result<Vec<(String, usize)>, ()>:
+    VariantList { $1 }
+    | { Ok(vec![]) }
+    ;
+
+VariantList -> Result<Vec<(String, usize)>, ()>:
+    VariantDeclaration { Ok(vec![$1?]) }
+    | VariantList ',' VariantDeclaration {
+        let mut variants = $1?;
+        variants.push($3?);
+        Ok(variants)
+    }
+    ;
+
+VariantDeclaration -> Result<(String, usize), ()>:
+    'IDENTIFIER' { Ok(($lexer.span_str(($1.map_err(|_| ())?).span()).to_string(), 0)) }
+    | 'IDENTIFIER' 'LPAR' Parameters 'RPAR' {
+        Ok(($lexer.span_str(($1.map_err(|_| ())?).span()).to_string(), $3?.len()))
+    }
+    ;
+
+Parameters -> Result<Vec<String>, ()>:
+    ParameterList { $1 }
+    | { Ok(vec![]) }
+    ;
+
+ParameterList -> Result<Vec<String>, ()>:
+    'IDENTIFIER' { Ok(vec![$lexer.span_str(($1.map_err(|_| ())?).span()).to_string()]) }
+    | ParameterList ',' 'IDENTIFIER' {
+        let mut params = $1?;
+        params.push($lexer.span_str(($3.map_err(|_| ())?).span()).to_string());
+        Ok(params)
+    }
     ;
     
 Expression -> Result<Node, ()>:
-    AdditiveExpression { $1 }
+    EqualityExpression { $1 }
     | PrimaryExpression 'ASSIGN' Expression {
-        match $1.map_err(|_| ())? {
-            Node::Id { value } => {
-                Ok(Node::Assign { id: value, rhs: Box::new($3?) })
-            },
-            _ => Err(())
-        }
+        assign($1?, None, $3?, span($lexer, $span))
+    }
+    | PrimaryExpression CompoundOperator Expression {
+        assign($1?, Some($2?), $3?, span($lexer, $span))
     }
     | 'LET' PrimaryExpression 'ASSIGN' Expression {
         match $2.map_err(|_| ())? {
-            Node::Id { value } => {
+            Node::Id { value, .. } => {
                 Ok(Node::Declare { id: value, rhs: Some(Box::new($4?)) })
             },
             _ => Err(())
@@ -32,43 +156,486 @@ Expression -> Result<Node, ()>:
     } 
     ;
 
+CompoundOperator -> Result<Operator, ()>:
+    'ADD_ASSIGN' { Ok(Operator::Add) }
+    | 'SUB_ASSIGN' { Ok(Operator::Sub) }
+    | 'MUL_ASSIGN' { Ok(Operator::Mul) }
+    | 'DIV_ASSIGN' { Ok(Operator::Div) }
+    | 'REM_ASSIGN' { Ok(Operator::Rem) }
+    ;
+
+EqualityExpression -> Result<Node, ()>:
+    RelationalExpression { $1 }
+    | EqualityExpression 'EQ' RelationalExpression {
+        Ok(Node::Eq{ lhs: Box::new($1?), rhs: Box::new($3?) })
+    }
+    | EqualityExpression 'NE' RelationalExpression {
+        Ok(Node::Ne{ lhs: Box::new($1?), rhs: Box::new($3?) })
+    }
+    ;
+
+RelationalExpression -> Result<Node, ()>:
+    RangeExpression { $1 }
+    | RelationalExpression 'LT' RangeExpression {
+        Ok(Node::Lt{ lhs: Box::new($1?), rhs: Box::new($3?) })
+    }
+    | RelationalExpression 'LE' RangeExpression {
+        Ok(Node::Le{ lhs: Box::new($1?), rhs: Box::new($3?) })
+    }
+    | RelationalExpression 'GT' RangeExpression {
+        Ok(Node::Gt{ lhs: Box::new($1?), rhs: Box::new($3?) })
+    }
+    | RelationalExpression 'GE' RangeExpression {
+        Ok(Node::Ge{ lhs: Box::new($1?), rhs: Box::new($3?) })
+    }
+    | RelationalExpression 'IN' RangeExpression {
+        Ok(Node::In{ lhs: Box::new($1?), rhs: Box::new($3?) })
+    }
+    ;
+
+RangeExpression -> Result<Node, ()>:
+    AdditiveExpression { $1 }
+    | AdditiveExpression 'DOTDOT' AdditiveExpression {
+        Ok(Node::Range{ lhs: Box::new($1?), rhs: Box::new($3?) })
+    }
+    ;
+
 AdditiveExpression -> Result<Node, ()>:
     MultiplicativeExpression { $1 }
     | AdditiveExpression 'ADD' MultiplicativeExpression { 
         Ok(Node::Add{ lhs: Box::new($1?), rhs: Box::new($3?) })
     }
+    | AdditiveExpression 'SUB' MultiplicativeExpression {
+        Ok(Node::Sub{ lhs: Box::new($1?), rhs: Box::new($3?) })
+    }
     ;
 
 MultiplicativeExpression -> Result<Node, ()>: 
+    Operand { $1 }
+    | MultiplicativeExpression 'MUL' Operand { 
+      Ok(Node::Mul{ lhs: Box::new($1?), rhs: Box::new($3?) })
+    }
+    | MultiplicativeExpression 'DIV' Operand {
+      Ok(Node::Div{ lhs: Box::new($1?), rhs: Box::new($3?) })
+    }
+    | MultiplicativeExpression 'REM' Operand {
+      Ok(Node::Rem{ lhs: Box::new($1?), rhs: Box::new($3?) })
+    }
+    ;
+
+// The same expressions without struct literals at the top level, for where
+// a block follows: in `for p in points {`, `points {` isn't a literal.
+EqualityNoStruct -> Result<Node, ()>:
+    RelationalNoStruct { $1 }
+    | EqualityNoStruct 'EQ' RelationalNoStruct {
+        Ok(Node::Eq{ lhs: Box::new($1?), rhs: Box::new($3?) })
+    }
+    | EqualityNoStruct 'NE' RelationalNoStruct {
+        Ok(Node::Ne{ lhs: Box::new($1?), rhs: Box::new($3?) })
+    }
+    ;
+
+RelationalNoStruct -> Result<Node, ()>:
+    RangeNoStruct { $1 }
+    | RelationalNoStruct 'LT' RangeNoStruct {
+        Ok(Node::Lt{ lhs: Box::new($1?), rhs: Box::new($3?) })
+    }
+    | RelationalNoStruct 'LE' RangeNoStruct {
+        Ok(Node::Le{ lhs: Box::new($1?), rhs: Box::new($3?) })
+    }
+    | RelationalNoStruct 'GT' RangeNoStruct {
+        Ok(Node::Gt{ lhs: Box::new($1?), rhs: Box::new($3?) })
+    }
+    | RelationalNoStruct 'GE' RangeNoStruct {
+        Ok(Node::Ge{ lhs: Box::new($1?), rhs: Box::new($3?) })
+    }
+    | RelationalNoStruct 'IN' RangeNoStruct {
+        Ok(Node::In{ lhs: Box::new($1?), rhs: Box::new($3?) })
+    }
+    ;
+
+RangeNoStruct -> Result<Node, ()>:
+    AdditiveNoStruct { $1 }
+    | AdditiveNoStruct 'DOTDOT' AdditiveNoStruct {
+        Ok(Node::Range{ lhs: Box::new($1?), rhs: Box::new($3?) })
+    }
+    ;
+
+AdditiveNoStruct -> Result<Node, ()>:
+    MultiplicativeNoStruct { $1 }
+    | AdditiveNoStruct 'ADD' MultiplicativeNoStruct { 
+        Ok(Node::Add{ lhs: Box::new($1?), rhs: Box::new($3?) })
+    }
+    | AdditiveNoStruct 'SUB' MultiplicativeNoStruct {
+        Ok(Node::Sub{ lhs: Box::new($1?), rhs: Box::new($3?) })
+    }
+    ;
+
+MultiplicativeNoStruct -> Result<Node, ()>: 
     PrimaryExpression { $1 }
-    | MultiplicativeExpression 'MUL' PrimaryExpression { 
+    | MultiplicativeNoStruct 'MUL' PrimaryExpression { 
       Ok(Node::Mul{ lhs: Box::new($1?), rhs: Box::new($3?) })
     }
+    | MultiplicativeNoStruct 'DIV' PrimaryExpression {
+      Ok(Node::Div{ lhs: Box::new($1?), rhs: Box::new($3?) })
+    }
+    | MultiplicativeNoStruct 'REM' PrimaryExpression {
+      Ok(Node::Rem{ lhs: Box::new($1?), rhs: Box::new($3?) })
+    }
+    ;
+
+Operand -> Result<Node, ()>:
+    PrimaryExpression { $1 }
+    | 'IDENTIFIER' 'LBRACE' FieldValues 'RBRACE' {
+        let name = $1.map_err(|_| ())?;
+        Ok(Node::StructLiteral {
+            name: $lexer.span_str(name.span()).to_string(),
+            fields: $3?,
+            span: span($lexer, name.span()),
+        })
+    }
     ;
 
 PrimaryExpression -> Result<Node, ()>:
-    'IDENTIFIER' { Ok(Node::Id { value: $lexer.span_str(($1.map_err(|_| ())?).span()).to_string() }) }
+    'IDENTIFIER' {
+        let id = $1.map_err(|_| ())?;
+        Ok(Node::Id { value: $lexer.span_str(id.span()).to_string(), span: span($lexer, id.span()) })
+    }
+    | PrimaryExpression 'LPAR' Arguments 'RPAR' {
+        match $1? {
+            Node::Id { value, span } => Ok(Node::Call { id: value, args: $3?, span }),
+            Node::Variant { ty, variant, args, span } if args.is_empty() => {
+                Ok(Node::Variant { ty, variant, args: $3?, span })
+            }
+            callee => Ok(Node::Apply { callee: Box::new(callee), args: $3? }),
+        }
+    }
+    | 'IDENTIFIER' 'COLONCOLON' 'IDENTIFIER' {
+        let ty = $1.map_err(|_| ())?;
+        Ok(Node::Variant {
+            ty: $lexer.span_str(ty.span()).to_string(),
+            variant: $lexer.span_str(($3.map_err(|_| ())?).span()).to_string(),
+            args: vec![],
+            span: span($lexer, ty.span()),
+        })
+    }
+    | 'MATCH' EqualityNoStruct 'LBRACE' Arms 'RBRACE' {
+        Ok(Node::Match {
+            scrutinee: Box::new($2?),
+            arms: $4?,
+            span: span($lexer, ($1.map_err(|_| ())?).span()),
+        })
+    }
+    | 'FN' 'LPAR' Parameters 'RPAR' Block { Ok(function(None, $3?, $5?)) }
+    | 'NIL' { $1.map_err(|_| ())?; Ok(Node::Nil) }
     |  'LPAR' Expression 'RPAR' { $2 }
-    | 'INTEGER' { parse_int($lexer.span_str(($1.map_err(|_| ())?).span())) }
+    | 'LBRACK' Arguments 'RBRACK' { Ok(Node::Array { items: $2? }) }
+    | 'LBRACE' Entries 'RBRACE' { Ok(Node::Map { entries: $2? }) }
+    | PrimaryExpression 'LBRACK' EqualityExpression 'RBRACK' {
+        Ok(Node::Index { target: Box::new($1?), index: Box::new($3?) })
+    }
+    | PrimaryExpression 'DOT' 'IDENTIFIER' {
+        let field = $3.map_err(|_| ())?;
+        Ok(Node::Field {
+            target: Box::new($1?),
+            field: $lexer.span_str(field.span()).to_string(),
+            span: span($lexer, field.span()),
+        })
+    }
+    | 'INTEGER' { parse_number($lexer.span_str(($1.map_err(|_| ())?).span())) }
+    | 'FLOAT' { parse_number($lexer.span_str(($1.map_err(|_| ())?).span())) }
+    | StringLiteral { $1 }
+    | 'TRUE' { $1.map_err(|_| ())?; Ok(Node::Bool { value: true }) }
+    | 'FALSE' { $1.map_err(|_| ())?; Ok(Node::Bool { value: false }) }
+    ;
+
+// A string literal, lexed into its text and its `${...}` interpolations.
+StringLiteral -> Result<Node, ()>:
+    'STR_BEGIN' StringParts 'STR_END' { Ok(string($2?)) }
+    ;
+
+StringParts -> Result<Vec<Node>, ()>:
+    StringParts StringPart { append($1?, $2?) }
+    | { Ok(vec![]) }
+    ;
+
+StringPart -> Result<Node, ()>:
+    'STR_TEXT' { Ok(Node::Str { value: unescape($lexer.span_str(($1.map_err(|_| ())?).span())) }) }
+    | 'DOLLAR' { Ok(Node::Str { value: $lexer.span_str(($1.map_err(|_| ())?).span()).to_string() }) }
+    | 'INTERP' EqualityExpression 'RBRACE' { $2 }
+    ;
+
+Arguments -> Result<Vec<Node>, ()>:
+    ArgumentList { $1 }
+    | { Ok(vec![]) }
+    ;
+
+ArgumentList -> Result<Vec<Node>, ()>:
+    EqualityExpression { Ok(vec![$1?]) }
+    | ArgumentList ',' EqualityExpression { append($1?, $3?) }
+    ;
+
+Entries -> Result<Vec<(Node, Node)>, ()>:
+    EntryList { $1 }
+    | { Ok(vec![]) }
+    ;
+
+EntryList -> Result<Vec<(Node, Node)>, ()>:
+    Entry { Ok(vec![$1?]) }
+    | EntryList ',' Entry {
+        let mut entries = $1?;
+        entries.push($3?);
+        Ok(entries)
+    }
+    ;
+
+Entry -> Result<(Node, Node), ()>:
+    EqualityExpression ':' EqualityExpression { Ok(($1?, $3?)) }
+    ;
+
+Arms -> Result<Vec<Arm>, ()>:
+    ArmList { $1 }
+    | ArmList ',' { $1 }
+    ;
+
+ArmList -> Result<Vec<Arm>, ()>:
+    Arm { Ok(vec![$1?]) }
+    | ArmList ',' Arm {
+        let mut arms = $1?;
+        arms.push($3?);
+        Ok(arms)
+    }
+    ;
+
+// A block arm can't be empty: `=> {}` is an empty map.
+Arm -> Result<Arm, ()>:
+    Pattern 'FATARROW' Expression { Ok(Arm { pattern: $1?, body: vec![], tail: Some($3?) }) }
+    | Pattern 'FATARROW' 'LBRACE' ArmStatements 'RBRACE' {
+        let (body, tail) = $4?;
+        Ok(Arm { pattern: $1?, body, tail })
+    }
+    ;
+
+ArmStatements -> Result<(Vec<Node>, Option<Node>), ()>:
+    LocatedStatement {
+        let (line, statement) = $1?;
+        Ok((vec![line, statement], None))
+    }
+    | LocatedExpression {
+        let (line, tail) = $1?;
+        Ok((vec![line], Some(tail)))
+    }
+    | LocatedStatement ArmStatements {
+        let (line, statement) = $1?;
+        let (mut body, tail) = $2?;
+        body.splice(0..0, [line, statement]);
+        Ok((body, tail))
+    }
+    ;
+
+Pattern -> Result<Pattern, ()>:
+    'UNDERSCORE' { $1.map_err(|_| ())?; Ok(Pattern::Wildcard) }
+    | 'IDENTIFIER' { Ok(Pattern::Bind($lexer.span_str(($1.map_err(|_| ())?).span()).to_string())) }
+    | 'IDENTIFIER' 'COLONCOLON' 'IDENTIFIER' {
+        let ty = $1.map_err(|_| ())?;
+        Ok(Pattern::Variant {
+            ty: $lexer.span_str(ty.span()).to_string(),
+            variant: $lexer.span_str(($3.map_err(|_| ())?).span()).to_string(),
+            args: vec![],
+            span: span($lexer, ty.span()),
+        })
+    }
+    | 'IDENTIFIER' 'COLONCOLON' 'IDENTIFIER' 'LPAR' PatternList 'RPAR' {
+        let ty = $1.map_err(|_| ())?;
+        Ok(Pattern::Variant {
+            ty: $lexer.span_str(ty.span()).to_string(),
+            variant: $lexer.span_str(($3.map_err(|_| ())?).span()).to_string(),
+            args: $5?,
+            span: span($lexer, ty.span()),
+        })
+    }
+    | 'INTEGER' { literal(parse_number($lexer.span_str(($1.map_err(|_| ())?).span()))?) }
+    | 'FLOAT' { literal(parse_number($lexer.span_str(($1.map_err(|_| ())?).span()))?) }
+    | StringLiteral { literal($1?) }
+    | 'TRUE' { $1.map_err(|_| ())?; Ok(Pattern::Literal(Value::Bool(true))) }
+    | 'FALSE' { $1.map_err(|_| ())?; Ok(Pattern::Literal(Value::Bool(false))) }
+    | 'NIL' { $1.map_err(|_| ())?; Ok(Pattern::Literal(Value::Nil)) }
+    ;
+
+PatternList -> Result<Vec<Pattern>, ()>:
+    Pattern { Ok(vec![$1?]) }
+    | PatternList ',' Pattern {
+        let mut patterns = $1?;
+        patterns.push($3?);
+        Ok(patterns)
+    }
+    ;
+
+FieldValues -> Result<Vec<FieldValue>, ()>:
+    FieldValueList { $1 }
+    | { Ok(vec![]) }
+    ;
+
+FieldValueList -> Result<Vec<FieldValue>, ()>:
+    FieldValue { Ok(vec![$1?]) }
+    | FieldValueList ',' FieldValue {
+        let mut fields = $1?;
+        fields.push($3?);
+        Ok(fields)
+    }
+    ;
+
+FieldValue -> Result<FieldValue, ()>:
+    'IDENTIFIER' ':' EqualityExpression {
+        let name = $1.map_err(|_| ())?;
+        Ok(FieldValue {
+            name: $lexer.span_str(name.span()).to_string(),
+            value: $3?,
+            span: span($lexer, name.span()),
+        })
+    }
     ;
 
 Builtins -> Result<Node, ()>:
     'PRINT_LN' 'LPAR' Expression 'RPAR' { Ok(Node::PrintLn{ rhs: Box::new($3?) }) };
 
 %%
-use crate::ast::Node;
+#[cfg(feature = "bigint")]
+use crate::bigint::BigUint;
+use crate::{
+    ast::{Arm, FieldValue, Node, Operator, Pattern, Span},
+    value::Value,
+};
+
+/// Where `span` starts in the source.
+fn span(
+    lexer: &dyn lrpar::NonStreamingLexer<lrlex::DefaultLexerTypes>,
+    span: cfgrammar::Span,
+) -> Span {
+    let ((line, column), _) = lexer.line_col(span);
+    Span { line, column }
+}
+
+/// Assigns `rhs` to `target`, a variable, element or field, after applying
+/// `op` to the two if there is one.
+fn assign(target: Node, op: Option<Operator>, rhs: Node, at: Span) -> Result<Node, ()> {
+    let rhs = Box::new(rhs);
+    match target {
+        Node::Id { value, .. } => Ok(Node::Assign { id: value, op, rhs, span: at }),
+        Node::Index { target, index } => Ok(Node::SetIndex { target, index, op, rhs }),
+        Node::Field { target, field, span } => Ok(Node::SetField { target, field, op, rhs, span }),
+        _ => Err(()),
+    }
+}
+
+/// A marker of the line `span` starts on.
+fn line(
+    lexer: &dyn lrpar::NonStreamingLexer<lrlex::DefaultLexerTypes>,
+    span: cfgrammar::Span,
+) -> Node {
+    let ((line, _), _) = lexer.line_col(span);
+    Node::Line { line }
+}
 
 fn append(mut lhs: Vec<Node>, rhs: Node ) -> Result<Vec<Node>, ()>{
     lhs.push(rhs);
     Ok(lhs)
 }
 
-fn parse_int(s: &str) -> Result<Node, ()> {
+/// Builds a function whose tail expression, if any, is its return value.
+fn function(name: Option<String>, params: Vec<String>, block: (Vec<Node>, Option<Node>)) -> Node {
+    let (mut body, tail) = block;
+    if let Some(tail) = tail {
+        body.push(Node::Return { rhs: Some(Box::new(tail)) });
+    }
+    Node::Function { name, params, body: std::rc::Rc::new(body) }
+}
+
+/// Imports the module at `path`, binding it to the file name without its
+/// extension.
+fn import(path: String, search: bool) -> Result<Node, ()> {
+    let name = std::path::Path::new(&path)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .ok_or(())?
+        .to_string();
+    Ok(Node::Import { path, search, name })
+}
+
+/// A literal pattern; strings with interpolations aren't literals.
+fn literal(node: Node) -> Result<Pattern, ()> {
+    let value = match node {
+        Node::Number { value } => Value::Int(value),
+        #[cfg(feature = "bigint")]
+        Node::BigNumber { value } => Value::from(value),
+        Node::Float { value } => Value::Float(value),
+        Node::Str { value } => Value::Str(value),
+        _ => {
+            eprintln!("Patterns cannot interpolate strings");
+            return Err(());
+        }
+    };
+    Ok(Pattern::Literal(value))
+}
+
+/// Parses an `INTEGER` or `FLOAT` lexeme; only floats have a fraction or
+/// an exponent. Integers too large for a u64 need the `bigint` feature.
+fn parse_number(s: &str) -> Result<Node, ()> {
+    if s.contains(['.', 'e', 'E']) {
+        return match s.parse::<f64>() {
+            Ok(value) if value.is_finite() => Ok(Node::Float { value }),
+            _ => {
+                eprintln!("{} cannot be represented as a float", s);
+                Err(())
+            }
+        };
+    }
     match s.parse::<u64>() {
         Ok(n_val) => Ok(Node::Number{ value: n_val }),
+        #[cfg(feature = "bigint")]
+        Err(_) => BigUint::parse(s).map(|value| Node::BigNumber { value }).ok_or(()),
+        #[cfg(not(feature = "bigint"))]
         Err(_) => {
             eprintln!("{} cannot be represented as a u64", s);
             Err(())
         }
     }
 }
+
+/// Joins the parts of a string literal. Only a literal that interpolates
+/// something is left to build its string when it runs.
+fn string(parts: Vec<Node>) -> Node {
+    let mut joined: Vec<Node> = vec![];
+    for part in parts {
+        match (joined.last_mut(), part) {
+            (Some(Node::Str { value }), Node::Str { value: text }) => value.push_str(&text),
+            (_, part) => joined.push(part),
+        }
+    }
+    match joined.as_slice() {
+        [] => Node::Str { value: String::new() },
+        [Node::Str { .. }] => joined.remove(0),
+        _ => Node::Interpolate { parts: joined },
+    }
+}
+
+/// Resolves the escapes in the text of a string literal. The lexer only
+/// lets through the ones handled here.
+fn unescape(text: &str) -> String {
+    let mut value = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        value.push(match c {
+            '\\' => match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('r') => '\r',
+                Some('0') => '\0',
+                Some(other) => other,
+                None => break,
+            },
+            c => c,
+        });
+    }
+    value
+}
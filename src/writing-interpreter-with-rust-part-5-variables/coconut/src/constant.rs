@@ -0,0 +1,286 @@
+//! `const` declarations: the compiler refuses to assign the constants it
+//! can see, and the bytecode compiler reads those whose values are known
+//! before the program runs as literals.
+
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{ast::Node, error::Error, stack, value::Value};
+
+/// Refuses assignments to the constants `ast` declares where they are in
+/// scope, pointing at the assignment. The AST is only borrowed mutably to
+/// share the walk with `inline`; it is left as it was.
+pub fn check(ast: &mut [Node]) -> Result<(), Error> {
+    Resolver::new(false).block(ast)
+}
+
+/// Checks `ast` like `check`. Where a constant's initializer can be worked
+/// out before the program runs, it and every read of the constant become
+/// the literal value.
+pub fn inline(ast: &mut [Node]) -> Result<(), Error> {
+    Resolver::new(true).block(ast)
+}
+
+/// What a name refers to where it is used.
+enum Binding {
+    /// A constant, with its value if that is known before the program runs.
+    Constant(Option<Value>),
+    Variable,
+}
+
+/// Walks the AST keeping track of the names in scope the way the backends
+/// do: blocks, functions, loops, handlers and match arms each get their own.
+struct Resolver {
+    scopes: Vec<HashMap<String, Binding>>,
+    inline: bool,
+}
+
+impl Resolver {
+    fn new(inline: bool) -> Self {
+        Resolver {
+            scopes: vec![HashMap::new()],
+            inline,
+        }
+    }
+
+    fn block(&mut self, body: &mut [Node]) -> Result<(), Error> {
+        body.iter_mut().try_for_each(|node| self.node(node))
+    }
+
+    /// Runs `walk` in a new scope in which `names` are variables.
+    fn scoped(
+        &mut self,
+        names: impl IntoIterator<Item = String>,
+        walk: impl FnOnce(&mut Self) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let scope = names.into_iter().map(|id| (id, Binding::Variable));
+        self.scopes.push(scope.collect());
+        let result = walk(self);
+        self.scopes.pop();
+        result
+    }
+
+    fn bind(&mut self, id: &str, binding: Binding) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(id.to_string(), binding);
+        }
+    }
+
+    fn lookup(&self, id: &str) -> Option<&Binding> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(id))
+    }
+
+    fn node(&mut self, node: &mut Node) -> Result<(), Error> {
+        stack::protect(|| self.visit(node))
+    }
+
+    fn visit(&mut self, node: &mut Node) -> Result<(), Error> {
+        match node {
+            Node::Id { value, .. } => {
+                let literal = match self.lookup(value) {
+                    Some(Binding::Constant(Some(value))) if self.inline => literal(value),
+                    _ => None,
+                };
+                if let Some(literal) = literal {
+                    *node = literal;
+                }
+            }
+            // A call by name looks the function up when it runs.
+            Node::Call { args, .. } => self.block(args)?,
+            Node::Assign { id, rhs, span, .. } => {
+                self.node(rhs)?;
+                if let Some(Binding::Constant(_)) = self.lookup(id) {
+                    return Err(Error::Compile {
+                        message: format!("Cannot assign to constant '{}'", id),
+                        span: *span,
+                    });
+                }
+            }
+            Node::Declare { id, rhs } => {
+                if let Some(rhs) = rhs {
+                    self.node(rhs)?;
+                }
+                self.bind(id, Binding::Variable);
+            }
+            Node::Const { id, rhs, .. } => {
+                self.node(rhs)?;
+                let value = self.value(rhs);
+                if let Some(literal) = value.as_ref().filter(|_| self.inline).and_then(literal) {
+                    **rhs = literal;
+                }
+                self.bind(id, Binding::Constant(value));
+            }
+            Node::Import { name, .. } => self.bind(name, Binding::Variable),
+            Node::Function { name, params, body } => {
+                // Declared before the closure is made, as the backends do.
+                if let Some(name) = name {
+                    self.bind(name, Binding::Variable);
+                }
+                let names = params.iter().cloned().chain(name.clone());
+                let body = Rc::make_mut(body);
+                self.scoped(names, |resolver| resolver.block(body))?;
+            }
+            Node::For { id, iterable, body } => {
+                self.node(iterable)?;
+                self.scoped([id.clone()], |resolver| resolver.block(body))?;
+            }
+            Node::Try { body, id, handler } => {
+                self.scoped([], |resolver| resolver.block(body))?;
+                self.scoped([id.clone()], |resolver| resolver.block(handler))?;
+            }
+            Node::Match {
+                scrutinee, arms, ..
+            } => {
+                self.node(scrutinee)?;
+                for arm in arms {
+                    let names: Vec<_> = arm
+                        .pattern
+                        .bindings()
+                        .into_iter()
+                        .map(String::from)
+                        .collect();
+                    self.scoped(names, |resolver| {
+                        resolver.block(&mut arm.body)?;
+                        arm.tail.iter_mut().try_for_each(|tail| resolver.node(tail))
+                    })?;
+                }
+            }
+            _ => node
+                .children_mut()
+                .into_iter()
+                .try_for_each(|child| self.node(child))?,
+        }
+        Ok(())
+    }
+
+    /// The value of `node` if it can be worked out without running the
+    /// program: literals, constants with known values and arithmetic or
+    /// comparisons of those. `None` also if working it out fails, so that
+    /// the error happens when the program runs.
+    fn value(&self, node: &Node) -> Option<Value> {
+        let binary = |lhs: &Node, rhs: &Node, op: fn(&Value, &Value) -> Result<Value, String>| {
+            stack::protect(|| op(&self.value(lhs)?, &self.value(rhs)?).ok())
+        };
+        match node {
+            Node::Number { value } => Some(Value::Int(*value)),
+            #[cfg(feature = "bigint")]
+            Node::BigNumber { value } => Some(Value::from(value.clone())),
+            Node::Float { value } => Some(Value::Float(*value)),
+            Node::Str { value } => Some(Value::Str(value.clone())),
+            Node::Bool { value } => Some(Value::Bool(*value)),
+            Node::Nil => Some(Value::Nil),
+            Node::Id { value, .. } => match self.lookup(value)? {
+                Binding::Constant(value) => value.clone(),
+                Binding::Variable => None,
+            },
+            Node::Add { lhs, rhs } => binary(lhs, rhs, Value::add),
+            Node::Sub { lhs, rhs } => binary(lhs, rhs, Value::sub),
+            Node::Mul { lhs, rhs } => binary(lhs, rhs, Value::mul),
+            Node::Div { lhs, rhs } => binary(lhs, rhs, Value::div),
+            Node::Rem { lhs, rhs } => binary(lhs, rhs, Value::rem),
+            Node::Eq { lhs, rhs } => binary(lhs, rhs, |l, r| Ok(l.equals(r))),
+            Node::Ne { lhs, rhs } => binary(lhs, rhs, |l, r| Ok(l.not_equals(r))),
+            Node::Lt { lhs, rhs } => binary(lhs, rhs, Value::lt),
+            Node::Le { lhs, rhs } => binary(lhs, rhs, Value::le),
+            Node::Gt { lhs, rhs } => binary(lhs, rhs, Value::gt),
+            Node::Ge { lhs, rhs } => binary(lhs, rhs, Value::ge),
+            _ => None,
+        }
+    }
+}
+
+/// The literal that evaluates to `value`, for the values that have one.
+fn literal(value: &Value) -> Option<Node> {
+    match value {
+        Value::Int(value) => Some(Node::Number { value: *value }),
+        #[cfg(feature = "bigint")]
+        Value::Big(value) => Some(Node::BigNumber {
+            value: value.clone(),
+        }),
+        Value::Float(value) => Some(Node::Float { value: *value }),
+        Value::Str(value) => Some(Node::Str {
+            value: value.clone(),
+        }),
+        Value::Bool(value) => Some(Node::Bool { value: *value }),
+        Value::Nil => Some(Node::Nil),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod constant_tests {
+    use super::*;
+    use crate::{ast::Span, parser::parse_str_strict};
+
+    fn parse(source: &str) -> Vec<Node> {
+        let mut ast = parse_str_strict(source).unwrap();
+        ast.retain(|node| !matches!(node, Node::Line { .. }));
+        ast
+    }
+
+    fn assign_error(id: &str, line: usize, column: usize) -> Error {
+        Error::Compile {
+            message: format!("Cannot assign to constant '{}'", id),
+            span: Span { line, column },
+        }
+    }
+
+    #[test]
+    fn assignments_to_constants_in_scope_are_refused() {
+        let mut ast = parse("const x = 1;\nfn f() {\n  x = 2;\n}");
+        assert_eq!(check(&mut ast), Err(assign_error("x", 3, 3)));
+        let mut ast = parse("const x = 1;\nlet f = fn() {\n  for i in 0..1 {\n    x = i;\n  }\n};");
+        assert_eq!(check(&mut ast), Err(assign_error("x", 4, 5)));
+    }
+
+    #[test]
+    fn shadowing_names_can_be_assigned() {
+        for source in [
+            "const x = 1; fn f(x) { x = 2; }",
+            "const x = 1; for x in 0..2 { x = 3; }",
+            "const x = 1; try { 1; } catch (x) { x = 2; }",
+            "const x = 1; match 1 { x => { x = 2; } };",
+            "const x = 1; fn g() { let x = 2; x = 3; }",
+            "x = 1; const x = 2;",
+            "fn f() { const x = 1; } x = 2;",
+        ] {
+            let mut ast = parse(source);
+            assert_eq!(check(&mut ast), Ok(()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn checking_leaves_the_ast_alone() {
+        let mut ast = parse("const x = 2 * 3; x + 1;");
+        let original = ast.clone();
+        check(&mut ast).unwrap();
+        assert_eq!(ast, original);
+    }
+
+    #[test]
+    fn known_constants_are_inlined() {
+        let mut ast = parse("const a = 2 * 3; const b = a + 0.5; fn f() { [a, b] }");
+        inline(&mut ast).unwrap();
+        // Padded so that the second declaration starts where it did.
+        assert_eq!(
+            ast,
+            parse("const a = 6;     const b = 6.5; fn f() { [6, 6.5] }")
+        );
+    }
+
+    #[test]
+    fn unknown_or_failing_constants_are_left_to_run() {
+        for source in [
+            "let n = 1; const a = n + 1; a;",
+            "const a = [1]; a;",
+            "const a = 1 / 0; a;",
+            "const a = 1 - 2; a;",
+            "a; const a = 1;",
+            "const a = 1; fn f(a) { a }",
+        ] {
+            let mut ast = parse(source);
+            let original = ast.clone();
+            inline(&mut ast).unwrap();
+            assert_eq!(ast, original, "{}", source);
+        }
+    }
+}
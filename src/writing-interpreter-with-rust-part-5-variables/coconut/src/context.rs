@@ -0,0 +1,275 @@
+use std::{
+    io::{stdout, Write},
+    rc::Rc,
+};
+
+use crate::{
+    builtins, bytecode,
+    error::{Error, Frame, Resource},
+    function::{Closure, Code},
+    host::Host,
+    input::{Input, Stdin},
+    limits::Limits,
+    module::Modules,
+    scope::{self, Scope},
+    stdlib, treewalk,
+    types::Types,
+    value::Value,
+};
+
+/// The state a program runs against: its variables and struct types, where
+/// `println` writes, where `readln` reads, the host functions it may call,
+/// the modules it imported and the limits it has to stay within.
+pub struct Context<'a> {
+    pub scope: Scope,
+    pub types: Types,
+    pub out: Box<dyn Write + 'a>,
+    pub input: Box<dyn Input + 'a>,
+    pub host: Host,
+    pub limits: Limits,
+    pub modules: Modules,
+    /// The name of the running source, shown in stack traces.
+    pub source: Rc<str>,
+    /// The line the running code is at. The tree walker keeps it current,
+    /// the VM only sets it from its line table when an error leaves it.
+    pub line: usize,
+    call_depth: usize,
+}
+
+impl<'a> Context<'a> {
+    pub fn new(out: Box<dyn Write + 'a>) -> Self {
+        Context {
+            scope: Scope::new(),
+            types: Types::default(),
+            out,
+            input: Box::new(Stdin),
+            host: Host::default(),
+            limits: Limits::default(),
+            modules: Modules::default(),
+            source: "<input>".into(),
+            line: 0,
+            call_depth: 0,
+        }
+    }
+
+    /// Declares `id`, refusing to grow the scope past `Limits::max_vars` or
+    /// to replace a constant declared in the same scope.
+    pub fn dec_var(&mut self, id: String, val: Value) -> Result<(), Error> {
+        if self.scope.declared_here(&id) && self.scope.is_constant(&id) {
+            return Err(format!("Cannot declare constant '{}' again", id).into());
+        }
+        self.check_declare(&id)?;
+        self.scope.dec_var(id, val);
+        Ok(())
+    }
+
+    /// Declares the constant `id`. Unlike a variable, it may replace a
+    /// constant, so that a script can be run twice against the same context.
+    pub fn dec_const(&mut self, id: String, val: Value) -> Result<(), Error> {
+        self.check_declare(&id)?;
+        self.scope.dec_const(id, val);
+        Ok(())
+    }
+
+    fn check_declare(&self, id: &str) -> Result<(), Error> {
+        match self.limits.max_vars.is_some() && !self.scope.declared_here(id) {
+            true => self.limits.check(Resource::Variables, self.scope.len() + 1),
+            false => Ok(()),
+        }
+    }
+
+    /// Assigns `id`. Assigning an undeclared name creates it, so this is
+    /// capped the same way as `dec_var`. The compiler refuses assignments
+    /// to the constants it can see; this catches the rest, e.g. from a
+    /// function defined before the constant.
+    pub fn set_var(&mut self, id: String, val: Value) -> Result<(), Error> {
+        if self.scope.is_constant(&id) {
+            return Err(format!("Cannot assign to constant '{}'", id).into());
+        }
+        if self.limits.max_vars.is_some() && !self.scope.contains(&id) {
+            self.limits
+                .check(Resource::Variables, self.scope.len() + 1)?;
+        }
+        self.scope.set_var(id, val);
+        Ok(())
+    }
+
+    /// Records entering a function call, enforcing `Limits::max_call_depth`.
+    /// Each successful call must be paired with `exit_call`.
+    pub fn enter_call(&mut self) -> Result<(), Error> {
+        self.limits
+            .check(Resource::CallDepth, self.call_depth + 1)?;
+        self.call_depth += 1;
+        Ok(())
+    }
+
+    pub fn exit_call(&mut self) {
+        self.call_depth -= 1;
+    }
+
+    /// Where `function` is running, for a stack trace.
+    pub fn frame(&self, function: &str) -> Frame {
+        Frame {
+            function: function.to_string(),
+            source: self.source.clone(),
+            line: self.line,
+        }
+    }
+
+    /// Calls the function `name`, counting it towards the call depth. A
+    /// variable of that name comes first, then host functions, which take
+    /// precedence over builtins of the same name so an embedder can replace
+    /// or disable e.g. `readln`. The prelude's functions come last.
+    pub fn call(&mut self, name: &str, args: &[Value]) -> Result<Value, Error> {
+        match self.resolve(name, args)? {
+            Resolved::Returned(value) => Ok(value),
+            Resolved::Function(callee) => self.call_value(&callee, args),
+        }
+    }
+
+    /// Works out what calling `name` calls, the way `call` does, running it
+    /// if it is a host function or builtin and otherwise leaving the
+    /// function value it names for the caller to call.
+    pub fn resolve(&mut self, name: &str, args: &[Value]) -> Result<Resolved, Error> {
+        if let Some(callee) = self.scope.get_own_var(name) {
+            return Ok(Resolved::Function(callee));
+        }
+        self.enter_call()?;
+        let result = match self.host.contains(name) {
+            true => Some(self.host.call(name, args)),
+            false => builtins::call(self, name, args).or_else(|| stdlib::call(self, name, args)),
+        };
+        self.exit_call();
+        if let Some(result) = result {
+            return result.map(Resolved::Returned);
+        }
+        match self.scope.get_var(name) {
+            Some(callee) => Ok(Resolved::Function(callee)),
+            None => self.host.call(name, args).map(Resolved::Returned),
+        }
+    }
+
+    /// Calls the function value `callee` with `args` bound to its
+    /// parameters, in a frame that sees its captures and the globals.
+    pub fn call_value(&mut self, callee: &Value, args: &[Value]) -> Result<Value, Error> {
+        let closure = match callee {
+            Value::Function(closure) => closure.clone(),
+            other => return Err(format!("Cannot call {}", other.type_name()).into()),
+        };
+        let caller = self.enter_function(&closure, args)?;
+        let result = match &closure.proto.code {
+            Code::Ast(body) => treewalk::call_body(body, self),
+            Code::Ops(chunk) => bytecode::call_function(chunk, self),
+        }
+        .map_err(|error| error.unwound_through(self.frame(closure.name())));
+        self.leave_function(caller);
+        result
+    }
+
+    /// Enters the frame of a call of `closure` and binds `args` to its
+    /// parameters, for a caller that runs the body itself. Each successful
+    /// entry must be paired with `leave_function`.
+    pub fn enter_function(&mut self, closure: &Closure, args: &[Value]) -> Result<Caller, Error> {
+        let params = &closure.proto.params;
+        if args.len() != params.len() {
+            return Err(format!(
+                "Function '{}' expects {} argument(s), got {}",
+                closure.name(),
+                params.len(),
+                args.len()
+            )
+            .into());
+        }
+        self.enter_call()?;
+        let caller = Caller {
+            source: std::mem::replace(&mut self.source, closure.source.clone()),
+            line: self.line,
+            scope: self
+                .scope
+                .enter_function(&closure.captured, &closure.globals),
+        };
+        let bound = params
+            .iter()
+            .zip(args)
+            .try_for_each(|(param, arg)| self.dec_var(param.clone(), arg.clone()));
+        match bound {
+            Ok(()) => Ok(caller),
+            Err(error) => {
+                let error = error.unwound_through(self.frame(closure.name()));
+                self.leave_function(caller);
+                Err(error)
+            }
+        }
+    }
+
+    /// Goes back to the caller of a function entered with `enter_function`.
+    pub fn leave_function(&mut self, caller: Caller) {
+        (self.source, self.line) = (caller.source, caller.line);
+        self.scope.exit(caller.scope);
+        self.exit_call();
+    }
+}
+
+/// What calling a name turned out to be.
+pub enum Resolved {
+    /// A host function or builtin, already run, and what it returned.
+    Returned(Value),
+    /// A function value, still to be called.
+    Function(Value),
+}
+
+/// Where a function was called from, to go back to when it returns.
+pub struct Caller {
+    source: Rc<str>,
+    line: usize,
+    scope: scope::Caller,
+}
+
+impl Default for Context<'_> {
+    fn default() -> Self {
+        Self::new(Box::new(stdout()))
+    }
+}
+
+#[cfg(test)]
+mod context_tests {
+    use super::*;
+    use std::io::sink;
+
+    fn limited(limits: Limits) -> Context<'static> {
+        let mut ctx = Context::new(Box::new(sink()));
+        ctx.limits = limits;
+        ctx
+    }
+
+    #[test]
+    fn variables_are_capped() {
+        let mut ctx = limited(Limits {
+            max_vars: Some(1),
+            ..Limits::default()
+        });
+        assert_eq!(ctx.dec_var("x".to_string(), Value::Int(1)), Ok(()));
+        assert_eq!(ctx.set_var("x".to_string(), Value::Int(2)), Ok(()));
+        assert!(ctx.set_var("y".to_string(), Value::Int(3)).is_err());
+        assert!(ctx.dec_var("y".to_string(), Value::Int(3)).is_err());
+    }
+
+    #[test]
+    fn call_depth_is_capped() {
+        let mut ctx = limited(Limits {
+            max_call_depth: Some(2),
+            ..Limits::default()
+        });
+        assert_eq!(ctx.enter_call(), Ok(()));
+        assert_eq!(ctx.enter_call(), Ok(()));
+        assert_eq!(
+            ctx.enter_call(),
+            Err(Error::ResourceExhausted {
+                resource: Resource::CallDepth,
+                limit: 2
+            })
+        );
+        ctx.exit_call();
+        assert_eq!(ctx.enter_call(), Ok(()));
+    }
+}
@@ -0,0 +1,202 @@
+use std::{fmt, time::Instant};
+
+use crate::{
+    ast::Node,
+    context::Context,
+    error::Error,
+    evaluator::Evaluator,
+    input::{Input, Recording},
+    limits::Limits,
+    parser::parse_str_strict,
+    prelude,
+    value::Value,
+};
+
+/// What a single backend did with a program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    pub result: Result<Option<Value>, Error>,
+    pub output: String,
+}
+
+impl Outcome {
+    /// Like `==`, except that values compare by what they hold: NaN agrees
+    /// with NaN, and each backend's functions and modules with the other's
+    /// of the same name. A backend that ran out of fuel or time agrees with
+    /// anything, since each counts fuel in its own steps.
+    fn agrees_with(&self, other: &Outcome) -> bool {
+        if self.cut_short() || other.cut_short() {
+            return true;
+        }
+        self.output == other.output
+            && match (&self.result, &other.result) {
+                (Ok(Some(l)), Ok(Some(r))) => same_value(l, r),
+                (Ok(None), Ok(None)) => true,
+                (Err(l), Err(r)) => same_error(l, r),
+                _ => false,
+            }
+    }
+
+    fn cut_short(&self) -> bool {
+        matches!(self.result, Err(Error::OutOfFuel | Error::DeadlineExceeded))
+    }
+}
+
+fn same_value(lhs: &Value, rhs: &Value) -> bool {
+    match (lhs, rhs) {
+        (Value::Float(l), Value::Float(r)) => {
+            l.to_bits() == r.to_bits() || l.is_nan() && r.is_nan()
+        }
+        (Value::Array(l), Value::Array(r)) => same_values(&l.borrow(), &r.borrow()),
+        (Value::Map(l), Value::Map(r)) => {
+            let (l, r) = (l.borrow(), r.borrow());
+            l.len() == r.len()
+                && l.iter()
+                    .zip(r.iter())
+                    .all(|((lk, lv), (rk, rv))| lk == rk && same_value(lv, rv))
+        }
+        (Value::Struct(l), Value::Struct(r)) => {
+            l.ty == r.ty && same_values(&l.values.borrow(), &r.values.borrow())
+        }
+        (Value::Enum(l), Value::Enum(r)) => {
+            l.ty == r.ty && l.variant == r.variant && same_values(&l.values, &r.values)
+        }
+        (Value::Function(l), Value::Function(r)) => l.name() == r.name(),
+        (Value::Module(l), Value::Module(r)) => l.name == r.name,
+        _ => lhs == rhs,
+    }
+}
+
+fn same_values(lhs: &[Value], rhs: &[Value]) -> bool {
+    lhs.len() == rhs.len() && lhs.iter().zip(rhs).all(|(l, r)| same_value(l, r))
+}
+
+fn same_error(lhs: &Error, rhs: &Error) -> bool {
+    match (lhs, rhs) {
+        (Error::Thrown { value: l }, Error::Thrown { value: r }) => same_value(l, r),
+        (
+            Error::Traced {
+                error: l,
+                trace: ltrace,
+            },
+            Error::Traced {
+                error: r,
+                trace: rtrace,
+            },
+        ) => ltrace == rtrace && same_error(l, r),
+        _ => lhs == rhs,
+    }
+}
+
+/// Two backends disagreed about a program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    pub lhs: (&'static str, Outcome),
+    pub rhs: (&'static str, Outcome),
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ((lname, lhs), (rname, rhs)) = (&self.lhs, &self.rhs);
+        write!(
+            f,
+            "backends diverged: {} returned {:?} and printed {:?}, {} returned {:?} and printed {:?}",
+            lname, lhs.result, lhs.output, rname, rhs.result, rhs.output
+        )
+    }
+}
+
+/// Runs `input` through both backends, each with a fresh scope, and returns
+/// the shared outcome only if they agree on the result, output and error.
+///
+/// Each backend parses the input for itself rather than getting a copy of
+/// one AST, as copying recurses as deep as the program nests. Parsing is
+/// repeatable for programs without syntax errors, the only ones run. Each
+/// backend runs under `limits`, as `differential_with_input` explains.
+pub fn differential(
+    input: &str,
+    limits: &Limits,
+    lhs: &dyn Evaluator,
+    rhs: &dyn Evaluator,
+) -> Result<Outcome, Box<Divergence>> {
+    let stdin = &mut "".as_bytes();
+    differential_with_input(input, "<input>", stdin, false, limits, lhs, rhs)
+}
+
+/// Like `differential`, with `source` as the name of the program, which
+/// its imports are relative to, and `stdin` as what both backends'
+/// `readln()` reads from. Lines are only taken from `stdin` when a backend
+/// first reads them, and replayed to the other. With `prelude`, each
+/// backend loads the prelude first and runs it too.
+///
+/// Each backend runs under its own copy of `limits`. It gets as long as
+/// was left before their deadline when the comparison started, so the
+/// second isn't cut short by the time the first took. Fuel is counted in
+/// each backend's own steps, so what a program prints before it runs out
+/// may differ.
+pub fn differential_with_input(
+    input: &str,
+    source: &str,
+    stdin: &mut dyn Input,
+    prelude: bool,
+    limits: &Limits,
+    lhs: &dyn Evaluator,
+    rhs: &dyn Evaluator,
+) -> Result<Outcome, Box<Divergence>> {
+    let timeout = limits
+        .deadline
+        .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+    let (lhs_ast, rhs_ast) = match (parse_str_strict(input), parse_str_strict(input)) {
+        (Ok(lhs_ast), Ok(rhs_ast)) => (lhs_ast, rhs_ast),
+        (Err(errors), _) | (_, Err(errors)) => {
+            let out: String = errors.iter().map(|error| format!("{}\n", error)).collect();
+            return Ok(outcome(Err(Error::Parse), out.into_bytes()));
+        }
+    };
+    let stdin = Recording::new(stdin);
+    let run_one = |ast, evaluator| {
+        let mut limits = limits.clone();
+        limits.deadline = timeout.map(|timeout| Instant::now() + timeout);
+        run(ast, source, &stdin, prelude, limits, evaluator)
+    };
+    let lhs_outcome = run_one(lhs_ast, lhs);
+    let rhs_outcome = run_one(rhs_ast, rhs);
+    if lhs_outcome.agrees_with(&rhs_outcome) {
+        Ok(lhs_outcome)
+    } else {
+        Err(Box::new(Divergence {
+            lhs: (lhs.name(), lhs_outcome),
+            rhs: (rhs.name(), rhs_outcome),
+        }))
+    }
+}
+
+fn run(
+    ast: Vec<Node>,
+    source: &str,
+    stdin: &Recording,
+    prelude: bool,
+    limits: Limits,
+    evaluator: &dyn Evaluator,
+) -> Outcome {
+    let mut out = vec![];
+    let mut ctx = Context::new(Box::new(&mut out));
+    ctx.input = Box::new(stdin.replay());
+    ctx.source = source.into();
+    ctx.limits = limits;
+    let result = match prelude {
+        true => prelude::load(&mut ctx, evaluator),
+        false => Ok(()),
+    }
+    .and_then(|()| evaluator.eval(ast, &mut ctx))
+    .map_err(|error| error.called_from(ctx.frame("<main>")));
+    drop(ctx);
+    outcome(result, out)
+}
+
+fn outcome(result: Result<Option<Value>, Error>, out: Vec<u8>) -> Outcome {
+    Outcome {
+        result,
+        output: String::from_utf8_lossy(&out).to_string(),
+    }
+}
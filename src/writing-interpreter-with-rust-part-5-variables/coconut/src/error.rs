@@ -0,0 +1,155 @@
+use std::{fmt, rc::Rc};
+
+use crate::{ast::Span, value::Value};
+
+/// Why a coconut program stopped before producing a result.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The input could not be parsed.
+    Parse,
+    /// The program itself failed, e.g. an unknown variable or an overflow.
+    Runtime(String),
+    /// The instruction budget set with `Limits::fuel` ran out.
+    OutOfFuel,
+    /// The wall-clock deadline set with `Limits::deadline` passed.
+    DeadlineExceeded,
+    /// An array was indexed past its end.
+    IndexOutOfBounds { index: u64, len: usize },
+    /// A struct was built or accessed with a field it doesn't declare.
+    UnknownField {
+        ty: String,
+        field: String,
+        span: Span,
+    },
+    /// The program was rejected before it started, e.g. a struct literal
+    /// of an undeclared struct.
+    Compile { message: String, span: Span },
+    /// A `throw` that no `catch` caught.
+    Thrown { value: Value },
+    /// A memory or depth cap from `Limits` was hit.
+    ResourceExhausted { resource: Resource, limit: usize },
+    /// A catchable `error` that unwound out of functions, with the frames
+    /// it left, innermost first.
+    Traced {
+        error: Box<Error>,
+        trace: Vec<Frame>,
+    },
+}
+
+/// Where a function call was when an error unwound out of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub function: String,
+    pub source: Rc<str>,
+    pub line: usize,
+}
+
+/// The capped resources behind `Error::ResourceExhausted`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resource {
+    Stack,
+    CallDepth,
+    Variables,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parse => write!(f, "Unable to parse input."),
+            Error::Runtime(msg) => write!(f, "{}", msg),
+            Error::OutOfFuel => write!(f, "Out of fuel"),
+            Error::DeadlineExceeded => write!(f, "Deadline exceeded"),
+            Error::IndexOutOfBounds { index, len } => {
+                write!(f, "Index {} out of bounds for length {}", index, len)
+            }
+            Error::UnknownField { ty, field, span } => {
+                write!(f, "{}: {} has no field '{}'", span, ty, field)
+            }
+            Error::Compile { message, span } => write!(f, "{}: {}", span, message),
+            Error::Thrown { value } => write!(f, "Uncaught exception: {}", value),
+            Error::ResourceExhausted { resource, limit } => {
+                write!(
+                    f,
+                    "Resource exhausted: {} limit of {} reached",
+                    resource, limit
+                )
+            }
+            Error::Traced { error, trace } => {
+                write!(f, "{}", error)?;
+                for frame in trace {
+                    write!(f, "\n  in {}", frame)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error {
+    /// The value a `catch` binds for this error. Thrown values are caught
+    /// as they are and failures of the program as their message; running
+    /// out of a resource or time can't be caught, or a script could keep
+    /// going past its limits.
+    pub fn catch_value(&self) -> Option<Value> {
+        match self {
+            Error::Thrown { value } => Some(value.clone()),
+            Error::Traced { error, .. } => error.catch_value(),
+            Error::Runtime(_) | Error::IndexOutOfBounds { .. } | Error::UnknownField { .. } => {
+                Some(Value::Str(self.to_string()))
+            }
+            Error::Parse
+            | Error::Compile { .. }
+            | Error::OutOfFuel
+            | Error::DeadlineExceeded
+            | Error::ResourceExhausted { .. } => None,
+        }
+    }
+
+    /// Records that the error left a function at `frame` on its way out.
+    /// Errors that can't be caught aren't traced.
+    pub fn unwound_through(self, frame: Frame) -> Error {
+        match self {
+            Error::Traced { error, mut trace } => {
+                trace.push(frame);
+                Error::Traced { error, trace }
+            }
+            error if error.catch_value().is_some() => Error::Traced {
+                error: Box::new(error),
+                trace: vec![frame],
+            },
+            other => other,
+        }
+    }
+
+    /// Records where the top level was when an error unwound out of the
+    /// functions it called. Errors raised at the top level itself stay
+    /// untraced.
+    pub fn called_from(self, frame: Frame) -> Error {
+        match self {
+            Error::Traced { .. } => self.unwound_through(frame),
+            other => other,
+        }
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}:{}", self.function, self.source, self.line)
+    }
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Resource::Stack => write!(f, "value stack"),
+            Resource::CallDepth => write!(f, "call depth"),
+            Resource::Variables => write!(f, "variable"),
+        }
+    }
+}
+
+impl From<String> for Error {
+    fn from(msg: String) -> Self {
+        Error::Runtime(msg)
+    }
+}
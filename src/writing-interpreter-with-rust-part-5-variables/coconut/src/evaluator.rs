@@ -0,0 +1,38 @@
+use crate::{ast::Node, bytecode, context::Context, error::Error, treewalk, value::Value};
+
+/// An execution backend for parsed coconut programs.
+///
+/// Every backend must agree on the result, the printed output and the error
+/// for the same program; `differential` checks exactly that. How much fuel
+/// a program costs is the one thing allowed to differ between them.
+pub trait Evaluator {
+    fn name(&self) -> &'static str;
+
+    fn eval(&self, ast: Vec<Node>, ctx: &mut Context) -> Result<Option<Value>, Error>;
+}
+
+/// Compiles the AST to `bytecode::Op`s and runs them on the stack VM.
+pub struct Bytecode;
+
+/// Evaluates the AST directly without compiling it first.
+pub struct TreeWalk;
+
+impl Evaluator for Bytecode {
+    fn name(&self) -> &'static str {
+        "bytecode"
+    }
+
+    fn eval(&self, ast: Vec<Node>, ctx: &mut Context) -> Result<Option<Value>, Error> {
+        bytecode::eval(ast, ctx)
+    }
+}
+
+impl Evaluator for TreeWalk {
+    fn name(&self) -> &'static str {
+        "tree-walk"
+    }
+
+    fn eval(&self, ast: Vec<Node>, ctx: &mut Context) -> Result<Option<Value>, Error> {
+        treewalk::eval(ast, ctx)
+    }
+}
@@ -0,0 +1,191 @@
+//! The template language of the `format` builtin, a small subset of Rust's
+//! `format!`: `{}` placeholders with an optional `:[[fill]align][0][width]`
+//! spec, and `{{`/`}}` for literal braces.
+
+use crate::value::Value;
+
+/// The widest a placeholder may pad to. Like Rust's, it fits in a `u16`,
+/// so that a template can't ask for gigabytes of padding.
+const MAX_WIDTH: usize = u16::MAX as usize;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+#[derive(Debug, PartialEq)]
+struct Spec {
+    fill: char,
+    align: Option<Align>,
+    zero: bool,
+    width: usize,
+}
+
+/// Substitutes `args` into the placeholders of `template` in order.
+pub fn format(template: &str, args: &[Value]) -> Result<String, String> {
+    let mut out = String::new();
+    let mut args = args.iter();
+    let mut placeholders = 0;
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                out.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                out.push('}');
+            }
+            '{' => {
+                let mut spec = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => spec.push(c),
+                        None => return Err("unterminated placeholder".to_string()),
+                    }
+                }
+                placeholders += 1;
+                let spec = parse_spec(&spec)?;
+                let arg = args
+                    .next()
+                    .ok_or(format!("missing argument for placeholder {}", placeholders))?;
+                pad(&mut out, arg, &spec);
+            }
+            '}' => return Err("unmatched '}'".to_string()),
+            _ => out.push(c),
+        }
+    }
+    if args.next().is_some() {
+        return Err(format!(
+            "too many arguments for {} placeholder(s)",
+            placeholders
+        ));
+    }
+    Ok(out)
+}
+
+fn parse_spec(spec: &str) -> Result<Spec, String> {
+    let mut parsed = Spec {
+        fill: ' ',
+        align: None,
+        zero: false,
+        width: 0,
+    };
+    if spec.is_empty() {
+        return Ok(parsed);
+    }
+    let invalid = || format!("invalid format spec '{}'", spec);
+    let mut rest = spec.strip_prefix(':').ok_or_else(invalid)?;
+    let chars: Vec<char> = rest.chars().take(2).collect();
+    if let Some(align) = chars.get(1).copied().and_then(align) {
+        parsed.fill = chars[0];
+        parsed.align = Some(align);
+        rest = &rest[chars[0].len_utf8() + 1..];
+    } else if let Some(align) = chars.first().copied().and_then(align) {
+        parsed.align = Some(align);
+        rest = &rest[1..];
+    }
+    if let Some(width) = rest.strip_prefix('0') {
+        parsed.zero = true;
+        rest = width;
+    }
+    if !rest.is_empty() {
+        parsed.width = rest.parse().map_err(|_| invalid())?;
+        if parsed.width > MAX_WIDTH {
+            return Err(format!("width {} is larger than {}", rest, MAX_WIDTH));
+        }
+    }
+    Ok(parsed)
+}
+
+fn align(c: char) -> Option<Align> {
+    match c {
+        '<' => Some(Align::Left),
+        '>' => Some(Align::Right),
+        '^' => Some(Align::Center),
+        _ => None,
+    }
+}
+
+/// Writes `arg` padded to the spec's width. Like Rust, numbers align right
+/// and everything else left unless the spec says otherwise.
+fn pad(out: &mut String, arg: &Value, spec: &Spec) {
+    let text = arg.to_string();
+    let padding = spec.width.saturating_sub(text.chars().count());
+    let numeric = arg.is_int() || matches!(arg, Value::Float(_));
+    if spec.zero && numeric && spec.align.is_none() {
+        out.extend(std::iter::repeat_n('0', padding));
+        out.push_str(&text);
+        return;
+    }
+    let default = if numeric { Align::Right } else { Align::Left };
+    let (before, after) = match spec.align.unwrap_or(default) {
+        Align::Left => (0, padding),
+        Align::Right => (padding, 0),
+        Align::Center => (padding / 2, padding - padding / 2),
+    };
+    out.extend(std::iter::repeat_n(spec.fill, before));
+    out.push_str(&text);
+    out.extend(std::iter::repeat_n(spec.fill, after));
+}
+
+#[cfg(test)]
+mod format_tests {
+    use super::*;
+
+    #[test]
+    fn substitutes_in_order() {
+        assert_eq!(
+            format("{} + {} = {}", &[1.into(), 2.into(), 3.into()]),
+            Ok("1 + 2 = 3".to_string())
+        );
+        assert_eq!(format("{{{}}}", &["x".into()]), Ok("{x}".to_string()));
+    }
+
+    #[test]
+    fn pads_to_width() {
+        assert_eq!(format("[{:4}]", &[7.into()]), Ok("[   7]".to_string()));
+        assert_eq!(format("[{:4}]", &["ab".into()]), Ok("[ab  ]".to_string()));
+        assert_eq!(format("[{:04}]", &[7.into()]), Ok("[0007]".to_string()));
+        assert_eq!(format("[{:<4}]", &[7.into()]), Ok("[7   ]".to_string()));
+        assert_eq!(
+            format("[{:*^5}]", &["ab".into()]),
+            Ok("[*ab**]".to_string())
+        );
+        assert_eq!(format("[{:1}]", &["abc".into()]), Ok("[abc]".to_string()));
+    }
+
+    #[test]
+    fn rejects_mismatched_arguments() {
+        assert_eq!(
+            format("{} {}", &[1.into()]),
+            Err("missing argument for placeholder 2".to_string())
+        );
+        assert_eq!(
+            format("{}", &[1.into(), 2.into()]),
+            Err("too many arguments for 1 placeholder(s)".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_templates() {
+        assert_eq!(
+            format("{", &[]),
+            Err("unterminated placeholder".to_string())
+        );
+        assert_eq!(format("}", &[]), Err("unmatched '}'".to_string()));
+        assert_eq!(
+            format("{:x}", &[1.into()]),
+            Err("invalid format spec ':x'".to_string())
+        );
+        assert_eq!(
+            format("{:100000000000}", &[1.into()]),
+            Err("width 100000000000 is larger than 65535".to_string())
+        );
+        assert_eq!(format("{:65535}", &[1.into()]).map(|s| s.len()), Ok(65535));
+    }
+}
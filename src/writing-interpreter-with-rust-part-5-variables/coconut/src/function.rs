@@ -0,0 +1,260 @@
+//! Function values: the code of a `fn` together with the variables it
+//! captured from the scopes around it.
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fmt,
+    rc::{Rc, Weak},
+};
+
+use crate::{ast::Node, bytecode::Chunk, context::Context, scope::Cell, stack, value::Value};
+
+/// A function's body, in the form of the backend that created it. Either
+/// backend can call either kind, e.g. a host passing a closure back in.
+#[derive(Debug, PartialEq)]
+pub enum Code {
+    Ast(Rc<Vec<Node>>),
+    Ops(Chunk),
+}
+
+/// Everything known about a `fn` before it runs: its parameters, body and
+/// the enclosing variables it refers to.
+#[derive(Debug, PartialEq)]
+pub struct Prototype {
+    pub name: Option<String>,
+    pub params: Vec<String>,
+    /// Free variables of the body, from `captures`.
+    pub captures: Vec<String>,
+    pub code: Code,
+}
+
+/// A prototype bound to the cells of the variables it captured, and to
+/// the globals and the name of the source it was created in.
+pub struct Closure {
+    pub proto: Rc<Prototype>,
+    pub captured: Vec<(String, Cell)>,
+    pub globals: Weak<RefCell<HashMap<String, Cell>>>,
+    pub source: Rc<str>,
+}
+
+impl Closure {
+    pub fn name(&self) -> &str {
+        self.proto.name.as_deref().unwrap_or("fn")
+    }
+}
+
+/// Closures are only equal to themselves.
+impl PartialEq for Closure {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+/// Shows the name only: the body differs between backends, which would make
+/// differential runs disagree.
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Closure({})", self.name())
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Prototype {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Prototype {
+            name: u.arbitrary()?,
+            params: u.arbitrary()?,
+            captures: u.arbitrary()?,
+            code: Code::Ops(u.arbitrary()?),
+        })
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Closure {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Closure {
+            proto: u.arbitrary()?,
+            captured: vec![],
+            globals: Weak::new(),
+            source: u.arbitrary::<String>()?.into(),
+        })
+    }
+}
+
+/// Creates a closure of `proto`, capturing the cells of those of its free
+/// variables that are locals where it is created.
+pub fn close(proto: Rc<Prototype>, ctx: &Context) -> Value {
+    let captured = proto
+        .captures
+        .iter()
+        .filter_map(|id| Some((id.clone(), ctx.scope.local(id)?)))
+        .collect();
+    Value::Function(Rc::new(Closure {
+        proto,
+        captured,
+        globals: ctx.scope.globals(),
+        source: ctx.source.clone(),
+    }))
+}
+
+/// The variables a function with `params` and `body` uses without
+/// declaring them first, in order of first use. Whether each one is a local
+/// of an enclosing function, and so gets captured, is only known where the
+/// closure is created.
+pub fn captures(params: &[String], body: &[Node]) -> Vec<String> {
+    let mut free = Free {
+        bound: vec![params.iter().cloned().collect()],
+        found: vec![],
+    };
+    free.block(body);
+    free.found
+}
+
+struct Free {
+    /// Names declared so far, one set per nested scope.
+    bound: Vec<HashSet<String>>,
+    found: Vec<String>,
+}
+
+impl Free {
+    fn block(&mut self, body: &[Node]) {
+        for node in body {
+            self.node(node);
+        }
+    }
+
+    fn bind(&mut self, id: &str) {
+        if let Some(scope) = self.bound.last_mut() {
+            scope.insert(id.to_string());
+        }
+    }
+
+    fn use_var(&mut self, id: &str) {
+        let bound = self.bound.iter().any(|scope| scope.contains(id));
+        if !bound && !self.found.iter().any(|f| f == id) {
+            self.found.push(id.to_string());
+        }
+    }
+
+    fn node(&mut self, node: &Node) {
+        stack::protect(|| self.visit(node))
+    }
+
+    fn visit(&mut self, node: &Node) {
+        match node {
+            Node::Id { value, .. } => self.use_var(value),
+            Node::Call { id, args, .. } => {
+                self.use_var(id);
+                args.iter().for_each(|arg| self.node(arg));
+            }
+            Node::Assign { id, rhs, .. } => {
+                self.node(rhs);
+                self.use_var(id);
+            }
+            Node::Declare { id, rhs } => {
+                if let Some(rhs) = rhs {
+                    self.node(rhs);
+                }
+                self.bind(id);
+            }
+            Node::Const { id, rhs, .. } => {
+                self.node(rhs);
+                self.bind(id);
+            }
+            Node::Function { name, params, body } => {
+                // A named function is in scope in its own body.
+                if let Some(name) = name {
+                    self.bind(name);
+                }
+                let mut inner = params.iter().cloned().collect::<HashSet<_>>();
+                inner.extend(name.clone());
+                self.bound.push(inner);
+                self.block(body);
+                self.bound.pop();
+            }
+            Node::Import { name, .. } => self.bind(name),
+            Node::For { id, iterable, body } => {
+                self.node(iterable);
+                self.bound.push(HashSet::from([id.clone()]));
+                self.block(body);
+                self.bound.pop();
+            }
+            Node::Try { body, id, handler } => {
+                self.bound.push(HashSet::new());
+                self.block(body);
+                self.bound.pop();
+                self.bound.push(HashSet::from([id.clone()]));
+                self.block(handler);
+                self.bound.pop();
+            }
+            Node::Match {
+                scrutinee, arms, ..
+            } => {
+                self.node(scrutinee);
+                for arm in arms {
+                    let bindings = arm.pattern.bindings().into_iter().map(str::to_string);
+                    self.bound.push(bindings.collect());
+                    self.block(&arm.body);
+                    arm.tail.iter().for_each(|tail| self.node(tail));
+                    self.bound.pop();
+                }
+            }
+            _ => node
+                .children()
+                .into_iter()
+                .for_each(|child| self.node(child)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod function_tests {
+    use super::*;
+    use crate::parser::parse_str_strict;
+
+    fn captures_of(source: &str) -> Vec<String> {
+        match parse_str_strict(source).unwrap().pop() {
+            Some(Node::Function { params, body, .. }) => captures(&params, &body),
+            other => panic!("not a function: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn captures_free_variables_in_order() {
+        assert_eq!(
+            captures_of("fn f(x) { let y = x + a; b = y; a + c(y) }"),
+            vec!["a", "b", "c"]
+        );
+    }
+
+    #[test]
+    fn nested_functions_pass_captures_through() {
+        assert_eq!(
+            captures_of("fn f(x) { fn g(y) { x + y + z } g(1) + w }"),
+            vec!["z", "w"]
+        );
+    }
+
+    #[test]
+    fn loop_variables_are_bound_in_the_body_only() {
+        assert_eq!(
+            captures_of("fn f() { for i in 0..n { let j = i; println(j); } j }"),
+            vec!["n", "j"]
+        );
+    }
+
+    #[test]
+    fn match_bindings_are_bound_in_their_arm_only() {
+        assert_eq!(
+            captures_of("fn f() { match o { Opt::Some(x) => x + y, _ => x } }"),
+            vec!["o", "y", "x"]
+        );
+    }
+
+    #[test]
+    fn variables_are_free_until_declared() {
+        assert_eq!(captures_of("fn f() { let y = y; y }"), vec!["y"]);
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+
+use crate::{error::Error, value::Value};
+
+/// A native function callable from coconut code.
+pub type NativeFn = dyn Fn(&[Value]) -> Result<Value, String>;
+
+struct HostFn {
+    arity: usize,
+    func: Box<NativeFn>,
+}
+
+/// Functions the embedding application exposes to scripts by name.
+#[derive(Default)]
+pub struct Host {
+    functions: HashMap<String, HostFn>,
+}
+
+impl Host {
+    /// Registers `func` under `name`, replacing any previous function of the
+    /// same name. Calls with other than `arity` arguments are rejected before
+    /// `func` runs.
+    pub fn register(
+        &mut self,
+        name: &str,
+        arity: usize,
+        func: impl Fn(&[Value]) -> Result<Value, String> + 'static,
+    ) {
+        self.functions.insert(
+            name.to_string(),
+            HostFn {
+                arity,
+                func: Box::new(func),
+            },
+        );
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.functions.contains_key(name)
+    }
+
+    /// Calls `name` with `args`. Errors returned by the function surface as
+    /// `Error::Runtime`.
+    pub fn call(&self, name: &str, args: &[Value]) -> Result<Value, Error> {
+        let function = self
+            .functions
+            .get(name)
+            .ok_or(format!("Function '{}' not found", name))?;
+        if args.len() != function.arity {
+            return Err(format!(
+                "Function '{}' expects {} argument(s), got {}",
+                name,
+                function.arity,
+                args.len()
+            )
+            .into());
+        }
+        Ok((function.func)(args)?)
+    }
+}
+
+#[cfg(test)]
+mod host_tests {
+    use super::*;
+
+    #[test]
+    fn call_registered_function() {
+        let mut host = Host::default();
+        host.register("add", 2, |args| args[0].add(&args[1]));
+        assert_eq!(
+            host.call("add", &[Value::Int(1), Value::Int(2)]),
+            Ok(Value::Int(3))
+        );
+    }
+
+    #[test]
+    fn call_checks_arity() {
+        let mut host = Host::default();
+        host.register("add", 2, |args| args[0].add(&args[1]));
+        assert_eq!(
+            host.call("add", &[Value::Int(1)]),
+            Err(Error::Runtime(
+                "Function 'add' expects 2 argument(s), got 1".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn call_unknown_function() {
+        assert_eq!(
+            Host::default().call("nope", &[]),
+            Err(Error::Runtime("Function 'nope' not found".to_string()))
+        );
+    }
+
+    #[test]
+    fn call_propagates_host_errors() {
+        let mut host = Host::default();
+        host.register("fail", 0, |_| Err("host failure".to_string()));
+        assert_eq!(
+            host.call("fail", &[]),
+            Err(Error::Runtime("host failure".to_string()))
+        );
+    }
+}
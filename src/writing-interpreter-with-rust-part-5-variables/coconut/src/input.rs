@@ -0,0 +1,138 @@
+use std::{
+    cell::RefCell,
+    io::{self, BufRead},
+};
+
+/// Where `readln()` takes its lines from.
+pub trait Input {
+    /// The next line without its line terminator, `None` at end of input.
+    fn read_line(&mut self) -> io::Result<Option<String>>;
+}
+
+impl<R: BufRead> Input for R {
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        if BufRead::read_line(self, &mut line)? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(trim_newline(line)))
+    }
+}
+
+/// Reads from the process's stdin. Unlike a `BufReader` around it, this
+/// shares stdin's own buffer, so nothing is read ahead and lost to the host.
+pub struct Stdin;
+
+impl Input for Stdin {
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(trim_newline(line)))
+    }
+}
+
+/// Lines read from another input as they are first asked for, so that
+/// several readers can be given the same ones. Each reader made by `replay`
+/// starts at the first line.
+pub struct Recording<'a> {
+    tape: RefCell<Tape<'a>>,
+}
+
+struct Tape<'a> {
+    source: &'a mut dyn Input,
+    lines: Vec<String>,
+    ended: bool,
+}
+
+impl<'a> Recording<'a> {
+    pub fn new(source: &'a mut dyn Input) -> Self {
+        Recording {
+            tape: RefCell::new(Tape {
+                source,
+                lines: vec![],
+                ended: false,
+            }),
+        }
+    }
+
+    pub fn replay(&self) -> Replay<'_, 'a> {
+        Replay {
+            recording: self,
+            next: 0,
+        }
+    }
+}
+
+/// Reads the lines of a `Recording`, reading on from its source past the
+/// ones recorded so far.
+pub struct Replay<'r, 'a> {
+    recording: &'r Recording<'a>,
+    next: usize,
+}
+
+impl Input for Replay<'_, '_> {
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        let mut tape = self.recording.tape.borrow_mut();
+        if self.next == tape.lines.len() && !tape.ended {
+            match tape.source.read_line()? {
+                Some(line) => tape.lines.push(line),
+                None => tape.ended = true,
+            }
+        }
+        let line = tape.lines.get(self.next).cloned();
+        if line.is_some() {
+            self.next += 1;
+        }
+        Ok(line)
+    }
+}
+
+fn trim_newline(mut line: String) -> String {
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+    line
+}
+
+#[cfg(test)]
+mod input_tests {
+    use super::*;
+
+    #[test]
+    fn reads_lines_until_end() {
+        let mut input = "one\r\ntwo\nthree".as_bytes();
+        assert_eq!(
+            Input::read_line(&mut input).unwrap(),
+            Some("one".to_string())
+        );
+        assert_eq!(
+            Input::read_line(&mut input).unwrap(),
+            Some("two".to_string())
+        );
+        assert_eq!(
+            Input::read_line(&mut input).unwrap(),
+            Some("three".to_string())
+        );
+        assert_eq!(Input::read_line(&mut input).unwrap(), None);
+    }
+
+    #[test]
+    fn replays_read_each_line_once() {
+        let mut source = "one\ntwo\n".as_bytes();
+        let recording = Recording::new(&mut source);
+        let mut first = recording.replay();
+        assert_eq!(first.read_line().unwrap(), Some("one".to_string()));
+        let mut second = recording.replay();
+        for expected in ["one", "two"] {
+            assert_eq!(second.read_line().unwrap(), Some(expected.to_string()));
+        }
+        assert_eq!(second.read_line().unwrap(), None);
+        assert_eq!(first.read_line().unwrap(), Some("two".to_string()));
+        assert_eq!(first.read_line().unwrap(), None);
+    }
+}
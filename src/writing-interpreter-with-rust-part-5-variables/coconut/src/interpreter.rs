@@ -0,0 +1,424 @@
+use std::{
+    io::{BufRead, Write},
+    path::PathBuf,
+    time::Instant,
+};
+
+use crate::{
+    context::Context,
+    error::Error,
+    eval_str_in,
+    evaluator::{Bytecode, Evaluator},
+    limits::Limits,
+    output::LineCallback,
+    prelude,
+    value::Value,
+};
+
+/// Embedding API: a coconut interpreter whose variables persist between
+/// calls to `eval`, and which can be bounded for running untrusted input.
+///
+/// ```no_run
+/// use coconut::{value::Value, Interpreter};
+///
+/// let mut interpreter = Interpreter::new().with_fuel(10_000);
+/// interpreter.eval("let x = 1;").unwrap();
+/// assert_eq!(interpreter.eval("x + 1").unwrap(), Some(Value::Int(2)));
+/// println!("{:?} fuel left", interpreter.remaining_fuel());
+/// ```
+pub struct Interpreter {
+    ctx: Context<'static>,
+    evaluator: Box<dyn Evaluator>,
+    /// Whether the prelude is still to be loaded, before the first `eval`.
+    prelude: bool,
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Interpreter {
+            ctx: Context::default(),
+            evaluator: Box::new(Bytecode),
+            prelude: true,
+        }
+    }
+
+    /// Runs programs on `evaluator` instead of the bytecode VM.
+    pub fn with_evaluator(mut self, evaluator: impl Evaluator + 'static) -> Self {
+        self.evaluator = Box::new(evaluator);
+        self
+    }
+
+    /// Leaves out the prelude, so scripts see only the native functions.
+    pub fn without_prelude(mut self) -> Self {
+        self.prelude = false;
+        self
+    }
+
+    /// Sends program output to `out` instead of stdout.
+    pub fn with_output(mut self, out: impl Write + 'static) -> Self {
+        self.ctx.out = Box::new(out);
+        self
+    }
+
+    /// Calls `callback` with every line the program prints.
+    pub fn with_output_fn(self, callback: impl FnMut(&str) + 'static) -> Self {
+        self.with_output(LineCallback::new(callback))
+    }
+
+    /// Makes `readln()` read from `input` instead of stdin.
+    pub fn with_input(mut self, input: impl BufRead + 'static) -> Self {
+        self.ctx.input = Box::new(input);
+        self
+    }
+
+    /// Names the programs in stack traces, e.g. after the file they came
+    /// from. Functions keep the name they were defined under.
+    pub fn with_source(mut self, name: &str) -> Self {
+        self.ctx.source = name.into();
+        self
+    }
+
+    /// Makes `import name;` search `dirs` after the importing file's
+    /// directory, instead of the directories in `COCONUT_PATH`.
+    pub fn with_module_path(mut self, dirs: Vec<PathBuf>) -> Self {
+        self.ctx.modules.path = dirs;
+        self
+    }
+
+    /// Stops execution with `Error::OutOfFuel` after `fuel` instructions.
+    /// The budget is shared by all subsequent calls to `eval`.
+    pub fn with_fuel(mut self, fuel: u64) -> Self {
+        self.ctx.limits.fuel = Some(fuel);
+        self
+    }
+
+    /// Stops execution with `Error::DeadlineExceeded` once `deadline` passes.
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.ctx.limits.deadline = Some(deadline);
+        self
+    }
+
+    /// Caps the depth of the bytecode VM's value stack.
+    pub fn with_max_stack(mut self, max: usize) -> Self {
+        self.ctx.limits.max_stack = Some(max);
+        self
+    }
+
+    /// Caps how deeply function calls may nest.
+    pub fn with_max_call_depth(mut self, max: usize) -> Self {
+        self.ctx.limits.max_call_depth = Some(max);
+        self
+    }
+
+    /// Caps the number of live variables.
+    pub fn with_max_vars(mut self, max: usize) -> Self {
+        self.ctx.limits.max_vars = Some(max);
+        self
+    }
+
+    /// Replaces all limits at once.
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.ctx.limits = limits;
+        self
+    }
+
+    /// Fuel left for further evaluation, `None` if there is no budget.
+    pub fn remaining_fuel(&self) -> Option<u64> {
+        self.ctx.limits.fuel
+    }
+
+    /// Replaces the remaining fuel, e.g. to top up a budget between scripts.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.ctx.limits.fuel = fuel;
+    }
+
+    /// Exposes `func` to scripts as `name(...)` taking `arity` arguments.
+    /// An `Err` returned by `func` becomes a coconut runtime error.
+    pub fn register(
+        &mut self,
+        name: &str,
+        arity: usize,
+        func: impl Fn(&[Value]) -> Result<Value, String> + 'static,
+    ) -> &mut Self {
+        self.ctx.host.register(name, arity, func);
+        self
+    }
+
+    pub fn eval(&mut self, input: &str) -> Result<Option<Value>, Error> {
+        if std::mem::take(&mut self.prelude) {
+            prelude::load(&mut self.ctx, self.evaluator.as_ref())?;
+        }
+        eval_str_in(input, self.evaluator.as_ref(), &mut self.ctx)
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod interpreter_tests {
+    use super::*;
+    use crate::{error::Resource, evaluator::TreeWalk};
+    use std::{cell::RefCell, io::Cursor, rc::Rc, time::Duration};
+
+    #[test]
+    fn variables_persist_between_evals() {
+        let mut interpreter = Interpreter::new();
+        interpreter.eval("let x = 2;").unwrap();
+        assert_eq!(interpreter.eval("x * 3").unwrap(), Some(Value::Int(6)));
+    }
+
+    #[test]
+    fn declarations_from_earlier_evals_are_not_checked() {
+        let mut interpreter = Interpreter::new();
+        assert!(interpreter.eval("let x; x;").is_err());
+        interpreter.eval("let y;").unwrap();
+        assert_eq!(interpreter.eval("y;"), Ok(Some(Value::Nil)));
+    }
+
+    #[test]
+    fn traces_name_the_source() {
+        let mut interpreter = Interpreter::new().with_source("calc.cnt");
+        assert_eq!(
+            interpreter
+                .eval("fn f() {\n  1 / 0\n}\nf();")
+                .unwrap_err()
+                .to_string(),
+            "Division by zero\n  in f at calc.cnt:2\n  in <main> at calc.cnt:4"
+        );
+    }
+
+    #[test]
+    fn the_prelude_can_be_left_out() {
+        let mut interpreter = Interpreter::new();
+        assert_eq!(
+            interpreter.eval("let s = 0..3; sum(s) + product(s);"),
+            Ok(Some(Value::Int(3)))
+        );
+        let mut interpreter = Interpreter::new().without_prelude();
+        assert_eq!(
+            interpreter.eval("product([1]);"),
+            Err(Error::Runtime("Function 'product' not found".to_string()))
+        );
+    }
+
+    #[test]
+    fn fuel_runs_out() {
+        let input = "1+1;".repeat(100);
+        for mut interpreter in [
+            Interpreter::new().with_fuel(50),
+            Interpreter::new().with_evaluator(TreeWalk).with_fuel(50),
+        ] {
+            assert_eq!(interpreter.eval(&input), Err(Error::OutOfFuel));
+            assert_eq!(interpreter.remaining_fuel(), Some(0));
+        }
+    }
+
+    #[test]
+    fn remaining_fuel_is_queryable() {
+        let mut interpreter = Interpreter::new().with_fuel(100);
+        assert_eq!(interpreter.eval("1+2").unwrap(), Some(Value::Int(3)));
+        assert_eq!(interpreter.remaining_fuel(), Some(97));
+        assert_eq!(Interpreter::new().remaining_fuel(), None);
+    }
+
+    #[test]
+    fn deadline_stops_execution() {
+        let mut interpreter = Interpreter::new().with_deadline(Instant::now());
+        assert_eq!(interpreter.eval("1+1"), Err(Error::DeadlineExceeded));
+
+        let mut interpreter =
+            Interpreter::new().with_deadline(Instant::now() + Duration::from_secs(60));
+        assert_eq!(interpreter.eval("1+1"), Ok(Some(Value::Int(2))));
+    }
+
+    #[test]
+    fn stack_depth_is_capped() {
+        let mut interpreter = Interpreter::new().with_max_stack(4);
+        assert_eq!(
+            interpreter.eval(&"1;".repeat(5)),
+            Err(Error::ResourceExhausted {
+                resource: Resource::Stack,
+                limit: 4
+            })
+        );
+    }
+
+    #[test]
+    fn errors_inside_loops_close_their_blocks() {
+        for mut interpreter in [
+            Interpreter::new(),
+            Interpreter::new().with_evaluator(TreeWalk),
+        ] {
+            assert_eq!(
+                interpreter.eval("for i in 0..3 { let y = i; y / 0; }"),
+                Err(Error::Runtime("Division by zero".to_string()))
+            );
+            // `z` must be a global, not a local of the abandoned loop body.
+            assert_eq!(
+                interpreter.eval("let z = 1; fn get_z() { z } get_z();"),
+                Ok(Some(Value::Int(1)))
+            );
+        }
+    }
+
+    #[test]
+    fn limits_cannot_be_caught() {
+        for mut interpreter in [
+            Interpreter::new().with_fuel(200),
+            Interpreter::new().with_evaluator(TreeWalk).with_fuel(200),
+        ] {
+            assert_eq!(
+                interpreter.eval("try { for i in 0..1000 { i; } } catch (e) { println(e); }"),
+                Err(Error::OutOfFuel)
+            );
+        }
+        for mut interpreter in [
+            Interpreter::new().with_max_call_depth(8),
+            Interpreter::new()
+                .with_evaluator(TreeWalk)
+                .with_max_call_depth(8),
+        ] {
+            assert_eq!(
+                interpreter.eval("fn f() { try { f(); } catch (e) { 1; } } f();"),
+                Err(Error::ResourceExhausted {
+                    resource: Resource::CallDepth,
+                    limit: 8
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn variables_are_capped() {
+        for mut interpreter in [
+            Interpreter::new().with_max_vars(2),
+            Interpreter::new().with_evaluator(TreeWalk).with_max_vars(2),
+        ] {
+            assert_eq!(interpreter.eval("let a = 1; let b = 2; a = 3;"), Ok(None));
+            assert_eq!(
+                interpreter.eval("let c = 3;"),
+                Err(Error::ResourceExhausted {
+                    resource: Resource::Variables,
+                    limit: 2
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn scripts_call_host_functions() {
+        for mut interpreter in [
+            Interpreter::new(),
+            Interpreter::new().with_evaluator(TreeWalk),
+        ] {
+            interpreter
+                .register("answer", 0, |_| Ok(Value::Int(42)))
+                .register("sum", 3, |args| args[0].add(&args[1])?.add(&args[2]));
+            assert_eq!(interpreter.eval("answer()"), Ok(Some(Value::Int(42))));
+            assert_eq!(
+                interpreter.eval("let x = sum(1, 2 * 3, answer()); x + 1;"),
+                Ok(Some(Value::Int(50)))
+            );
+            assert_eq!(
+                interpreter.eval("sum(1, 2)"),
+                Err(Error::Runtime(
+                    "Function 'sum' expects 3 argument(s), got 2".to_string()
+                ))
+            );
+        }
+    }
+
+    #[test]
+    fn host_errors_become_runtime_errors() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register("checked_div", 2, |args| {
+            let quotient = args[0].as_int()?.checked_div(args[1].as_int()?);
+            Ok(Value::Int(quotient.ok_or("division by zero".to_string())?))
+        });
+        assert_eq!(
+            interpreter.eval("checked_div(6, 3)"),
+            Ok(Some(Value::Int(2)))
+        );
+        assert_eq!(
+            interpreter.eval("checked_div(1, 0)"),
+            Err(Error::Runtime("division by zero".to_string()))
+        );
+    }
+
+    #[test]
+    fn host_calls_count_towards_call_depth() {
+        let mut interpreter = Interpreter::new().with_max_call_depth(0);
+        interpreter.register("one", 0, |_| Ok(Value::Int(1)));
+        assert_eq!(
+            interpreter.eval("one()"),
+            Err(Error::ResourceExhausted {
+                resource: Resource::CallDepth,
+                limit: 0
+            })
+        );
+    }
+
+    #[test]
+    fn output_goes_to_callback() {
+        let lines = Rc::new(RefCell::new(vec![]));
+        let sink = lines.clone();
+        let mut interpreter =
+            Interpreter::new().with_output_fn(move |line| sink.borrow_mut().push(line.to_string()));
+        interpreter.eval("println(1); println(2 + 3);").unwrap();
+        assert_eq!(*lines.borrow(), vec!["1", "5"]);
+    }
+
+    #[test]
+    fn readln_reads_scripted_input() {
+        for evaluator in [&Bytecode as &dyn Evaluator, &TreeWalk] {
+            let lines = Rc::new(RefCell::new(vec![]));
+            let sink = lines.clone();
+            let mut ctx = Context::new(Box::new(LineCallback::new(move |line: &str| {
+                sink.borrow_mut().push(line.to_string())
+            })));
+            ctx.input = Box::new(Cursor::new("2\n40\n"));
+            let result = eval_str_in(
+                "let a = parse_int(readln()); let b = parse_int(readln()); println(a + b);",
+                evaluator,
+                &mut ctx,
+            );
+            assert_eq!(result, Ok(None));
+            assert_eq!(*lines.borrow(), vec!["42"]);
+        }
+    }
+
+    #[test]
+    fn bad_input_is_a_runtime_error() {
+        let mut interpreter = Interpreter::new().with_input(Cursor::new("forty two\n"));
+        assert_eq!(
+            interpreter.eval("parse_int(readln())"),
+            Err(Error::Runtime(
+                "parse_int: 'forty two' is not an integer".to_string()
+            ))
+        );
+        assert_eq!(
+            interpreter.eval("readln()"),
+            Err(Error::Runtime("readln: end of input".to_string()))
+        );
+        assert_eq!(
+            interpreter.eval("parse_int(1)"),
+            Err(Error::Runtime(
+                "parse_int: Expected string, got int".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn host_functions_override_builtins() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register("readln", 0, |_| Err("readln is disabled".to_string()));
+        assert_eq!(
+            interpreter.eval("readln()"),
+            Err(Error::Runtime("readln is disabled".to_string()))
+        );
+    }
+}
@@ -1,19 +1,75 @@
+pub mod assigned;
 pub mod ast;
+#[cfg(feature = "bigint")]
+pub mod bigint;
+pub mod builtins;
 pub mod bytecode;
+pub mod constant;
+pub mod context;
+pub mod differential;
+pub mod error;
+pub mod evaluator;
+pub mod format;
+pub mod function;
+pub mod host;
+pub mod input;
+pub mod interpreter;
+pub mod limits;
+pub mod module;
+pub mod output;
 pub mod parser;
+pub mod prelude;
 pub mod scope;
+pub mod stack;
+pub mod stdlib;
+pub mod treewalk;
+pub mod types;
+pub mod value;
 
-use bytecode::eval;
-use parser::parse_str;
-use scope::Scope;
+use std::io::{stdout, Write};
 
-pub fn eval_str(input: &String) -> Result<Option<u64>, String> {
-    match parse_str(input) {
-        Some(Ok(ast)) => match eval(ast, &mut Scope::new()) {
-            Ok(result) => Ok(result),
-            Err(x) => Err(x),
-        },
-        Some(Err(_)) => Err("Unable to parse input.".to_string()),
-        _ => Ok(None),
+use context::Context;
+use error::Error;
+use evaluator::{Bytecode, Evaluator};
+use parser::parse_str_strict;
+use value::Value;
+
+pub use interpreter::Interpreter;
+
+/// Evaluates `input` on the bytecode VM, printing to stdout. Like
+/// `eval_str_with`, it runs without the prelude.
+pub fn eval_str(input: &str) -> Result<Option<Value>, String> {
+    eval_str_with(input, &Bytecode, &mut stdout()).map_err(|e| e.to_string())
+}
+
+/// Evaluates `input` in a fresh context that prints to `out`. The prelude
+/// isn't loaded, so only builtins and the standard library are there to
+/// call; to have it, `prelude::load` it into a context for `eval_str_in`.
+pub fn eval_str_with(
+    input: &str,
+    evaluator: &dyn Evaluator,
+    out: &mut dyn Write,
+) -> Result<Option<Value>, Error> {
+    eval_str_in(input, evaluator, &mut Context::new(Box::new(out)))
+}
+
+/// Parses `input` and evaluates it against an existing context, keeping its
+/// variables and limits. A program with any syntax error isn't run at all:
+/// the errors are printed and `Error::Parse` returned.
+pub fn eval_str_in(
+    input: &str,
+    evaluator: &dyn Evaluator,
+    ctx: &mut Context,
+) -> Result<Option<Value>, Error> {
+    match parse_str_strict(input) {
+        Ok(ast) => evaluator
+            .eval(ast, ctx)
+            .map_err(|error| error.called_from(ctx.frame("<main>"))),
+        Err(errors) => {
+            for error in errors {
+                println!("{}", error);
+            }
+            Err(Error::Parse)
+        }
     }
 }
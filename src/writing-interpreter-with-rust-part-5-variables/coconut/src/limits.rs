@@ -0,0 +1,133 @@
+use std::time::Instant;
+
+use crate::error::{Error, Resource};
+
+/// How deeply calls nest when `Limits::max_call_depth` is unset, to stop
+/// runaway recursion with an error. Deeper limits are safe too: the VM keeps
+/// its own call frames and the tree walker grows its native stack.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 256;
+
+/// Bounds on how much work a program may do, for running untrusted scripts.
+/// The default is unlimited, except for the call depth.
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    /// Instructions left to execute. `None` means no budget.
+    pub fuel: Option<u64>,
+    /// Point in time after which execution stops.
+    pub deadline: Option<Instant>,
+    /// Maximum depth of the bytecode VM's value stack.
+    pub max_stack: Option<usize>,
+    /// Maximum number of nested function calls, `DEFAULT_MAX_CALL_DEPTH`
+    /// if unset.
+    pub max_call_depth: Option<usize>,
+    /// Maximum number of live variables in `Scope`.
+    pub max_vars: Option<usize>,
+}
+
+impl Limits {
+    /// Charges one unit of fuel and checks the deadline. Called by the
+    /// backends once per executed instruction.
+    pub fn tick(&mut self) -> Result<(), Error> {
+        self.spend(1)
+    }
+
+    /// Charges `units` of fuel at once, for work that grows with its
+    /// operands, and checks the deadline. Runs out of fuel without spending
+    /// any if fewer than `units` are left.
+    pub fn spend(&mut self, units: u64) -> Result<(), Error> {
+        if let Some(fuel) = self.fuel {
+            if fuel < units {
+                return Err(Error::OutOfFuel);
+            }
+            self.fuel = Some(fuel - units);
+        }
+        if let Some(deadline) = self.deadline {
+            if Instant::now() >= deadline {
+                return Err(Error::DeadlineExceeded);
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails with `Error::ResourceExhausted` if `used` exceeds the cap for
+    /// `resource`.
+    pub fn check(&self, resource: Resource, used: usize) -> Result<(), Error> {
+        let limit = match resource {
+            Resource::Stack => self.max_stack,
+            Resource::CallDepth => self.max_call_depth.or(Some(DEFAULT_MAX_CALL_DEPTH)),
+            Resource::Variables => self.max_vars,
+        };
+        match limit {
+            Some(limit) if used > limit => Err(Error::ResourceExhausted { resource, limit }),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod limits_tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn tick_consumes_fuel() {
+        let mut limits = Limits {
+            fuel: Some(2),
+            ..Limits::default()
+        };
+        assert_eq!(limits.tick(), Ok(()));
+        assert_eq!(limits.tick(), Ok(()));
+        assert_eq!(limits.fuel, Some(0));
+        assert_eq!(limits.tick(), Err(Error::OutOfFuel));
+    }
+
+    #[test]
+    fn spend_charges_all_or_nothing() {
+        let mut limits = Limits {
+            fuel: Some(10),
+            ..Limits::default()
+        };
+        assert_eq!(limits.spend(4), Ok(()));
+        assert_eq!(limits.spend(7), Err(Error::OutOfFuel));
+        assert_eq!(limits.fuel, Some(6));
+    }
+
+    #[test]
+    fn tick_checks_deadline() {
+        let mut limits = Limits {
+            deadline: Some(Instant::now()),
+            ..Limits::default()
+        };
+        assert_eq!(limits.tick(), Err(Error::DeadlineExceeded));
+        limits.deadline = Some(Instant::now() + Duration::from_secs(60));
+        assert_eq!(limits.tick(), Ok(()));
+    }
+
+    #[test]
+    fn only_call_depth_is_limited_by_default() {
+        let mut limits = Limits::default();
+        for _ in 0..1000 {
+            assert_eq!(limits.tick(), Ok(()));
+        }
+        assert_eq!(limits.check(Resource::Stack, usize::MAX), Ok(()));
+        assert!(limits
+            .check(Resource::CallDepth, DEFAULT_MAX_CALL_DEPTH + 1)
+            .is_err());
+    }
+
+    #[test]
+    fn check_caps_resources() {
+        let limits = Limits {
+            max_vars: Some(2),
+            ..Limits::default()
+        };
+        assert_eq!(limits.check(Resource::Variables, 2), Ok(()));
+        assert_eq!(
+            limits.check(Resource::Variables, 3),
+            Err(Error::ResourceExhausted {
+                resource: Resource::Variables,
+                limit: 2
+            })
+        );
+    }
+}
@@ -1,54 +1,157 @@
 use std::{
     env, fs,
     io::{stdin, stdout, Write},
+    process,
+    str::FromStr,
+    time::{Duration, Instant},
 };
 
-use coconut::eval_str;
+use coconut::{
+    context::Context,
+    differential::differential_with_input,
+    eval_str_in,
+    evaluator::{Bytecode, Evaluator, TreeWalk},
+    input::Stdin,
+    limits::Limits,
+    prelude,
+};
+
+#[cfg(test)]
+use coconut::{eval_str, eval_str_with, value::Value};
+
+/// How the CLI runs programs: on a single backend or on all of them at once.
+/// Differential runs replay the lines one backend's `readln()` took from
+/// stdin to the other.
+enum Mode {
+    Backend(&'static dyn Evaluator),
+    Differential,
+}
+
+/// Settings taken from the command line flags.
+struct Options {
+    mode: Mode,
+    limits: Limits,
+    timeout: Option<Duration>,
+    prelude: bool,
+}
+
+impl Options {
+    /// The limits to run a program under, with its deadline counted from
+    /// now.
+    fn limits(&self) -> Limits {
+        let mut limits = self.limits.clone();
+        if let Some(timeout) = self.timeout {
+            limits.deadline = Some(Instant::now() + timeout);
+        }
+        limits
+    }
+}
 
 fn main() {
     println!("Writing Interpreter With Rust Part 5");
-    let args: Vec<String> = env::args().collect();
-    if args.len() > 1 {
-        if args[1].ends_with(".cnt".clone()) {
-            eval_file(args[1].clone())
+    let mut options = Options {
+        mode: Mode::Backend(&Bytecode),
+        limits: Limits::default(),
+        timeout: None,
+        prelude: true,
+    };
+    let mut args = vec![];
+    for arg in env::args().skip(1) {
+        match arg.split_once('=') {
+            Some(("--fuel", v)) => options.limits.fuel = Some(flag_value(&arg, v)),
+            Some(("--timeout-ms", v)) => {
+                options.timeout = Some(Duration::from_millis(flag_value(&arg, v)))
+            }
+            Some(("--max-stack", v)) => options.limits.max_stack = Some(flag_value(&arg, v)),
+            Some(("--max-call-depth", v)) => {
+                options.limits.max_call_depth = Some(flag_value(&arg, v))
+            }
+            Some(("--max-vars", v)) => options.limits.max_vars = Some(flag_value(&arg, v)),
+            _ => match arg.as_str() {
+                "--tree-walk" => options.mode = Mode::Backend(&TreeWalk),
+                "--diff" => options.mode = Mode::Differential,
+                "--no-prelude" => options.prelude = false,
+                _ => args.push(arg),
+            },
+        }
+    }
+    if let Some(arg) = args.first() {
+        if arg.ends_with(".cnt") {
+            eval_file(arg.clone(), &options)
         } else {
-            eval(&args[1])
+            eval(arg, "<input>", &options)
         }
     } else {
-        repl()
+        repl(&options)
     }
 }
 
-fn eval_file(file_name: String) {
-    match fs::read_to_string(file_name) {
+fn flag_value<T: FromStr>(flag: &str, value: &str) -> T {
+    value.parse().unwrap_or_else(|_| {
+        eprintln!("Invalid value in {}", flag);
+        process::exit(1)
+    })
+}
+
+fn eval_file(file_name: String, options: &Options) {
+    match fs::read_to_string(&file_name) {
         Ok(content) => {
-            eval(&content);
+            eval(&content, &file_name, options);
         }
         Err(e) => eprintln!("Unable to evaluate expression, {}", e),
     }
 }
 
-fn repl() {
+fn repl(options: &Options) {
     loop {
         print!("> ");
         stdout().flush().unwrap();
-        match stdin().lines().next() {
-            Some(Ok(input)) => {
-                if input.trim() == "exit" {
-                    break;
-                }
-                if input.trim().is_empty() {
-                    continue;
-                }
-                eval(&input);
+        // Bound first, so that stdin is unlocked again for `readln()`.
+        let line = stdin().lines().next();
+        if let Some(Ok(input)) = line {
+            if input.trim() == "exit" {
+                break;
             }
-            _ => {}
+            if input.trim().is_empty() {
+                continue;
+            }
+            eval(&input, "<input>", options);
         }
     }
 }
 
-fn eval(input: &String) {
-    match eval_str(input) {
+/// Runs `input`, naming it `source` in stack traces.
+fn eval(input: &str, source: &str, options: &Options) {
+    let result = match options.mode {
+        Mode::Backend(evaluator) => {
+            let mut ctx = Context::default();
+            ctx.source = source.into();
+            ctx.limits = options.limits();
+            match options.prelude {
+                true => prelude::load(&mut ctx, evaluator),
+                false => Ok(()),
+            }
+            .and_then(|()| eval_str_in(input, evaluator, &mut ctx))
+        }
+        Mode::Differential => {
+            match differential_with_input(
+                input,
+                source,
+                &mut Stdin,
+                options.prelude,
+                &options.limits(),
+                &Bytecode,
+                &TreeWalk,
+            ) {
+                Ok(outcome) => {
+                    print!("{}", outcome.output);
+                    outcome.result
+                }
+                Err(divergence) => Err(divergence.to_string().into()),
+            }
+        }
+    };
+    match result {
         Ok(Some(result)) => {
             println!("{}", result);
         }
@@ -58,10 +161,11 @@ fn eval(input: &String) {
 }
 
 #[test]
+#[allow(clippy::unnecessary_to_owned)]
 fn test_comments() {
     assert_eq!(
         eval_str(&"// 2+2\n 1+1".to_string()).unwrap(),
-        Some(2),
+        Some(Value::Int(2)),
         "expected 1+1=2"
     );
     assert_eq!(
@@ -72,44 +176,109 @@ fn test_comments() {
 }
 
 #[cfg(test)]
+#[allow(clippy::unnecessary_to_owned)]
 mod main_tests {
     use super::*;
     #[test]
     fn math_expressions() {
         assert_eq!(
             eval_str(&"0+1*1*1".to_string()).unwrap(),
-            Some(1),
+            Some(Value::Int(1)),
             "expected 0+1*1*1"
         );
         assert_eq!(
             eval_str(&"1+1".to_string()).unwrap(),
-            Some(2),
+            Some(Value::Int(2)),
             "expected 1+1=2"
         );
         assert_eq!(
             eval_str(&"1*(1+2)".to_string()).unwrap(),
-            Some(3),
+            Some(Value::Int(3)),
             "expected 1*(1+2)=3"
         );
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::unnecessary_to_owned)]
 mod var_tests {
     use super::*;
     #[test]
     fn vars_declare_match() {
         assert_eq!(
             eval_str(&"let x = 1; let y = 2; y + x;".to_string()).unwrap(),
-            Some(3)
+            Some(Value::Int(3))
         );
     }
     #[test]
     fn vars_reassign_math() {
         assert_eq!(
             eval_str(&"let x = 1; let y = 2; x = 3; x + y;".to_string()).unwrap(),
-            Some(5)
+            Some(Value::Int(5))
+        );
+    }
+
+    #[test]
+    fn vars_reassign_println() {
+        let mut out = vec![];
+        assert_eq!(
+            eval_str_with(
+                "let a = 1; let b = 2; a = 3; println(a+b);",
+                &Bytecode,
+                &mut out
+            ),
+            Ok(None)
         );
+        assert_eq!(String::from_utf8(out).unwrap(), "5\n");
+    }
+
+    #[test]
+    fn vars_declared_first_are_assigned_in_branches() {
+        let program = "
+            fn sign(n) {
+                let s;
+                match n == 0 {
+                    true => { s = \"zero\"; },
+                    false => { s = \"positive\"; }
+                };
+                s
+            }
+            [sign(0), sign(3)];
+        ";
+        for evaluator in [&Bytecode as &dyn Evaluator, &TreeWalk] {
+            assert_eq!(
+                eval_str_with(program, evaluator, &mut vec![])
+                    .unwrap()
+                    .unwrap()
+                    .to_string(),
+                "[\"zero\", \"positive\"]"
+            );
+        }
+        assert_eq!(
+            eval_str("let s;\nmatch 1 {\n  1 => { s = 1; },\n  _ => nil\n};\ns + 1;"),
+            Err("6:1: Cannot read 's' before it is assigned".to_string())
+        );
+    }
+
+    #[test]
+    fn vars_chained_assignment() {
+        let program = "
+            let x = y = 3;
+            let xs = [0];
+            println(xs[0] = (x += 1) * 2);
+            [x, y, xs];
+        ";
+        for evaluator in [&Bytecode as &dyn Evaluator, &TreeWalk] {
+            let mut out = vec![];
+            assert_eq!(
+                eval_str_with(program, evaluator, &mut out)
+                    .unwrap()
+                    .unwrap()
+                    .to_string(),
+                "[4, 3, [8]]"
+            );
+            assert_eq!(String::from_utf8(out).unwrap(), "8\n");
+        }
     }
 
     #[test]
@@ -119,4 +288,1289 @@ mod var_tests {
             Err("Variable 'a' not found".to_string())
         );
     }
+
+    #[test]
+    fn programs_with_syntax_errors_are_not_run() {
+        for program in ["let ok = ; println(ok == true);", "println(1); int(-1.5);"] {
+            for evaluator in [&Bytecode as &dyn Evaluator, &TreeWalk] {
+                let mut out = vec![];
+                assert_eq!(
+                    eval_str_with(program, evaluator, &mut out),
+                    Err(coconut::error::Error::Parse),
+                    "{}",
+                    program
+                );
+                assert!(out.is_empty(), "{}", program);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod float_tests {
+    use super::*;
+
+    #[test]
+    fn floats_parse() {
+        assert_eq!(eval_str("1.5;").unwrap(), Some(Value::Float(1.5)));
+        assert_eq!(eval_str("2e10;").unwrap(), Some(Value::Float(2e10)));
+        assert_eq!(eval_str("2.5E-1;").unwrap(), Some(Value::Float(0.25)));
+        assert_eq!(
+            eval_str("1e999;"),
+            Err("Unable to parse input.".to_string())
+        );
+    }
+
+    #[test]
+    fn floats_mix_with_ints() {
+        assert_eq!(
+            eval_str("let total = 1 + 2 + 4; total / 2;").unwrap(),
+            Some(Value::Int(3))
+        );
+        assert_eq!(
+            eval_str("let total = 1 + 2 + 4; float(total) / 2;").unwrap(),
+            Some(Value::Float(3.5))
+        );
+        assert_eq!(
+            eval_str("let miles = 26.2; int(miles * 1.609);").unwrap(),
+            Some(Value::Int(42))
+        );
+    }
+
+    #[test]
+    fn comparisons_and_division_errors() {
+        assert_eq!(eval_str("1 < 1.5;").unwrap(), Some(Value::Bool(true)));
+        assert_eq!(eval_str("2 * 3 >= 7;").unwrap(), Some(Value::Bool(false)));
+        assert_eq!(eval_str("10 - 3 % 2;").unwrap(), Some(Value::Int(9)));
+        assert_eq!(eval_str("1 / 0;"), Err("Division by zero".to_string()));
+    }
+}
+
+#[cfg(all(test, feature = "bigint"))]
+mod bigint_tests {
+    use super::*;
+
+    #[test]
+    fn big_literals() {
+        assert_eq!(
+            eval_str("123456789012345678901234567890 + 1;")
+                .unwrap()
+                .map(|v| v.to_string()),
+            Some("123456789012345678901234567891".to_string())
+        );
+    }
+
+    #[test]
+    fn factorial_goes_past_20() {
+        let product = (1..=25)
+            .map(|n| n.to_string())
+            .collect::<Vec<_>>()
+            .join(" * ");
+        assert_eq!(
+            eval_str(&format!("{};", product))
+                .unwrap()
+                .map(|v| v.to_string()),
+            Some("15511210043330985984000000".to_string())
+        );
+    }
+
+    #[test]
+    fn big_values_compare_and_shrink() {
+        assert_eq!(
+            eval_str("18446744073709551616 > 18446744073709551615;").unwrap(),
+            Some(Value::Bool(true))
+        );
+        assert_eq!(
+            eval_str("18446744073709551616 - 1;").unwrap(),
+            Some(Value::Int(u64::MAX))
+        );
+        assert_eq!(
+            eval_str("parse_int(\"99999999999999999999\") == 99999999999999999999;").unwrap(),
+            Some(Value::Bool(true))
+        );
+    }
+
+    #[test]
+    fn big_arithmetic_costs_fuel_by_size() {
+        let limits = Limits {
+            fuel: Some(100000),
+            ..Limits::default()
+        };
+        for source in [
+            "let x = 3; for i in 0..16 { x *= x; } 0;",
+            "let x = 3; for i in 0..16 { x = x * x; } 0;",
+            "pow(3, 65536); 0;",
+        ] {
+            let outcome = differential_with_input(
+                source,
+                "<input>",
+                &mut "".as_bytes(),
+                false,
+                &limits,
+                &Bytecode,
+                &TreeWalk,
+            )
+            .unwrap();
+            assert_eq!(
+                outcome.result,
+                Err(coconut::error::Error::OutOfFuel),
+                "{}",
+                source
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod array_tests {
+    use super::*;
+    use coconut::error::Error;
+
+    #[test]
+    fn arrays_index_and_assign() {
+        assert_eq!(
+            eval_str("let a = [10, 20, 30]; a[1] = a[0] + a[2]; a[1];").unwrap(),
+            Some(Value::Int(40))
+        );
+        assert_eq!(
+            eval_str("let grid = [[1, 2], [3, 4]]; grid[1][0];").unwrap(),
+            Some(Value::Int(3))
+        );
+    }
+
+    #[test]
+    fn arrays_push_pop_and_len() {
+        let mut out = vec![];
+        assert_eq!(
+            eval_str_with(
+                "let a = []; let b = a; push(a, 1); push(b, \"x\"); println(a); println(len(a)); pop(a);",
+                &Bytecode,
+                &mut out
+            ),
+            Ok(Some(Value::from("x")))
+        );
+        assert_eq!(String::from_utf8(out).unwrap(), "[1, \"x\"]\n2\n");
+    }
+
+    #[test]
+    fn arrays_report_out_of_bounds() {
+        assert_eq!(
+            eval_str_with("let a = [1, 2]; a[2] = 3;", &TreeWalk, &mut vec![]),
+            Err(Error::IndexOutOfBounds { index: 2, len: 2 })
+        );
+        assert_eq!(
+            eval_str("[1, 2][5];"),
+            Err("Index 5 out of bounds for length 2".to_string())
+        );
+    }
+}
+
+#[cfg(test)]
+mod map_tests {
+    use super::*;
+
+    #[test]
+    fn maps_count_words() {
+        let mut out = vec![];
+        let program = "
+            let counts = {};
+            let words = [\"a\", \"b\", \"a\"];
+            counts[words[0]] = get(counts, words[0], 0) + 1;
+            counts[words[1]] = get(counts, words[1], 0) + 1;
+            counts[words[2]] = get(counts, words[2], 0) + 1;
+            println(counts);
+            println(keys(counts));
+            len(counts);
+        ";
+        assert_eq!(
+            eval_str_with(program, &TreeWalk, &mut out),
+            Ok(Some(Value::Int(2)))
+        );
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"a\": 2, \"b\": 1}\n[\"a\", \"b\"]\n"
+        );
+    }
+
+    #[test]
+    fn maps_test_membership() {
+        assert_eq!(
+            eval_str("let m = {\"k\": 1}; \"k\" in m == true;").unwrap(),
+            Some(Value::Bool(true))
+        );
+        assert_eq!(
+            eval_str("let m = {\"k\": 1}; \"v\" in m;").unwrap(),
+            Some(Value::Bool(false))
+        );
+    }
+
+    #[test]
+    fn maps_report_missing_keys() {
+        assert_eq!(
+            eval_str("let m = {\"k\": 1}; m[\"v\"];"),
+            Err("Key \"v\" not found".to_string())
+        );
+        assert_eq!(
+            eval_str("let m = {[1]: 1};"),
+            Err("Cannot use array as a map key".to_string())
+        );
+    }
+}
+
+#[cfg(test)]
+mod string_tests {
+    use super::*;
+    use coconut::parser::parse_str_strict;
+
+    #[test]
+    fn strings_concatenate() {
+        assert_eq!(
+            eval_str("let a = \"foo\"; a + \"bar\";").unwrap(),
+            Some(Value::from("foobar"))
+        );
+    }
+
+    #[test]
+    fn strings_unescape() {
+        let mut out = vec![];
+        assert_eq!(
+            eval_str_with(r#"println("a\tb\n\"c\"\\");"#, &Bytecode, &mut out),
+            Ok(None)
+        );
+        assert_eq!(String::from_utf8(out).unwrap(), "a\tb\n\"c\"\\\n");
+    }
+
+    #[test]
+    fn strings_compare() {
+        assert_eq!(
+            eval_str("\"a\" + \"b\" == \"ab\";").unwrap(),
+            Some(Value::Bool(true))
+        );
+        assert_eq!(eval_str("\"1\" != 1;").unwrap(), Some(Value::Bool(true)));
+    }
+
+    #[test]
+    fn strings_interpolate() {
+        assert_eq!(
+            eval_str("let x = 2; \"x = ${x}, next = ${x + 1}!\";").unwrap(),
+            Some(Value::from("x = 2, next = 3!"))
+        );
+        assert_eq!(
+            eval_str("let s = \"a\"; \"${s}${s == \"a\"} ${ {\"k\": 1}[\"k\"] }\";").unwrap(),
+            Some(Value::from("atrue 1"))
+        );
+        assert_eq!(eval_str("\"\\${x}\";").unwrap(), Some(Value::from("${x}")));
+        assert_eq!(eval_str("\"$5\";").unwrap(), Some(Value::from("$5")));
+    }
+
+    #[test]
+    fn strings_interpolate_expressions_only() {
+        for program in [
+            "\"${x = 3}\";",
+            "\"${println(1)}\";",
+            "\"${1\";",
+            "\"\\q\";",
+        ] {
+            assert!(parse_str_strict(program).is_err(), "{}", program);
+        }
+        for evaluator in [&Bytecode as &dyn Evaluator, &TreeWalk] {
+            assert_eq!(
+                eval_str_with("fn str(x) { 42 } \"v=${1}\";", evaluator, &mut vec![]),
+                Ok(Some(Value::from("v=1")))
+            );
+        }
+    }
+
+    #[test]
+    fn string_concatenation_costs_fuel_by_length() {
+        let limits = Limits {
+            fuel: Some(100000),
+            ..Limits::default()
+        };
+        let source = "let s = \"ab\"; for i in 0..32 { s = s + s; } 0;";
+        let outcome = differential_with_input(
+            source,
+            "<input>",
+            &mut "".as_bytes(),
+            false,
+            &limits,
+            &Bytecode,
+            &TreeWalk,
+        )
+        .unwrap();
+        assert_eq!(outcome.result, Err(coconut::error::Error::OutOfFuel));
+    }
+
+    #[test]
+    fn strings_format() {
+        assert_eq!(
+            eval_str("format(\"{} + {} = {:03}\", 1, 2, 3);").unwrap(),
+            Some(Value::from("1 + 2 = 003"))
+        );
+        assert_eq!(
+            eval_str("format(\"{}\");"),
+            Err("format: missing argument for placeholder 1".to_string())
+        );
+    }
+
+    #[test]
+    fn strings_do_not_mix_with_ints() {
+        assert_eq!(
+            eval_str("\"a\" * 2;"),
+            Err("Cannot multiply string and int".to_string())
+        );
+    }
+}
+
+#[cfg(test)]
+mod function_tests {
+    use super::*;
+    use coconut::{
+        error::{Error, Resource},
+        evaluator::Evaluator,
+    };
+
+    #[test]
+    fn closures_keep_captured_state() {
+        let program = "
+            fn counter() {
+                let n = 0;
+                fn() { n = n + 1; n }
+            }
+            let a = counter();
+            let b = counter();
+            a(); a(); b();
+            [a(), b()];
+        ";
+        for evaluator in [&Bytecode as &dyn Evaluator, &TreeWalk] {
+            assert_eq!(
+                eval_str_with(program, evaluator, &mut vec![]),
+                Ok(Some(Value::array(vec![3.into(), 2.into()])))
+            );
+        }
+    }
+
+    #[test]
+    fn functions_are_values() {
+        assert_eq!(
+            eval_str("fn twice(f, x) { f(f(x)) } twice(fn(x) { x * 3 }, 2);").unwrap(),
+            Some(Value::Int(18))
+        );
+        assert_eq!(
+            eval_str("let add = fn(a) { fn(b) { a + b } }; add(2)(40);").unwrap(),
+            Some(Value::Int(42))
+        );
+        let mut out = vec![];
+        eval_str_with("fn f() {} println(f); println(f());", &Bytecode, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "<fn f>\nnil\n");
+    }
+
+    #[test]
+    fn functions_recurse() {
+        let program = "
+            let stop = {0: fn(n) { 1 }};
+            fn fact(n) { get(stop, n, fn(n) { n * fact(n - 1) })(n) }
+            fact(10);
+        ";
+        assert_eq!(eval_str(program).unwrap(), Some(Value::Int(3628800)));
+    }
+
+    #[test]
+    fn runaway_recursion_is_capped() {
+        let mut ctx = Context::new(Box::new(vec![]));
+        ctx.limits.max_call_depth = Some(10);
+        assert_eq!(
+            eval_str_in("fn f() { f() } f();", &TreeWalk, &mut ctx),
+            Err(Error::ResourceExhausted {
+                resource: Resource::CallDepth,
+                limit: 10
+            })
+        );
+    }
+
+    #[test]
+    fn calls_check_callee_and_arity() {
+        assert_eq!(
+            eval_str("fn f(a, b) { a } f(1);"),
+            Err("Function 'f' expects 2 argument(s), got 1".to_string())
+        );
+        assert_eq!(
+            eval_str("let x = 1; x();"),
+            Err("Cannot call int".to_string())
+        );
+        assert_eq!(eval_str("[1](0);"), Err("Cannot call array".to_string()));
+    }
+}
+
+#[cfg(test)]
+mod loop_tests {
+    use super::*;
+
+    #[test]
+    fn for_sums_a_range() {
+        assert_eq!(
+            eval_str("let sum = 0; for i in 1..11 { sum = sum + i; } sum;").unwrap(),
+            Some(Value::Int(55))
+        );
+        assert_eq!(
+            eval_str("let n = 0; for i in 5..5 { n = n + 1; } n;").unwrap(),
+            Some(Value::Int(0))
+        );
+    }
+
+    #[test]
+    fn for_walks_collections() {
+        let mut out = vec![];
+        let program = "
+            for x in [1, \"two\"] { println(x); }
+            for c in \"ab\" { println(c); }
+            for k in {\"y\": 1, \"x\": 2} { println(k); }
+        ";
+        assert_eq!(eval_str_with(program, &TreeWalk, &mut out), Ok(None));
+        assert_eq!(String::from_utf8(out).unwrap(), "1\ntwo\na\nb\nx\ny\n");
+    }
+
+    #[test]
+    fn loop_variables_are_fresh_each_iteration() {
+        let program = "
+            let fs = [];
+            for i in 0..3 { push(fs, fn() { i }); }
+            [fs[0](), fs[2]()];
+        ";
+        assert_eq!(
+            eval_str(program).unwrap(),
+            Some(Value::array(vec![0.into(), 2.into()]))
+        );
+        assert_eq!(
+            eval_str("for i in 0..1 { let j = i; } j;"),
+            Err("Variable 'j' not found".to_string())
+        );
+    }
+
+    #[test]
+    fn return_leaves_loops() {
+        let program = "
+            fn find(xs, wanted) { for x in xs { for hit in [x == wanted] { return hit; } } }
+            [find([1, 2], 2), find([], 2)];
+        ";
+        assert_eq!(
+            eval_str(program).unwrap(),
+            Some(Value::array(vec![Value::Bool(false), Value::Nil]))
+        );
+        assert_eq!(
+            eval_str("for x in 1 { }"),
+            Err("Cannot iterate over int".to_string())
+        );
+    }
+}
+
+#[cfg(test)]
+mod struct_tests {
+    use super::*;
+    use coconut::{ast::Span, error::Error};
+
+    #[test]
+    fn structs_build_read_and_write_fields() {
+        let program = "
+            struct Point { x, y }
+            let p = Point { y: 2, x: 1 };
+            p.x = p.x + 10;
+            println(p);
+            p.x * p.y;
+        ";
+        let mut out = vec![];
+        assert_eq!(
+            eval_str_with(program, &TreeWalk, &mut out),
+            Ok(Some(Value::Int(22)))
+        );
+        assert_eq!(String::from_utf8(out).unwrap(), "Point { x: 11, y: 2 }\n");
+    }
+
+    #[test]
+    fn structs_are_shared_like_arrays() {
+        let program = "
+            struct Counter { n }
+            fn bump(c) { c.n = c.n + 1; }
+            let c = Counter { n: 0 };
+            bump(c);
+            bump(c);
+            c.n;
+        ";
+        assert_eq!(eval_str(program).unwrap(), Some(Value::Int(2)));
+    }
+
+    #[test]
+    fn unknown_fields_point_at_the_source() {
+        assert_eq!(
+            eval_str_with(
+                "struct P { x }\nlet p = P { x: 1 };\np.y;",
+                &Bytecode,
+                &mut vec![]
+            ),
+            Err(Error::UnknownField {
+                ty: "P".to_string(),
+                field: "y".to_string(),
+                span: Span { line: 3, column: 3 }
+            })
+        );
+        assert_eq!(
+            eval_str("struct P { x } println(1); P { x: 1, y: 2 };"),
+            Err("1:38: P has no field 'y'".to_string())
+        );
+        assert_eq!(
+            eval_str("let n = 1; n.x;"),
+            Err("Cannot access field 'x' of int".to_string())
+        );
+    }
+
+    #[test]
+    fn loops_iterate_over_struct_fields() {
+        let program = "
+            struct Bag { items }
+            let bag = Bag { items: [1, 2, 3] };
+            let sum = 0;
+            for x in bag.items { sum = sum + x; }
+            sum;
+        ";
+        assert_eq!(eval_str(program).unwrap(), Some(Value::Int(6)));
+    }
+}
+
+#[cfg(test)]
+mod enum_tests {
+    use super::*;
+
+    #[test]
+    fn match_picks_the_first_matching_arm() {
+        let program = "
+            enum Shape { Circle(r), Rect(w, h), Empty }
+            fn area(s) {
+                match s {
+                    Shape::Circle(r) => 3 * r * r,
+                    Shape::Rect(w, 1) => { println(\"thin\"); w },
+                    Shape::Rect(w, h) => w * h,
+                    Shape::Empty => 0,
+                }
+            }
+            println(Shape::Rect(2, \"x\"));
+            area(Shape::Circle(2)) + area(Shape::Rect(5, 1)) + area(Shape::Rect(2, 3));
+        ";
+        for evaluator in [&Bytecode as &dyn Evaluator, &TreeWalk] {
+            let mut out = vec![];
+            assert_eq!(
+                eval_str_with(program, evaluator, &mut out),
+                Ok(Some(Value::Int(23)))
+            );
+            assert_eq!(
+                String::from_utf8(out).unwrap(),
+                "Shape::Rect(2, \"x\")\nthin\n"
+            );
+        }
+    }
+
+    #[test]
+    fn bindings_are_scoped_to_their_arm() {
+        let program = "
+            let x = 1;
+            let y = match [x] { x => x };
+            [x, y];
+        ";
+        assert_eq!(eval_str(program).unwrap().unwrap().to_string(), "[1, [1]]");
+    }
+
+    #[test]
+    fn non_exhaustive_matches_are_rejected_before_running() {
+        assert_eq!(
+            eval_str("enum O { S(v), N }\nprintln(1);\nmatch O::N { O::S(true) => 1, O::N => 2 };"),
+            Err("3:1: Match does not cover O::S(false)".to_string())
+        );
+        assert_eq!(
+            eval_str("match 1 { 1 => 2 };"),
+            Err("1:1: Match does not cover _".to_string())
+        );
+        assert_eq!(
+            eval_str("enum O { S(v) } O::S(1, 2);"),
+            Err("1:17: O::S takes 1 value(s), got 2".to_string())
+        );
+    }
+
+    #[test]
+    fn values_of_other_types_match_no_variant() {
+        assert_eq!(
+            eval_str("enum O { S(v), N } match 3 { O::S(_) => 1, O::N => 2 };"),
+            Err("No match arm for 3".to_string())
+        );
+    }
+}
+
+#[cfg(test)]
+mod exception_tests {
+    use super::*;
+    use coconut::error::{Error, Frame};
+
+    fn frame(function: &str, line: usize) -> Frame {
+        Frame {
+            function: function.to_string(),
+            source: "<input>".into(),
+            line,
+        }
+    }
+
+    #[test]
+    fn catch_binds_the_thrown_value() {
+        let program = "
+            let caught = nil;
+            try {
+                let a = [1];
+                throw {\"code\": len(a)};
+                println(\"unreachable\");
+            } catch (e) {
+                caught = e;
+            }
+            caught[\"code\"];
+        ";
+        assert_eq!(eval_str(program).unwrap(), Some(Value::Int(1)));
+    }
+
+    #[test]
+    fn runtime_errors_are_caught_as_their_message() {
+        let program = "
+            fn f(x) { 10 / x }
+            let messages = [];
+            for x in [1, 0, \"a\"] {
+                try { push(messages, f(x)); } catch (e) { push(messages, e); }
+            }
+            messages;
+        ";
+        for evaluator in [&Bytecode as &dyn Evaluator, &TreeWalk] {
+            assert_eq!(
+                eval_str_with(program, evaluator, &mut vec![])
+                    .unwrap()
+                    .unwrap()
+                    .to_string(),
+                "[10, \"Division by zero\", \"Cannot divide int and string\"]"
+            );
+        }
+    }
+
+    #[test]
+    fn uncaught_exceptions_carry_a_stack_trace() {
+        let program = "
+            fn inner() { throw \"boom\"; }
+            fn outer() { let g = fn() { inner() }; g() }
+            outer();
+        ";
+        for evaluator in [&Bytecode as &dyn Evaluator, &TreeWalk] {
+            assert_eq!(
+                eval_str_with(program, evaluator, &mut vec![]),
+                Err(Error::Traced {
+                    error: Box::new(Error::Thrown {
+                        value: Value::Str("boom".to_string()),
+                    }),
+                    trace: vec![
+                        frame("inner", 2),
+                        frame("fn", 3),
+                        frame("outer", 3),
+                        frame("<main>", 4),
+                    ],
+                })
+            );
+        }
+        assert_eq!(
+            eval_str(program),
+            Err(
+                "Uncaught exception: boom\n  in inner at <input>:2\n  in fn at <input>:3\n  \
+                 in outer at <input>:3\n  in <main> at <input>:4"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn runtime_errors_carry_the_line_of_each_frame() {
+        let program = "
+            fn divide(a, b) {
+                a / b
+            }
+            fn average(xs) {
+                let total = 0;
+                for x in xs { total = total + x; }
+                let count = match len(xs) { 2 => { 0 }, _ => len(xs) };
+                divide(total, count)
+            }
+            average([1, 2]);
+        ";
+        for evaluator in [&Bytecode as &dyn Evaluator, &TreeWalk] {
+            assert_eq!(
+                eval_str_with(program, evaluator, &mut vec![]),
+                Err(Error::Traced {
+                    error: Box::new(Error::Runtime("Division by zero".to_string())),
+                    trace: vec![frame("divide", 3), frame("average", 9), frame("<main>", 11),],
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn errors_outside_functions_are_not_traced() {
+        for evaluator in [&Bytecode as &dyn Evaluator, &TreeWalk] {
+            assert_eq!(
+                eval_str_with("let x = 1;\nx / 0;", evaluator, &mut vec![]),
+                Err(Error::Runtime("Division by zero".to_string()))
+            );
+            assert_eq!(
+                eval_str_with(
+                    "fn f() { 1 / 0 }\nlet m = nil;\ntry { f(); } catch (e) { m = e; }\nm",
+                    evaluator,
+                    &mut vec![]
+                ),
+                Ok(Some(Value::Str("Division by zero".to_string())))
+            );
+        }
+    }
+
+    #[test]
+    fn rethrown_exceptions_reach_the_outer_catch() {
+        let program = "
+            let log = [];
+            try {
+                try { throw 1; } catch (e) { push(log, e); throw e + 1; }
+            } catch (e) {
+                push(log, e);
+            }
+            log;
+        ";
+        assert_eq!(eval_str(program).unwrap().unwrap().to_string(), "[1, 2]");
+    }
+}
+
+#[cfg(test)]
+mod module_tests {
+    use super::*;
+    use coconut::{differential::Outcome, error::Error, Interpreter};
+    use std::{cell::RefCell, rc::Rc};
+
+    /// Runs `input` as if it were the file `source` on both backends.
+    fn run_as(source: &str, input: &str) -> Vec<Outcome> {
+        let interpreters = [
+            Interpreter::new(),
+            Interpreter::new().with_evaluator(TreeWalk),
+        ];
+        interpreters
+            .into_iter()
+            .map(|interpreter| {
+                let output = Rc::new(RefCell::new(String::new()));
+                let sink = output.clone();
+                let mut interpreter = interpreter
+                    .with_source(source)
+                    .with_output_fn(move |line| sink.borrow_mut().push_str(&format!("{}\n", line)));
+                let result = interpreter.eval(input);
+                let output = output.borrow().clone();
+                Outcome { result, output }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn imported_modules_share_only_their_exports() {
+        let input = fs::read_to_string("prog/modules.cnt").unwrap();
+        for outcome in run_as("prog/modules.cnt", &input) {
+            assert_eq!(outcome.result, Ok(None));
+            assert_eq!(
+                outcome.output,
+                "area: 3.14159, pi here: 3\nmean: 2\nmean area: 7.853975\ncalls: 2\n"
+            );
+        }
+        let private = "import \"lib/stats.cnt\";\nstats.count;";
+        for Outcome { result, .. } in run_as("prog/main.cnt", private) {
+            assert_eq!(
+                result.unwrap_err().to_string(),
+                "2:7: module stats has no field 'count'"
+            );
+        }
+        let assign = "import \"lib/geometry.cnt\";\ngeometry.pi = 3;";
+        for Outcome { result, .. } in run_as("prog/main.cnt", assign) {
+            assert_eq!(
+                result,
+                Err(Error::Runtime(
+                    "Cannot assign to geometry.pi outside of it".to_string()
+                ))
+            );
+        }
+    }
+
+    #[test]
+    fn traces_name_the_file_of_each_frame() {
+        let input = "import \"lib/stats.cnt\";\n\nstats.mean([]);";
+        for Outcome { result, .. } in run_as("prog/main.cnt", input) {
+            assert_eq!(
+                result.unwrap_err().to_string(),
+                "Division by zero\n  in mean at prog/lib/stats.cnt:8\n  in <main> at prog/main.cnt:3"
+            );
+        }
+        let missing = "import \"lib/missing.cnt\";";
+        for Outcome { result, .. } in run_as("prog/main.cnt", missing) {
+            assert!(result
+                .unwrap_err()
+                .to_string()
+                .starts_with("Cannot import prog/lib/missing.cnt: "));
+        }
+    }
+}
+
+#[cfg(test)]
+mod stdlib_tests {
+    use super::*;
+    use coconut::evaluator::Evaluator;
+
+    #[test]
+    fn library_functions_compose_with_closures() {
+        let program = "
+            let xs = map(range(1, 6), fn(x) { pow(x, 2) });
+            let odd = filter(xs, fn(x) { x % 2 == 1 });
+            [sum(xs), reduce(odd, fn(a, b) { max(a, b) }, 0), reverse(sort([3, 1, 2]))];
+        ";
+        for evaluator in [&Bytecode as &dyn Evaluator, &TreeWalk] {
+            assert_eq!(
+                eval_str_with(program, evaluator, &mut vec![])
+                    .unwrap()
+                    .unwrap()
+                    .to_string(),
+                "[55, 25, [3, 2, 1]]"
+            );
+        }
+    }
+
+    #[test]
+    fn variables_shadow_library_functions() {
+        assert_eq!(
+            eval_str("fn abs(x) { 0 } let min = fn(a, b) { 1 }; [abs(5), min(2, 3)];")
+                .unwrap()
+                .unwrap()
+                .to_string(),
+            "[0, 1]"
+        );
+    }
+
+    #[test]
+    fn errors_in_callbacks_are_traced() {
+        let program = "fn f(x) {\n  1 / x\n}\nmap([1, 0], f);";
+        assert_eq!(
+            eval_str(program),
+            Err("Division by zero\n  in f at <input>:2\n  in <main> at <input>:4".to_string())
+        );
+    }
+}
+
+#[cfg(test)]
+mod compound_tests {
+    use super::*;
+
+    #[test]
+    fn compound_assignments_update_variables_elements_and_fields() {
+        let program = "
+            struct Counter { n }
+            let c = Counter { n: 0 };
+            let xs = [1, 2, 3];
+            let total = 0;
+            for i in 0..len(xs) {
+                xs[i] *= 10;
+                total += xs[i];
+                c.n += 1;
+            }
+            total -= 1;
+            [total, xs, c.n];
+        ";
+        for evaluator in [&Bytecode as &dyn Evaluator, &TreeWalk] {
+            assert_eq!(
+                eval_str_with(program, evaluator, &mut vec![])
+                    .unwrap()
+                    .unwrap()
+                    .to_string(),
+                "[59, [10, 20, 30], 3]"
+            );
+        }
+    }
+
+    #[test]
+    fn targets_are_evaluated_once() {
+        let program = "
+            let calls = [];
+            let xs = [[1]];
+            fn target(name) { push(calls, name); xs }
+            fn index(name) { push(calls, name); 0 }
+            target(\"t\")[index(\"i\")][index(\"j\")] += len(calls);
+            [xs, calls];
+        ";
+        for evaluator in [&Bytecode as &dyn Evaluator, &TreeWalk] {
+            assert_eq!(
+                eval_str_with(program, evaluator, &mut vec![])
+                    .unwrap()
+                    .unwrap()
+                    .to_string(),
+                "[[[4]], [\"t\", \"i\", \"j\"]]"
+            );
+        }
+    }
+
+    #[test]
+    fn compound_assignments_are_checked_like_assignments() {
+        assert_eq!(
+            eval_str("const n = 1;\nn += 1;"),
+            Err("2:1: Cannot assign to constant 'n'".to_string())
+        );
+        assert_eq!(
+            eval_str("let n;\nn *= 2;"),
+            Err("2:1: Cannot read 'n' before it is assigned".to_string())
+        );
+        assert_eq!(
+            eval_str("let s = \"a\"; s %= 2;"),
+            Err("Cannot take the remainder of string and int".to_string())
+        );
+    }
+}
+
+#[cfg(test)]
+mod const_tests {
+    use super::*;
+    use coconut::{error::Error, Interpreter};
+
+    #[test]
+    fn assignments_the_compiler_sees_point_at_the_statement() {
+        assert_eq!(
+            eval_str("const x = 1;\nfn f() {\n  let y = 2;\n  x = y;\n}"),
+            Err("4:3: Cannot assign to constant 'x'".to_string())
+        );
+    }
+
+    #[test]
+    fn assignments_it_cannot_see_fail_when_they_run() {
+        let program = "fn f() {\n  x = 2;\n}\nconst x = 1;\nf();";
+        for evaluator in [&Bytecode as &dyn Evaluator, &TreeWalk] {
+            assert_eq!(
+                eval_str_with(program, evaluator, &mut vec![])
+                    .unwrap_err()
+                    .to_string(),
+                "Cannot assign to constant 'x'\n  in f at <input>:2\n  in <main> at <input>:5"
+            );
+        }
+        let mut interpreter = Interpreter::new();
+        interpreter.eval("const x = 1;").unwrap();
+        assert_eq!(
+            interpreter.eval("x = 2;"),
+            Err(Error::Runtime("Cannot assign to constant 'x'".to_string()))
+        );
+        assert_eq!(
+            interpreter.eval("let x = 2;"),
+            Err(Error::Runtime(
+                "Cannot declare constant 'x' again".to_string()
+            ))
+        );
+        assert_eq!(interpreter.eval("x;"), Ok(Some(Value::Int(1))));
+    }
+
+    #[test]
+    fn constants_hold_the_same_value_not_a_frozen_one() {
+        assert_eq!(
+            eval_str("const xs = [1]; push(xs, 2); xs;")
+                .unwrap()
+                .unwrap()
+                .to_string(),
+            "[1, 2]"
+        );
+    }
+}
+
+#[cfg(test)]
+mod diff_tests {
+    use super::*;
+    use coconut::{
+        ast::Node,
+        context::Context,
+        differential::{differential, Outcome},
+        error::Error,
+    };
+
+    const PROGRAMS: &[&str] = &[
+        "0+1*1*1",
+        "1*(1+2)",
+        "// 2+2",
+        "let x = 1; let y = 2; x = 3; x + y;",
+        "let a = 1; println(a + 2); a;",
+        "let x = y = 3; let z = (x = 4) + y; [x, y, z];",
+        "a + 1;",
+        "18446744073709551615 + 1",
+        "4294967296 * 4294967296",
+        "let big = 99999999999999999999 * 99999999999999999999; big / 3 % 1000 < big;",
+        "let x = y = 3;",
+        "println(x = 3);",
+        "let a = 1; foo(a, 2);",
+        "let s = \"ab\" + \"c\"; println(s); len(s);",
+        "\"a\" + 1;",
+        "1 == 1 != false;",
+        "let x = 2; println(\"x = ${x}, x * x = ${x * x}\");",
+        "format(\"{:>4}|{:<3}|\", 1, \"ab\");",
+        "let avg = (1 + 2.5 + 3) / 3; println(avg); int(avg * 100) % 7;",
+        "2e10 - 1 >= float(20000000000) - 1;",
+        "1 - 2;",
+        "0.0 / 0.0;",
+        "struct P { x } let nan = 0.0 / 0.0; [nan, {1: nan}, P { x: nan }];",
+        "fn f() { throw [0.0 / 0.0]; } [f, f()];",
+        "[9007199254740993 == 9007199254740992.0, 18446744073709551615 == 18446744073709551616.0];",
+        "let a = [1, \"two\", [3.0]]; a[1] = a[2]; push(a, len(a)); println(a); pop(a) + a[0];",
+        "let a = []; a[0];",
+        "let a = [1]; a[\"x\"] = 2;",
+        "let a = [1]; push(a, [a]);",
+        "let m = {\"b\": 1, 2: [true]}; m[\"a\"] = m[2]; println(m); println(keys(m)); \"a\" in m;",
+        "let m = {}; get(m, \"x\", 0) + m[\"x\"];",
+        "{1.5: 1};",
+        "{[]: 1, 2: x};",
+        "1 in [1.0] == \"b\" in \"abc\";",
+        "7 / (3 - 3);",
+        "\"a\" < \"b\" == 1 <= 1.0;",
+        "fn add(a, b) { a + b } let inc = fn(x) { add(x, 1) }; println(inc); inc(inc(1));",
+        "fn f() { return; 1 } f() == nil;",
+        "let n = 10; fn get_n() { n } n = 20; get_n();",
+        "fn make(x) { fn get() { x } fn set(v) { x = v; } [get, set] } let p = make(1); p[1](5); p[0]();",
+        "fn f(x) { x } f(1, 2);",
+        "fn f() { g() } fn g() { 1 } f();",
+        "return 3; 4;",
+        "let s = 0; for i in 0..4 { s = s + i * i; i; } s;",
+        "for x in [1, [2], \"3\"] { println(x); x + 1; }",
+        "let a = [1]; for x in a { if_small(x); }",
+        "let a = [1, 2]; for x in a { for y in 0..x { push(a, y); } } len(a);",
+        "let r = 2..2 + 3; [len(r), r[1], 4 in r, 5 in r];",
+        "for k in {\"b\": 0, \"a\": 1} { println(k); }",
+        "for i in 0..1.5 { }",
+        "struct P { x, y } let p = P { y: [1], x: \"a\" }; p.y = p.x; println(p); p.y;",
+        "struct P { x } let p = P { x: 1 }; p.x = [p];",
+        "struct P { x } let p = P { x: 1 }; println(p); p.q;",
+        "struct P { x } P { x: 1 } == P { x: 1.0 };",
+        "struct E {} for e in [E {}] { println(e); }",
+        "enum O { S(v), N } let o = O::S([1]); println(o); [o == O::S([1]), O::N == O::N, o == O::N];",
+        "enum O { S(v), N } for o in [O::S(1), O::N, O::S(\"a\")] { println(match o { O::S(1) => \"one\", O::S(v) => v, O::N => nil }); }",
+        "enum O { S(v), N } match O::S(O::N) { O::S(O::S(x)) => x, O::S(_) => { let y = 2; y = y * 3; }, O::N => 0 };",
+        "match \"b\" { \"a\" => 1, 2.5 => 2, b => b + \"!\" };",
+        "enum O { S(v) } let a = []; push(a, O::S(a));",
+        "enum O { S(v) } match nil { nil => 1, O::S(_) => 2, _ => 3 };",
+        "fn f(x) { try { return 1 / x; } catch (e) { println(e); } -1 } [f(2), f(0)];",
+        "try { for i in 0..3 { let a = [i]; a[i]; } } catch (e) { println(e); } let i = 5; i;",
+        "fn f() { throw [1]; } fn g() { f() } try { g(); } catch (e) { push(e, 2); println(e); } g();",
+        "try { throw 1; } catch (e) { e + nope; }",
+        "let e = 1; try { 1; } catch (e) { e = 2; } e;",
+        "fn f(a) {\n  [a][1]\n}\nfn g() {\n  let h = fn() {\n    f(1)\n  };\n  h()\n}\n\ng();",
+        "let m = match 1 {\n  1 => {\n    fn() {\n      nope\n    }\n  }\n  _ => nil\n};\nm();",
+        "[abs(1 - 2.5), min(\"b\", \"a\"), clamp(7, 1, 5), gcd(12, 18), sqrt(2), pow(2, 70)];",
+        "sort([2, \"a\"]);",
+        "map(0..3, fn(x) { println(x); x / (1 - x) });",
+        "filter([1, 2], fn(x) { x });",
+        "reduce(\"abc\", fn(s, c) { c + s }, \"\") == reverse(\"abc\");",
+        "let x; let y; match [1] { [] => { x = 1; y = 2; }, _ => { x = 3; } }; x;",
+        "let x; match 1 { 1 => { x = 1; }, _ => nil }; x;",
+        "fn f(n) { let r; try { r = 10 / n; } catch (e) { r = e; } r } [f(2), f(0)];",
+        "let a; for i in 0..3 { a = i; println(a); }",
+        "let x; export let y;",
+        "let x = 7; x += 2; x -= 1; x *= 3; x /= 5; x %= 3; let s = \"a\"; s += \"b\"; [x, s];",
+        "let a = [1, [2]]; let n = 0; fn at(i) { n += 1; i } a[at(0)] *= 4; a[at(1)] += [3]; [a, n];",
+        "struct P { x } let ps = [P { x: 1 }]; let n = 0; fn p() { n += 1; ps[0] } p().x += 0.5; [ps, n];",
+        "let m = {}; m[\"k\"] += 1;",
+        "let a = [1]; a[0] /= 0; a;",
+        "let x = \"a\"; x -= 1;",
+        "y += 1;",
+        "let s = 0; for i in 1..5 { s += i * i; } s;",
+        "const a = 2 * 3; const b = a / 4 + 0.5; fn f() { [a, b, a == 6] } println(\"${a}\"); f();",
+        "const n = 1 / 0; n;",
+        "fn f() { const c = [1]; push(c, 2); c } [f(), f()];",
+        "fn f() { x = 2; } const x = 1; f();",
+        "const x = 1; let x = 2;",
+        "const x = 1; fn g(x) { x = 2; x } [g(0), x];",
+    ];
+
+    fn assert_agree(input: &str) -> Outcome {
+        match differential(input, &Limits::default(), &Bytecode, &TreeWalk) {
+            Ok(outcome) => outcome,
+            Err(divergence) => panic!("{:?}: {}", input, divergence),
+        }
+    }
+
+    #[test]
+    fn backends_agree_on_snippets() {
+        for input in PROGRAMS {
+            assert_agree(input);
+        }
+    }
+
+    #[test]
+    fn backends_agree_on_prog_files() {
+        for entry in fs::read_dir("prog").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|ext| ext != "cnt") {
+                continue;
+            }
+            let input = fs::read_to_string(&path).unwrap();
+            let source = path.display().to_string();
+            let (stdin, limits) = (&mut "".as_bytes(), Limits::default());
+            if let Err(divergence) =
+                differential_with_input(&input, &source, stdin, true, &limits, &Bytecode, &TreeWalk)
+            {
+                panic!("{}: {}", source, divergence);
+            }
+        }
+    }
+
+    #[test]
+    fn diff_captures_output() {
+        let outcome = assert_agree("let a = 1; let b = 2; a = 3; println(a+b);");
+        assert_eq!(outcome.result, Ok(None));
+        assert_eq!(outcome.output, "5\n");
+    }
+
+    #[test]
+    fn diff_replays_input() {
+        let outcome = differential_with_input(
+            "let n = parse_int(readln()); println(n + 1); readln();",
+            "<input>",
+            &mut "41\nlast\n".as_bytes(),
+            false,
+            &Limits::default(),
+            &Bytecode,
+            &TreeWalk,
+        )
+        .unwrap();
+        assert_eq!(outcome.result, Ok(Some(Value::Str("last".to_string()))));
+        assert_eq!(outcome.output, "42\n");
+    }
+
+    #[test]
+    fn diff_reads_input_only_when_asked() {
+        let mut stdin = "1\n2\n".as_bytes();
+        let outcome = differential_with_input(
+            "readln();",
+            "<input>",
+            &mut stdin,
+            false,
+            &Limits::default(),
+            &Bytecode,
+            &TreeWalk,
+        )
+        .unwrap();
+        assert_eq!(outcome.result, Ok(Some(Value::Str("1".to_string()))));
+        assert_eq!(stdin, b"2\n");
+    }
+
+    #[test]
+    fn diff_deep_nesting_stays_on_the_stack() {
+        // One term a line: lrpar works out the column of each name by
+        // counting from the start of its line.
+        let depth = 3000;
+        let sum = vec!["x"; depth].join(" +\n");
+        let nested = format!("{}x{}", "(x +\n".repeat(depth - 1), ")".repeat(depth - 1));
+        for source in [
+            format!("let x = 1; {};", sum),
+            format!("let x = 1; {};", nested),
+            format!("fn f(x) {{ {} }} f(1);", sum),
+            format!("let x = 1; fn f() {{ {} }} f();", nested),
+        ] {
+            for evaluator in [&Bytecode as &dyn Evaluator, &TreeWalk] {
+                assert_eq!(
+                    eval_str_with(&source, evaluator, &mut vec![]),
+                    Ok(Some(Value::Int(depth as u64))),
+                    "{}",
+                    evaluator.name()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn diff_recursion_reaches_the_call_depth_limit() {
+        use coconut::{error::Resource, limits::DEFAULT_MAX_CALL_DEPTH};
+        // `down` looks up its base case in a map, so each level nests two
+        // calls: `down` and the function it looked up.
+        let down = |n: usize, limits: &Limits| {
+            let source = format!(
+                "fn down(n) {{ get({{0: fn() {{ 0 }}}}, n, fn() {{ down(n - 1) + 1 }})() }} down({});",
+                n
+            );
+            differential_with_input(
+                &source,
+                "<input>",
+                &mut "".as_bytes(),
+                false,
+                limits,
+                &Bytecode,
+                &TreeWalk,
+            )
+            .unwrap()
+            .result
+        };
+        let levels = DEFAULT_MAX_CALL_DEPTH / 2;
+        let limits = Limits::default();
+        assert_eq!(
+            down(levels - 1, &limits),
+            Ok(Some(Value::Int(levels as u64 - 1)))
+        );
+        assert_eq!(
+            down(levels, &limits),
+            Err(Error::ResourceExhausted {
+                resource: Resource::CallDepth,
+                limit: DEFAULT_MAX_CALL_DEPTH
+            })
+        );
+        let limits = Limits {
+            max_call_depth: Some(10000),
+            ..Limits::default()
+        };
+        assert_eq!(down(4999, &limits), Ok(Some(Value::Int(4999))));
+    }
+
+    #[test]
+    fn diff_reports_divergence() {
+        struct Broken;
+        impl Evaluator for Broken {
+            fn name(&self) -> &'static str {
+                "broken"
+            }
+            fn eval(&self, _: Vec<Node>, _: &mut Context) -> Result<Option<Value>, Error> {
+                Ok(Some(Value::Int(42)))
+            }
+        }
+        assert!(differential("1+1", &Limits::default(), &Bytecode, &Broken).is_err());
+    }
+
+    #[test]
+    fn diff_runs_both_backends_under_the_limits() {
+        let limits = Limits {
+            fuel: Some(1000),
+            ..Limits::default()
+        };
+        let outcome = differential(
+            "let n = 0; for i in 0..1000000 { n += i; } n;",
+            &limits,
+            &Bytecode,
+            &TreeWalk,
+        )
+        .unwrap();
+        assert_eq!(outcome.result, Err(Error::OutOfFuel));
+        let limits = Limits {
+            deadline: Some(Instant::now()),
+            ..Limits::default()
+        };
+        let outcome = differential("1;", &limits, &Bytecode, &TreeWalk).unwrap();
+        assert_eq!(outcome.result, Err(Error::DeadlineExceeded));
+    }
+
+    #[test]
+    fn diff_ignores_a_backend_that_ran_out_of_fuel() {
+        struct Starved;
+        impl Evaluator for Starved {
+            fn name(&self) -> &'static str {
+                "starved"
+            }
+            fn eval(&self, _: Vec<Node>, _: &mut Context) -> Result<Option<Value>, Error> {
+                Err(Error::OutOfFuel)
+            }
+        }
+        assert!(differential("1+1", &Limits::default(), &Bytecode, &Starved).is_ok());
+    }
 }
@@ -0,0 +1,326 @@
+//! Modules: coconut files that programs `import`. Each one runs once, with
+//! globals of its own, and shares only the names it `export`s.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    env, fmt, fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use crate::{
+    ast::{Node, Span},
+    context::Context,
+    error::Error,
+    parser::parse_str_strict,
+    scope::Globals,
+    value::Value,
+};
+
+/// A loaded module. Importers read its exports from its globals, so they
+/// also see what the module assigns to them later.
+pub struct Module {
+    pub name: String,
+    pub exports: BTreeSet<String>,
+    /// Its functions only hold on to these weakly.
+    globals: Globals,
+}
+
+impl Module {
+    /// The exported `field`; `span` is where it is named.
+    pub fn get(&self, field: &str, span: Span) -> Result<Value, Error> {
+        let cell = match self.exports.contains(field) {
+            true => self.globals.borrow().get(field).cloned(),
+            false => None,
+        };
+        match cell {
+            Some(cell) => Ok(cell.get()),
+            None => Err(Error::UnknownField {
+                ty: format!("module {}", self.name),
+                field: field.to_string(),
+                span,
+            }),
+        }
+    }
+}
+
+/// Modules are only equal to themselves.
+impl PartialEq for Module {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Module({})", self.name)
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Module {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Module {
+            name: u.arbitrary()?,
+            exports: BTreeSet::new(),
+            globals: Globals::default(),
+        })
+    }
+}
+
+/// Runs the top level of a parsed program. Each backend imports modules
+/// with its own.
+pub type Eval = fn(Vec<Node>, &mut Context) -> Result<Option<Value>, Error>;
+
+/// The modules a context has loaded, and where it looks for more.
+pub struct Modules {
+    /// Directories `import name;` searches after that of the importing file.
+    pub path: Vec<PathBuf>,
+    /// By canonical path, so each file loads once however it is named.
+    loaded: HashMap<PathBuf, Rc<Module>>,
+    /// The canonical and the given paths of the modules being loaded,
+    /// outermost first.
+    loading: Vec<(PathBuf, PathBuf)>,
+}
+
+impl Default for Modules {
+    /// Searches the directories listed in `COCONUT_PATH`.
+    fn default() -> Self {
+        Modules {
+            path: env::var_os("COCONUT_PATH")
+                .map(|path| env::split_paths(&path).collect())
+                .unwrap_or_default(),
+            loaded: HashMap::new(),
+            loading: vec![],
+        }
+    }
+}
+
+/// Loads the module an `import` refers to, running it with `eval` unless
+/// it is loaded already. Its top level runs with nothing but its own
+/// globals in sight.
+pub fn import(ctx: &mut Context, path: &str, search: bool, eval: Eval) -> Result<Value, Error> {
+    let file = resolve(ctx, path, search)?;
+    let key =
+        fs::canonicalize(&file).map_err(|e| format!("Cannot import {}: {}", file.display(), e))?;
+    if let Some(module) = ctx.modules.loaded.get(&key) {
+        return Ok(Value::Module(module.clone()));
+    }
+    if let Some(start) = ctx.modules.loading.iter().position(|(k, _)| *k == key) {
+        let cycle: Vec<_> = ctx.modules.loading[start..]
+            .iter()
+            .map(|(_, file)| file)
+            .chain([&file])
+            .map(|file| file.display().to_string())
+            .collect();
+        return Err(format!("Import cycle: {}", cycle.join(" -> ")).into());
+    }
+    let source = fs::read_to_string(&file)
+        .map_err(|e| format!("Cannot import {}: {}", file.display(), e))?;
+    let ast = parse_str_strict(&source)
+        .map_err(|errors| format!("Cannot parse {}:\n{}", file.display(), errors.join("\n")))?;
+    let names = exports(&ast)?;
+
+    let globals = Globals::default();
+    ctx.modules.loading.push((key.clone(), file.clone()));
+    let caller_scope = ctx.scope.enter_module(globals.clone());
+    let caller = (
+        std::mem::replace(&mut ctx.source, file.display().to_string().into()),
+        ctx.line,
+    );
+    let result = eval(ast, ctx).map_err(|error| error.unwound_through(ctx.frame("<module>")));
+    (ctx.source, ctx.line) = caller;
+    ctx.scope.exit(caller_scope);
+    ctx.modules.loading.pop();
+    result?;
+
+    let name = file.file_stem().unwrap_or_default().to_string_lossy();
+    let module = Rc::new(Module {
+        name: name.to_string(),
+        exports: names.into_iter().collect(),
+        globals,
+    });
+    ctx.modules.loaded.insert(key, module.clone());
+    Ok(Value::Module(module))
+}
+
+/// The file an import refers to: `path` relative to the directory of the
+/// importing file or, for `import name;`, the first `name.cnt` in that
+/// directory or on the search path.
+fn resolve(ctx: &Context, path: &str, search: bool) -> Result<PathBuf, Error> {
+    let here = Path::new(&*ctx.source).parent().unwrap_or(Path::new(""));
+    if !search {
+        return Ok(here.join(path));
+    }
+    let file = format!("{}.cnt", path);
+    std::iter::once(here)
+        .chain(ctx.modules.path.iter().map(PathBuf::as_path))
+        .map(|dir| dir.join(&file))
+        .find(|candidate| candidate.is_file())
+        .ok_or_else(|| format!("Cannot find module {}", path).into())
+}
+
+/// The names `ast` exports. Only `let`s, `const`s and `fn`s at the top
+/// level can be exported.
+pub fn exports(ast: &[Node]) -> Result<Vec<String>, Error> {
+    let mut names = vec![];
+    for node in ast {
+        match node {
+            Node::Export { decl, span } => {
+                match &**decl {
+                    Node::Declare { id, .. }
+                    | Node::Const { id, .. }
+                    | Node::Function { name: Some(id), .. } => names.push(id.clone()),
+                    _ => {
+                        return Err(compile_error(
+                            *span,
+                            "Only let, const and fn can be exported",
+                        ))
+                    }
+                }
+                nested_exports(decl.children())?;
+            }
+            _ => nested_exports(node.children())?,
+        }
+    }
+    Ok(names)
+}
+
+/// Refuses an export anywhere within `nodes`, walking them without
+/// recursion since they nest as deeply as the program does.
+fn nested_exports(nodes: Vec<&Node>) -> Result<(), Error> {
+    let mut pending: Vec<&Node> = nodes.into_iter().rev().collect();
+    while let Some(node) = pending.pop() {
+        if let Node::Export { span, .. } = node {
+            return Err(compile_error(*span, "Only the top level can export"));
+        }
+        pending.extend(node.children().into_iter().rev());
+    }
+    Ok(())
+}
+
+fn compile_error(span: Span, message: &str) -> Error {
+    Error::Compile {
+        message: message.to_string(),
+        span,
+    }
+}
+
+#[cfg(test)]
+mod module_tests {
+    use super::*;
+    use crate::evaluator::{Evaluator, TreeWalk};
+    use std::io::sink;
+
+    /// A fresh directory under the system's temporary one with `files`.
+    fn dir_with(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = env::temp_dir().join(format!("coconut-{}-{}", name, std::process::id()));
+        for (file, source) in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
+        }
+        dir
+    }
+
+    fn run(dir: &Path, source: &str) -> Result<Option<Value>, Error> {
+        let mut ctx = Context::new(Box::new(sink()));
+        ctx.source = dir.join("main.cnt").display().to_string().into();
+        ctx.modules.path = vec![dir.join("lib")];
+        let ast = parse_str_strict(source).unwrap();
+        TreeWalk.eval(ast, &mut ctx)
+    }
+
+    #[test]
+    fn exports_are_top_level_lets_and_fns() {
+        let names = |source| exports(&parse_str_strict(source).unwrap());
+        assert_eq!(
+            names("export let a = 1; let b = 2; export fn c() { b } export const d = 3;"),
+            Ok(vec!["a".to_string(), "c".to_string(), "d".to_string()])
+        );
+        assert_eq!(
+            names("\nexport 1;"),
+            Err(compile_error(
+                Span { line: 2, column: 1 },
+                "Only let, const and fn can be exported"
+            ))
+        );
+        assert_eq!(
+            names("fn f() {\n  export let x = 1;\n}"),
+            Err(compile_error(
+                Span { line: 2, column: 3 },
+                "Only the top level can export"
+            ))
+        );
+    }
+
+    #[test]
+    fn imports_resolve_files_and_the_search_path() {
+        let dir = dir_with(
+            "resolve",
+            &[
+                ("near.cnt", "export let x = 1;"),
+                ("lib/far.cnt", "export let x = 2;"),
+                ("lib/near.cnt", "export let x = 3;"),
+            ],
+        );
+        assert_eq!(
+            run(&dir, "import \"near.cnt\"; import far; near.x + far.x;"),
+            Ok(Some(Value::Int(3)))
+        );
+        // The importing file's directory comes before the search path.
+        assert_eq!(run(&dir, "import near; near.x;"), Ok(Some(Value::Int(1))));
+        assert_eq!(
+            run(&dir, "import nowhere;"),
+            Err(Error::Runtime("Cannot find module nowhere".to_string()))
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn modules_load_once_and_keep_their_globals() {
+        let dir = dir_with(
+            "once",
+            &[
+                (
+                    "counter.cnt",
+                    "println(\"loading\"); let n = 0; export fn next() { n = n + 1; n }",
+                ),
+                (
+                    "user.cnt",
+                    "import \"counter.cnt\"; export let first = counter.next();",
+                ),
+            ],
+        );
+        let source = "
+            let n = 100;
+            import \"user.cnt\";
+            import \"./counter.cnt\";
+            [user.first, counter.next(), n];
+        ";
+        assert_eq!(
+            run(&dir, source).unwrap().unwrap().to_string(),
+            "[1, 2, 100]"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn import_cycles_are_reported() {
+        let dir = dir_with(
+            "cycle",
+            &[
+                ("a.cnt", "import \"b.cnt\";"),
+                ("b.cnt", "import \"a.cnt\";"),
+            ],
+        );
+        let error = run(&dir, "import \"a.cnt\";").unwrap_err();
+        let a = dir.join("a.cnt").display().to_string();
+        let b = dir.join("b.cnt").display().to_string();
+        assert_eq!(
+            error.catch_value(),
+            Some(Value::Str(format!("Import cycle: {} -> {} -> {}", a, b, a)))
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
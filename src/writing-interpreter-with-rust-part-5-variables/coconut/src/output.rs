@@ -0,0 +1,54 @@
+use std::io::{Result, Write};
+
+/// Adapts a callback to `Write` so it can be used as an output sink. The
+/// callback is called once per printed line, without the trailing newline.
+pub struct LineCallback<F: FnMut(&str)> {
+    callback: F,
+    buf: Vec<u8>,
+}
+
+impl<F: FnMut(&str)> LineCallback<F> {
+    pub fn new(callback: F) -> Self {
+        LineCallback {
+            callback,
+            buf: vec![],
+        }
+    }
+}
+
+impl<F: FnMut(&str)> Write for LineCallback<F> {
+    fn write(&mut self, bytes: &[u8]) -> Result<usize> {
+        self.buf.extend_from_slice(bytes);
+        while let Some(end) = self.buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=end).collect();
+            (self.callback)(&String::from_utf8_lossy(&line[..end]));
+        }
+        Ok(bytes.len())
+    }
+
+    /// Hands over a trailing partial line, if any.
+    fn flush(&mut self) -> Result<()> {
+        if !self.buf.is_empty() {
+            let line: Vec<u8> = self.buf.drain(..).collect();
+            (self.callback)(&String::from_utf8_lossy(&line));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod output_tests {
+    use super::*;
+
+    #[test]
+    fn callback_gets_whole_lines() {
+        let mut lines = vec![];
+        {
+            let mut sink = LineCallback::new(|line| lines.push(line.to_string()));
+            write!(sink, "1").unwrap();
+            write!(sink, "2\n3\n4").unwrap();
+            sink.flush().unwrap();
+        }
+        assert_eq!(lines, vec!["12", "3", "4"]);
+    }
+}
@@ -8,9 +8,9 @@ lrpar_mod!("coconut.y"); // brings the Parser for `coconut.y` into scope.
 
 use crate::ast;
 
-pub fn parse_str(input: &String) -> Option<Result<Vec<ast::Node>, ()>> {
+pub fn parse_str(input: &str) -> Option<Result<Vec<ast::Node>, ()>> {
     let lexer_def = coconut_l::lexerdef(); // Lex the input.
-    let lexer = lexer_def.lexer(&input);
+    let lexer = lexer_def.lexer(input);
     let (res, errs) = coconut_y::parse(&lexer); // Parse the input.
                                                 // Check for errors
     for e in errs {
@@ -18,3 +18,22 @@ pub fn parse_str(input: &String) -> Option<Result<Vec<ast::Node>, ()>> {
     }
     res
 }
+
+/// Parses `input`, failing if it has any syntax error. lrpar still
+/// recovers from the errors to find them all; they are returned, not
+/// printed, and the repaired tree is dropped.
+pub fn parse_str_strict(input: &str) -> Result<Vec<ast::Node>, Vec<String>> {
+    let lexer_def = coconut_l::lexerdef();
+    let lexer = lexer_def.lexer(input);
+    let (res, errs) = coconut_y::parse(&lexer);
+    if !errs.is_empty() {
+        return Err(errs
+            .iter()
+            .map(|e| e.pp(&lexer, &coconut_y::token_epp))
+            .collect());
+    }
+    match res {
+        Some(Ok(ast)) => Ok(ast),
+        _ => Err(vec!["Unable to parse input.".to_string()]),
+    }
+}
@@ -0,0 +1,146 @@
+//! The prelude: library functions written in coconut, which programs see
+//! beneath their own globals unless it is left out.
+
+use std::mem;
+
+use crate::{
+    context::Context, error::Error, evaluator::Evaluator, parser::parse_str_strict, scope::Globals,
+};
+
+/// The prelude's source, compiled into the interpreter.
+pub const SOURCE: &str = include_str!("prelude.cnt");
+
+/// Runs the prelude on `evaluator` and makes its globals visible to what
+/// `ctx` runs next. It runs without limits, so it costs programs no fuel.
+pub fn load(ctx: &mut Context, evaluator: &dyn Evaluator) -> Result<(), Error> {
+    let ast = parse_str_strict(SOURCE)
+        .map_err(|errors| format!("Cannot parse the prelude:\n{}", errors.join("\n")))?;
+    let globals = Globals::default();
+    let caller_scope = ctx.scope.enter_module(globals.clone());
+    let caller = (
+        mem::replace(&mut ctx.source, "<prelude>".into()),
+        mem::take(&mut ctx.limits),
+    );
+    let result = evaluator.eval(ast, ctx);
+    (ctx.source, ctx.limits) = caller;
+    ctx.scope.exit(caller_scope);
+    result?;
+    ctx.scope.set_prelude(globals);
+    Ok(())
+}
+
+#[cfg(test)]
+mod prelude_tests {
+    use super::*;
+    use crate::{
+        eval_str_in,
+        evaluator::{Bytecode, TreeWalk},
+        value::Value,
+    };
+    use std::io::sink;
+
+    /// What `input` evaluates to, displayed, on each backend.
+    fn eval(input: &str) -> Vec<String> {
+        [&Bytecode as &dyn Evaluator, &TreeWalk]
+            .into_iter()
+            .map(|evaluator| {
+                let mut ctx = Context::new(Box::new(sink()));
+                load(&mut ctx, evaluator).unwrap();
+                match eval_str_in(input, evaluator, &mut ctx) {
+                    Ok(value) => value.map_or("nil".to_string(), |v| v.to_string()),
+                    Err(error) => error.to_string(),
+                }
+            })
+            .collect()
+    }
+
+    fn assert_eval(input: &str, expected: &str) {
+        assert_eq!(eval(input), [expected, expected], "{}", input);
+    }
+
+    #[test]
+    fn each_calls_f_for_its_effects() {
+        assert_eval(
+            "let a = []; each(1..4, fn(x) { push(a, x * x); }); a;",
+            "[1, 4, 9]",
+        );
+    }
+
+    #[test]
+    fn any_all_and_find_stop_at_the_first_answer() {
+        let odd = "let odd = fn(x) { x % 2 == 1 };";
+        assert_eval(
+            &format!("{} [any([2, 3], odd), any([], odd)];", odd),
+            "[true, false]",
+        );
+        assert_eval(
+            &format!("{} [all([1, 3], odd), all([1, 2], odd)];", odd),
+            "[true, false]",
+        );
+        assert_eval(
+            &format!("{} [find([2, 3, 5], odd), find([2], odd)];", odd),
+            "[3, nil]",
+        );
+        assert_eval("any([1, 0], fn(x) { 1 / x == 1 });", "true");
+    }
+
+    #[test]
+    fn index_of_count_and_product() {
+        assert_eval("[index_of(\"abc\", \"c\"), index_of([1], 2)];", "[2, nil]");
+        assert_eval("count(0..10, fn(x) { x > 6 });", "3");
+        assert_eval("[product([2, 3, 4]), product([])];", "[24, 1]");
+    }
+
+    #[test]
+    fn zip_flatten_join_and_repeat() {
+        assert_eval("zip([1, 2, 3], \"ab\");", "[[1, \"a\"], [2, \"b\"]]");
+        assert_eval("flatten([[1], [], [2, [3]]]);", "[1, 2, [3]]");
+        assert_eval(
+            "[join([1, \"\", 2.5], \", \"), join([], \"-\")];",
+            "[\"1, , 2.5\", \"\"]",
+        );
+        assert_eval("repeat(\"ab\", 3);", "ababab");
+    }
+
+    #[test]
+    fn programs_hide_prelude_functions_without_changing_them() {
+        assert_eval("fn count(xs) { 0 } count([1]);", "0");
+        assert_eval("let zip = 1; zip;", "1");
+        assert_eval(
+            "product = fn(xs) { 0 }; [product([2]), count([1, 2], fn(x) { true })];",
+            "[0, 2]",
+        );
+    }
+
+    #[test]
+    fn loading_costs_no_fuel_or_variables() {
+        let mut ctx = Context::new(Box::new(sink()));
+        ctx.limits.fuel = Some(10);
+        ctx.limits.max_vars = Some(1);
+        load(&mut ctx, &Bytecode).unwrap();
+        assert_eq!(ctx.limits.fuel, Some(10));
+        assert_eq!(ctx.scope.len(), 0);
+        assert_eq!(ctx.source.as_ref(), "<input>");
+        assert_eq!(
+            eval_str_in("let x = product; 1;", &Bytecode, &mut ctx),
+            Ok(Some(Value::Int(1)))
+        );
+        assert_eq!(ctx.limits.fuel, Some(7));
+    }
+
+    #[test]
+    fn eval_str_runs_without_it() {
+        assert_eq!(
+            crate::eval_str("product([2]);"),
+            Err("Function 'product' not found".to_string())
+        );
+    }
+
+    #[test]
+    fn errors_are_traced_into_the_prelude() {
+        assert_eval(
+            "each([1, 0], fn(x) { 1 / x });",
+            "Division by zero\n  in fn at <input>:1\n  in each at <prelude>:8\n  in <main> at <input>:1",
+        );
+    }
+}
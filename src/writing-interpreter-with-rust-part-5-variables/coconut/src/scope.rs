@@ -1,44 +1,375 @@
-use std::collections::HashMap;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::{Rc, Weak},
+};
 
+use crate::value::Value;
+
+/// A variable's storage. Closures capture these cells rather than the
+/// values in them, so a captured variable stays shared between the closure
+/// and the scope it was declared in.
+pub type Cell = Rc<Var>;
+
+/// A variable: its value, and whether it was declared `const`.
+#[derive(Debug)]
+pub struct Var {
+    value: RefCell<Value>,
+    pub constant: bool,
+}
+
+impl Var {
+    pub fn get(&self) -> Value {
+        self.value.borrow().clone()
+    }
+}
+
+/// The global variables of the program or of one module. Functions keep
+/// a weak reference to the globals they were defined among and see those
+/// wherever they are called from.
+pub type Globals = Rc<RefCell<HashMap<String, Cell>>>;
+
+/// Global variables plus a stack of local frames. The frames from `base`
+/// on belong to the function that is running; those below are its callers'
+/// and are out of sight until it returns. Beneath the globals, everything
+/// sees the prelude's.
 pub struct Scope {
-    store: HashMap<String, u64>,
+    globals: Globals,
+    frames: Vec<HashMap<String, Cell>>,
+    base: usize,
+    prelude: Globals,
+    /// How many variables the frames hold, kept so that `len` needn't count
+    /// them on every declaration.
+    locals: usize,
+}
+
+/// What the caller of a function or importer of a module had in sight, for
+/// `Scope::exit` to restore.
+pub struct Caller {
+    base: usize,
+    globals: Globals,
+}
+
+impl Default for Scope {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Scope {
     pub fn new() -> Self {
         Scope {
-            store: HashMap::new(),
+            globals: Globals::default(),
+            frames: vec![],
+            base: 0,
+            prelude: Globals::default(),
+            locals: 0,
+        }
+    }
+
+    /// Declares `id` in the innermost frame, or as a global outside of any
+    /// function or block.
+    pub fn dec_var(&mut self, id: String, val: Value) -> Option<Value> {
+        self.declare(id, val, false)
+    }
+
+    /// Declares `id` like `dec_var`, as a constant.
+    pub fn dec_const(&mut self, id: String, val: Value) -> Option<Value> {
+        self.declare(id, val, true)
+    }
+
+    fn declare(&mut self, id: String, val: Value, constant: bool) -> Option<Value> {
+        let cell = Rc::new(Var {
+            value: RefCell::new(val),
+            constant,
+        });
+        let old = match self.frames[self.base..].last_mut() {
+            Some(frame) => {
+                let old = frame.insert(id, cell);
+                self.locals += old.is_none() as usize;
+                old
+            }
+            None => self.globals.borrow_mut().insert(id, cell),
+        };
+        old.map(|cell| cell.get())
+    }
+
+    /// Assigns the visible `id`, declaring it if there is none. Assigning
+    /// a name from the prelude declares a global that hides it. Constants
+    /// are assigned like any other variable; `Context` refuses to.
+    pub fn set_var(&mut self, id: String, val: Value) -> Option<Value> {
+        match self.own_cell(&id) {
+            Some(cell) => Some(cell.value.replace(val)),
+            None => self.dec_var(id, val),
+        }
+    }
+
+    pub fn get_var(&self, id: &str) -> Option<Value> {
+        self.cell(id).map(|cell| cell.get())
+    }
+
+    /// The variable `id` unless it comes from the prelude.
+    pub fn get_own_var(&self, id: &str) -> Option<Value> {
+        self.own_cell(id).map(|cell| cell.get())
+    }
+
+    /// Whether the `id` that assigning would update is a constant.
+    pub fn is_constant(&self, id: &str) -> bool {
+        self.own_cell(id).is_some_and(|cell| cell.constant)
+    }
+
+    /// The cell `id` resolves to: the innermost local, else the global,
+    /// else the prelude's.
+    pub fn cell(&self, id: &str) -> Option<Cell> {
+        self.own_cell(id)
+            .or_else(|| self.prelude.borrow().get(id).cloned())
+    }
+
+    fn own_cell(&self, id: &str) -> Option<Cell> {
+        self.local(id)
+            .or_else(|| self.globals.borrow().get(id).cloned())
+    }
+
+    /// The cell of a local of the running function, the only variables
+    /// closures capture. Globals are looked up when they are used instead,
+    /// so a function can call itself or one declared after it.
+    pub fn local(&self, id: &str) -> Option<Cell> {
+        self.frames[self.base..]
+            .iter()
+            .rev()
+            .find_map(|frame| frame.get(id).cloned())
+    }
+
+    /// Whether assigning `id` would update a variable rather than add one.
+    pub fn contains(&self, id: &str) -> bool {
+        self.own_cell(id).is_some()
+    }
+
+    /// Whether declaring `id` would replace a variable rather than add one.
+    pub fn declared_here(&self, id: &str) -> bool {
+        match self.frames[self.base..].last() {
+            Some(frame) => frame.contains_key(id),
+            None => self.globals.borrow().contains_key(id),
+        }
+    }
+
+    /// Starts a block; its declarations go out of scope at `pop_block`.
+    pub fn push_block(&mut self) {
+        self.frames.push(HashMap::new());
+    }
+
+    /// Closes the innermost block, refusing to close the running function's
+    /// frame or any of its callers'.
+    pub fn pop_block(&mut self) -> Result<(), String> {
+        if self.frames.len() <= self.base {
+            return Err("No block to close".to_string());
+        }
+        self.truncate(self.frames.len() - 1);
+        Ok(())
+    }
+
+    /// How many blocks and function frames are open.
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Closes the blocks opened since `depth`, e.g. those an error left
+    /// behind when it stopped the program in the middle of them.
+    pub fn unwind_to(&mut self, depth: usize) {
+        self.truncate(depth.max(self.base));
+    }
+
+    fn truncate(&mut self, depth: usize) {
+        if let Some(closed) = self.frames.get(depth..) {
+            self.locals -= closed.iter().map(HashMap::len).sum::<usize>();
+            self.frames.truncate(depth);
+        }
+    }
+
+    /// The globals in sight, which functions defined now will see.
+    pub fn globals(&self) -> Weak<RefCell<HashMap<String, Cell>>> {
+        Rc::downgrade(&self.globals)
+    }
+
+    /// Starts a call to a closure, hiding the caller's locals. The new frame
+    /// starts out with the closure's captured cells, and `globals` replace
+    /// the caller's while they still exist.
+    pub fn enter_function(
+        &mut self,
+        captured: &[(String, Cell)],
+        globals: &Weak<RefCell<HashMap<String, Cell>>>,
+    ) -> Caller {
+        let frame = captured
+            .iter()
+            .map(|(id, cell)| (id.clone(), cell.clone()))
+            .collect::<HashMap<_, _>>();
+        self.locals += frame.len();
+        self.frames.push(frame);
+        let globals = globals.upgrade().unwrap_or_else(|| self.globals.clone());
+        Caller {
+            base: std::mem::replace(&mut self.base, self.frames.len() - 1),
+            globals: std::mem::replace(&mut self.globals, globals),
+        }
+    }
+
+    /// Starts the top level of a module, which sees nothing but `globals`
+    /// and declares its variables there.
+    pub fn enter_module(&mut self, globals: Globals) -> Caller {
+        Caller {
+            base: std::mem::replace(&mut self.base, self.frames.len()),
+            globals: std::mem::replace(&mut self.globals, globals),
         }
     }
 
-    pub fn dec_var(&mut self, id: String, val: u64) -> Option<u64> {
-        self.store.insert(id, val)
+    /// Makes the globals of the prelude visible beneath everyone's own.
+    pub fn set_prelude(&mut self, prelude: Globals) {
+        self.prelude = prelude;
     }
 
-    pub fn set_var(&mut self, id: String, val: u64) -> Option<u64> {
-        self.store.insert(id, val)
+    /// Leaves the function or module entered last, closing whatever frames
+    /// it left open.
+    pub fn exit(&mut self, caller: Caller) {
+        self.truncate(self.base);
+        self.base = caller.base;
+        self.globals = caller.globals;
     }
 
-    pub fn get_var(&self, id: String) -> Option<&u64> {
-        self.store.get(&id.clone())
+    /// Number of live variables.
+    pub fn len(&self) -> usize {
+        self.globals.borrow().len() + self.locals
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 }
 
 #[cfg(test)]
 mod scope_tests {
-    use super::Scope;
+    use super::{Globals, Scope};
+    use crate::value::Value;
+    use std::rc::{Rc, Weak};
 
     #[test]
     fn expected_declare_variable() {
         let mut scope = Scope::new();
-        scope.dec_var("x".to_string(), 1);
-        assert_eq!(*scope.get_var("x".to_string()).unwrap(), 1);
+        scope.dec_var("x".to_string(), Value::Int(1));
+        assert_eq!(scope.get_var("x").unwrap(), Value::Int(1));
     }
     #[test]
     fn expected_declare_and_set_variable() {
         let mut scope = Scope::new();
-        scope.dec_var("x".to_string(), 1);
-        scope.set_var("x".to_string(), 2);
-        assert_eq!(*scope.get_var("x".to_string()).unwrap(), 2);
+        scope.dec_var("x".to_string(), Value::Int(1));
+        scope.set_var("x".to_string(), Value::Int(2));
+        assert_eq!(scope.get_var("x").unwrap(), Value::Int(2));
+    }
+
+    #[test]
+    fn blocks_shadow_and_assign_outer_variables() {
+        let mut scope = Scope::new();
+        scope.dec_var("x".to_string(), Value::Int(1));
+        scope.dec_var("y".to_string(), Value::Int(1));
+        scope.push_block();
+        scope.dec_var("x".to_string(), Value::Int(2));
+        scope.set_var("y".to_string(), Value::Int(2));
+        assert_eq!(scope.get_var("x"), Some(Value::Int(2)));
+        assert_eq!(scope.len(), 3);
+        scope.pop_block().unwrap();
+        assert_eq!(scope.get_var("x"), Some(Value::Int(1)));
+        assert_eq!(scope.get_var("y"), Some(Value::Int(2)));
+    }
+
+    #[test]
+    fn functions_see_captures_and_globals_only() {
+        let mut scope = Scope::new();
+        scope.dec_var("global".to_string(), Value::Int(1));
+        scope.push_block();
+        scope.dec_var("captured".to_string(), Value::Int(2));
+        scope.dec_var("hidden".to_string(), Value::Int(3));
+        let captured = vec![("captured".to_string(), scope.local("captured").unwrap())];
+        let globals = scope.globals();
+        let caller = scope.enter_function(&captured, &globals);
+        assert_eq!(scope.get_var("global"), Some(Value::Int(1)));
+        assert_eq!(scope.get_var("hidden"), None);
+        scope.set_var("captured".to_string(), Value::Int(4));
+        scope.exit(caller);
+        assert_eq!(scope.get_var("captured"), Some(Value::Int(4)));
+        assert_eq!(scope.get_var("hidden"), Some(Value::Int(3)));
+    }
+
+    #[test]
+    fn modules_and_their_functions_see_their_own_globals() {
+        let mut scope = Scope::new();
+        scope.dec_var("x".to_string(), Value::Int(1));
+        scope.push_block();
+        let module = Globals::default();
+        let caller = scope.enter_module(module.clone());
+        assert_eq!(scope.get_var("x"), None);
+        scope.dec_var("x".to_string(), Value::Int(2));
+        scope.exit(caller);
+        assert_eq!(scope.get_var("x"), Some(Value::Int(1)));
+        assert_eq!(scope.depth(), 1);
+
+        let caller = scope.enter_function(&[], &Rc::downgrade(&module));
+        assert_eq!(scope.get_var("x"), Some(Value::Int(2)));
+        scope.exit(caller);
+        // Once the module's globals are gone, functions see the caller's.
+        let caller = scope.enter_function(&[], &Weak::new());
+        assert_eq!(scope.get_var("x"), Some(Value::Int(1)));
+        scope.exit(caller);
+    }
+
+    #[test]
+    fn the_prelude_is_hidden_rather_than_assigned() {
+        let mut scope = Scope::new();
+        let prelude = Globals::default();
+        let caller = scope.enter_module(prelude.clone());
+        scope.dec_var("f".to_string(), Value::Int(1));
+        scope.exit(caller);
+        scope.set_prelude(prelude);
+        assert_eq!(scope.get_var("f"), Some(Value::Int(1)));
+        assert_eq!(scope.get_own_var("f"), None);
+        assert!(!scope.contains("f"));
+        scope.set_var("f".to_string(), Value::Int(2));
+        assert_eq!(scope.get_own_var("f"), Some(Value::Int(2)));
+        scope.push_block();
+        let caller = scope.enter_function(&[], &Weak::new());
+        assert_eq!(scope.get_var("f"), Some(Value::Int(2)));
+        scope.exit(caller);
+        assert_eq!(scope.len(), 1);
+        assert_eq!(scope.prelude.borrow()["f"].get(), Value::Int(1));
+    }
+
+    #[test]
+    fn constants_are_told_apart_from_variables() {
+        let mut scope = Scope::new();
+        scope.dec_const("c".to_string(), Value::Int(1));
+        scope.dec_var("v".to_string(), Value::Int(2));
+        assert!(scope.is_constant("c"));
+        assert!(!scope.is_constant("v") && !scope.is_constant("missing"));
+        scope.push_block();
+        scope.dec_var("c".to_string(), Value::Int(3));
+        assert!(!scope.is_constant("c"));
+        scope.pop_block().unwrap();
+        assert!(scope.is_constant("c"));
+    }
+
+    #[test]
+    fn blocks_close_no_further_than_the_running_function() {
+        let mut scope = Scope::new();
+        assert!(scope.pop_block().is_err());
+        scope.push_block();
+        let caller = scope.enter_function(&[], &Weak::new());
+        scope.dec_var("x".to_string(), Value::Int(1));
+        scope.push_block();
+        scope.dec_var("y".to_string(), Value::Int(2));
+        assert_eq!(scope.len(), 2);
+        scope.pop_block().unwrap();
+        assert_eq!(scope.len(), 1);
+        scope.pop_block().unwrap();
+        assert!(scope.pop_block().is_err());
+        scope.unwind_to(0);
+        scope.exit(caller);
+        assert_eq!((scope.depth(), scope.len()), (1, 0));
     }
 }
@@ -0,0 +1,14 @@
+//! Room on the native stack for the passes that recurse over the AST.
+//! Programs may nest expressions and calls as deeply as they like, so each
+//! step of such a pass goes through `protect`.
+
+/// Stack that must be left for a step to run without growing it, and how
+/// much to grow it by.
+const RED_ZONE: usize = 256 * 1024;
+const STACK_GROWTH: usize = 4 * 1024 * 1024;
+
+/// Runs `f`, on a newly allocated stack segment if the thread's is running
+/// out.
+pub fn protect<R>(f: impl FnOnce() -> R) -> R {
+    stacker::maybe_grow(RED_ZONE, STACK_GROWTH, f)
+}
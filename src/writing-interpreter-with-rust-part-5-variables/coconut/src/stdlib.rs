@@ -0,0 +1,490 @@
+//! The standard library: numeric and collection functions written in Rust.
+//! They are called like builtins, after those and before host functions.
+
+use std::{cmp::Ordering, fmt::Display};
+
+use crate::{builtins::arity, context::Context, error::Error, value::Value};
+
+type Function = fn(&mut Context, &[Value]) -> Result<Value, Error>;
+
+/// Every function with its arity.
+const FUNCTIONS: &[(&str, usize, Function)] = &[
+    ("abs", 1, abs),
+    ("min", 2, min),
+    ("max", 2, max),
+    ("pow", 2, pow),
+    ("sqrt", 1, sqrt),
+    ("clamp", 3, clamp),
+    ("gcd", 2, gcd),
+    ("range", 2, range),
+    ("sum", 1, sum),
+    ("sort", 1, sort),
+    ("reverse", 1, reverse),
+    ("concat", 1, concat),
+    ("map", 2, map),
+    ("filter", 2, filter),
+    ("reduce", 3, reduce),
+];
+
+/// Calls the library function `name` if there is one.
+pub fn call(ctx: &mut Context, name: &str, args: &[Value]) -> Option<Result<Value, Error>> {
+    let (_, expected, function) = FUNCTIONS.iter().find(|(n, ..)| *n == name)?;
+    Some(arity(name, args, *expected).and_then(|_| function(ctx, args)))
+}
+
+fn fail(name: &str, error: impl Display) -> Error {
+    format!("{}: {}", name, error).into()
+}
+
+fn not_a_number(name: &str, arg: &Value) -> Error {
+    fail(name, format!("Expected number, got {}", arg.type_name()))
+}
+
+fn abs(_: &mut Context, args: &[Value]) -> Result<Value, Error> {
+    match &args[0] {
+        arg if arg.is_int() => Ok(arg.clone()),
+        Value::Float(f) => Ok(Value::Float(f.abs())),
+        arg => Err(not_a_number("abs", arg)),
+    }
+}
+
+/// The lesser of two numbers or strings, the first if they are equal.
+fn min(_: &mut Context, args: &[Value]) -> Result<Value, Error> {
+    match args[1].compare(&args[0]).map_err(|e| fail("min", e))? {
+        Some(Ordering::Less) => Ok(args[1].clone()),
+        _ => Ok(args[0].clone()),
+    }
+}
+
+fn max(_: &mut Context, args: &[Value]) -> Result<Value, Error> {
+    match args[1].compare(&args[0]).map_err(|e| fail("max", e))? {
+        Some(Ordering::Greater) => Ok(args[1].clone()),
+        _ => Ok(args[0].clone()),
+    }
+}
+
+/// Ints to an int power by repeated squaring, so the result overflows or
+/// grows big like a product would; anything with a float in it by `powf`.
+fn pow(ctx: &mut Context, args: &[Value]) -> Result<Value, Error> {
+    let (base, exponent) = (&args[0], &args[1]);
+    if let (Some(b), Some(e)) = (base.as_f64(), exponent.as_f64()) {
+        if matches!(base, Value::Float(_)) || matches!(exponent, Value::Float(_)) {
+            return Ok(Value::Float(b.powf(e)));
+        }
+    }
+    if !base.is_int() {
+        return Err(not_a_number("pow", base));
+    }
+    let mut exponent = exponent.as_int().map_err(|e| fail("pow", e))?;
+    let (mut result, mut square) = (Value::Int(1), base.clone());
+    while exponent > 0 {
+        ctx.limits.tick()?;
+        if exponent % 2 == 1 {
+            ctx.limits.spend(result.cost(&square))?;
+            result = result.mul(&square).map_err(|e| fail("pow", e))?;
+        }
+        exponent /= 2;
+        if exponent > 0 {
+            ctx.limits.spend(square.cost(&square))?;
+            square = square.mul(&square).map_err(|e| fail("pow", e))?;
+        }
+    }
+    Ok(result)
+}
+
+fn sqrt(_: &mut Context, args: &[Value]) -> Result<Value, Error> {
+    match args[0].as_f64() {
+        Some(f) => Ok(Value::Float(f.sqrt())),
+        None => Err(not_a_number("sqrt", &args[0])),
+    }
+}
+
+/// `x` limited to `lo..=hi`.
+fn clamp(_: &mut Context, args: &[Value]) -> Result<Value, Error> {
+    let (x, lo, hi) = (&args[0], &args[1], &args[2]);
+    if lo.compare(hi).map_err(|e| fail("clamp", e))? == Some(Ordering::Greater) {
+        return Err(fail(
+            "clamp",
+            format!("lower bound {} is above upper bound {}", lo, hi),
+        ));
+    }
+    if x.compare(lo).map_err(|e| fail("clamp", e))? == Some(Ordering::Less) {
+        return Ok(lo.clone());
+    }
+    match x.compare(hi).map_err(|e| fail("clamp", e))? {
+        Some(Ordering::Greater) => Ok(hi.clone()),
+        _ => Ok(x.clone()),
+    }
+}
+
+fn gcd(_: &mut Context, args: &[Value]) -> Result<Value, Error> {
+    let mut a = args[0].as_int().map_err(|e| fail("gcd", e))?;
+    let mut b = args[1].as_int().map_err(|e| fail("gcd", e))?;
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    Ok(Value::Int(a))
+}
+
+/// The array of ints from `start` up to but excluding `end`.
+fn range(ctx: &mut Context, args: &[Value]) -> Result<Value, Error> {
+    let start = args[0].as_int().map_err(|e| fail("range", e))?;
+    let end = args[1].as_int().map_err(|e| fail("range", e))?;
+    items(ctx, "range", &Value::Range(start, end)).map(Value::array)
+}
+
+fn sum(ctx: &mut Context, args: &[Value]) -> Result<Value, Error> {
+    items(ctx, "sum", &args[0])?
+        .iter()
+        .try_fold(Value::Int(0), |total, item| total.add(item))
+        .map_err(|e| fail("sum", e))
+}
+
+/// A sorted copy of the items. Only numbers, or only strings, can be sorted,
+/// and not NaN.
+fn sort(ctx: &mut Context, args: &[Value]) -> Result<Value, Error> {
+    let mut items = items(ctx, "sort", &args[0])?;
+    if let Some(first) = items.first() {
+        for item in &items {
+            let ordered = first.compare(item).map_err(|e| fail("sort", e))?.is_some()
+                && item.compare(item).map_err(|e| fail("sort", e))?.is_some();
+            if !ordered {
+                return Err(fail("sort", format!("Cannot order {}", item)));
+            }
+        }
+    }
+    // Having checked them, every pair of items is ordered.
+    items.sort_by(|l, r| l.compare(r).ok().flatten().unwrap_or(Ordering::Equal));
+    Ok(Value::array(items))
+}
+
+/// A string with its characters reversed, or the items of anything else
+/// in reverse.
+fn reverse(ctx: &mut Context, args: &[Value]) -> Result<Value, Error> {
+    match &args[0] {
+        Value::Str(s) => Ok(Value::Str(s.chars().rev().collect())),
+        xs => {
+            let mut items = items(ctx, "reverse", xs)?;
+            items.reverse();
+            Ok(Value::array(items))
+        }
+    }
+}
+
+/// The items run together as `str` shows them, built in one go so that
+/// joining many strings takes time in proportion to the result. Like `+`
+/// on strings, it costs a unit of fuel per byte.
+fn concat(ctx: &mut Context, args: &[Value]) -> Result<Value, Error> {
+    let mut joined = String::new();
+    for item in items(ctx, "concat", &args[0])? {
+        let item = item.to_string();
+        ctx.limits.spend(item.len() as u64)?;
+        joined.push_str(&item);
+    }
+    Ok(Value::Str(joined))
+}
+
+fn map(ctx: &mut Context, args: &[Value]) -> Result<Value, Error> {
+    let mut mapped = vec![];
+    for item in items(ctx, "map", &args[0])? {
+        mapped.push(ctx.call_value(&args[1], &[item])?);
+    }
+    Ok(Value::array(mapped))
+}
+
+/// The items the predicate returns `true` for.
+fn filter(ctx: &mut Context, args: &[Value]) -> Result<Value, Error> {
+    let mut kept = vec![];
+    for item in items(ctx, "filter", &args[0])? {
+        match ctx.call_value(&args[1], std::slice::from_ref(&item))? {
+            Value::Bool(true) => kept.push(item),
+            Value::Bool(false) => {}
+            other => {
+                let message = format!(
+                    "Expected bool from the predicate, got {}",
+                    other.type_name()
+                );
+                return Err(fail("filter", message));
+            }
+        }
+    }
+    Ok(Value::array(kept))
+}
+
+/// Folds the items into `init` from the left with `f(total, item)`.
+fn reduce(ctx: &mut Context, args: &[Value]) -> Result<Value, Error> {
+    let mut total = args[2].clone();
+    for item in items(ctx, "reduce", &args[0])? {
+        total = ctx.call_value(&args[1], &[total, item])?;
+    }
+    Ok(total)
+}
+
+/// The items a `for` loop over `xs` would see, as they are now. Each costs
+/// a tick of fuel, so huge ranges run out of it rather than of memory.
+fn items(ctx: &mut Context, name: &str, xs: &Value) -> Result<Vec<Value>, Error> {
+    let xs = xs.iterable().map_err(|e| fail(name, e))?;
+    let mut items = vec![];
+    while let Some(item) = xs.nth(items.len() as u64) {
+        ctx.limits.tick()?;
+        items.push(item);
+    }
+    Ok(items)
+}
+
+#[cfg(test)]
+mod stdlib_tests {
+    use super::*;
+    use crate::{eval_str_in, evaluator::TreeWalk, limits::Limits};
+    use std::io::sink;
+
+    fn ctx() -> Context<'static> {
+        Context::new(Box::new(sink()))
+    }
+
+    fn ok(name: &str, args: &[Value]) -> Value {
+        call(&mut ctx(), name, args).unwrap().unwrap()
+    }
+
+    fn err(name: &str, args: &[Value]) -> String {
+        call(&mut ctx(), name, args)
+            .unwrap()
+            .unwrap_err()
+            .to_string()
+    }
+
+    fn ints(items: &[u64]) -> Value {
+        Value::array(items.iter().copied().map(Value::Int).collect())
+    }
+
+    /// The function `source` evaluates to, along with its context.
+    fn function(source: &str) -> (Context<'static>, Value) {
+        let mut ctx = ctx();
+        let f = eval_str_in(source, &TreeWalk, &mut ctx).unwrap().unwrap();
+        (ctx, f)
+    }
+
+    #[test]
+    fn unknown_names_and_wrong_arities() {
+        assert!(call(&mut ctx(), "println", &[]).is_none());
+        assert_eq!(
+            err("abs", &[]),
+            "Function 'abs' expects 1 argument(s), got 0"
+        );
+    }
+
+    #[test]
+    fn abs_keeps_ints_and_drops_the_sign_of_floats() {
+        assert_eq!(ok("abs", &[Value::Int(3)]), Value::Int(3));
+        assert_eq!(ok("abs", &[Value::Float(-2.5)]), Value::Float(2.5));
+        assert_eq!(
+            err("abs", &["x".into()]),
+            "abs: Expected number, got string"
+        );
+    }
+
+    #[test]
+    fn min_and_max_compare_numbers_and_strings() {
+        assert_eq!(
+            ok("min", &[Value::Int(3), Value::Float(2.5)]),
+            Value::Float(2.5)
+        );
+        assert_eq!(
+            ok("max", &[Value::Int(3), Value::Float(2.5)]),
+            Value::Int(3)
+        );
+        assert_eq!(ok("min", &["b".into(), "a".into()]), "a".into());
+        assert_eq!(ok("max", &["b".into(), "a".into()]), "b".into());
+        assert_eq!(
+            err("max", &[Value::Int(1), "a".into()]),
+            "max: Cannot compare string and int"
+        );
+    }
+
+    #[test]
+    fn pow_multiplies_ints_and_falls_back_to_floats() {
+        assert_eq!(ok("pow", &[Value::Int(3), Value::Int(4)]), Value::Int(81));
+        assert_eq!(ok("pow", &[Value::Int(7), Value::Int(0)]), Value::Int(1));
+        assert_eq!(
+            ok("pow", &[Value::Int(4), Value::Float(0.5)]),
+            Value::Float(2.0)
+        );
+        assert_eq!(
+            ok("pow", &[Value::Float(1.5), Value::Int(2)]),
+            Value::Float(2.25)
+        );
+        let overflowed = call(&mut ctx(), "pow", &[Value::Int(2), Value::Int(64)]).unwrap();
+        match cfg!(feature = "bigint") {
+            true => assert_eq!(overflowed.unwrap().to_string(), "18446744073709551616"),
+            false => assert_eq!(overflowed.unwrap_err().to_string(), "pow: overflowed"),
+        }
+        assert_eq!(
+            err("pow", &["2".into(), Value::Int(2)]),
+            "pow: Expected number, got string"
+        );
+    }
+
+    #[test]
+    fn sqrt_returns_floats() {
+        assert_eq!(ok("sqrt", &[Value::Int(16)]), Value::Float(4.0));
+        assert_eq!(ok("sqrt", &[Value::Float(2.25)]), Value::Float(1.5));
+        assert_eq!(
+            err("sqrt", &[Value::Bool(true)]),
+            "sqrt: Expected number, got bool"
+        );
+    }
+
+    #[test]
+    fn clamp_limits_to_the_bounds() {
+        let clamp = |x| ok("clamp", &[x, Value::Int(1), Value::Int(5)]);
+        assert_eq!(clamp(Value::Int(0)), Value::Int(1));
+        assert_eq!(clamp(Value::Float(2.5)), Value::Float(2.5));
+        assert_eq!(clamp(Value::Int(9)), Value::Int(5));
+        assert_eq!(
+            err("clamp", &[Value::Int(1), Value::Int(5), Value::Int(1)]),
+            "clamp: lower bound 5 is above upper bound 1"
+        );
+    }
+
+    #[test]
+    fn gcd_of_ints() {
+        assert_eq!(ok("gcd", &[Value::Int(12), Value::Int(18)]), Value::Int(6));
+        assert_eq!(ok("gcd", &[Value::Int(0), Value::Int(5)]), Value::Int(5));
+        assert_eq!(
+            err("gcd", &[Value::Float(1.0), Value::Int(5)]),
+            "gcd: Expected int, got float"
+        );
+    }
+
+    #[test]
+    fn range_builds_arrays_and_costs_fuel() {
+        assert_eq!(
+            ok("range", &[Value::Int(2), Value::Int(5)]),
+            ints(&[2, 3, 4])
+        );
+        assert_eq!(ok("range", &[Value::Int(5), Value::Int(2)]), ints(&[]));
+        let mut ctx = ctx();
+        ctx.limits = Limits {
+            fuel: Some(100),
+            ..Limits::default()
+        };
+        assert_eq!(
+            call(&mut ctx, "range", &[Value::Int(0), Value::Int(u64::MAX)]),
+            Some(Err(Error::OutOfFuel))
+        );
+    }
+
+    #[test]
+    fn sum_adds_up_any_iterable() {
+        assert_eq!(ok("sum", &[ints(&[1, 2, 3])]), Value::Int(6));
+        assert_eq!(ok("sum", &[Value::Range(1, 5)]), Value::Int(10));
+        assert_eq!(ok("sum", &[ints(&[])]), Value::Int(0));
+        assert_eq!(
+            ok(
+                "sum",
+                &[Value::array(vec![Value::Int(1), Value::Float(0.5)])]
+            ),
+            Value::Float(1.5)
+        );
+        assert_eq!(
+            err("sum", &[Value::array(vec!["a".into()])]),
+            "sum: Cannot add int and string"
+        );
+    }
+
+    #[test]
+    fn sort_copies_and_orders() {
+        let xs = ints(&[3, 1, 2]);
+        assert_eq!(ok("sort", std::slice::from_ref(&xs)), ints(&[1, 2, 3]));
+        assert_eq!(xs, ints(&[3, 1, 2]));
+        assert_eq!(
+            ok("sort", &["cab".into()]).to_string(),
+            "[\"a\", \"b\", \"c\"]"
+        );
+        assert_eq!(
+            err("sort", &[Value::array(vec![Value::Int(1), "a".into()])]),
+            "sort: Cannot compare int and string"
+        );
+        assert_eq!(
+            err(
+                "sort",
+                &[Value::array(vec![Value::Int(1), Value::Float(f64::NAN)])]
+            ),
+            "sort: Cannot order NaN"
+        );
+    }
+
+    #[test]
+    fn reverse_strings_and_iterables() {
+        assert_eq!(ok("reverse", &["héllo".into()]), "olléh".into());
+        assert_eq!(ok("reverse", &[Value::Range(1, 4)]), ints(&[3, 2, 1]));
+        assert_eq!(
+            err("reverse", &[Value::Int(1)]),
+            "reverse: Cannot iterate over int"
+        );
+    }
+
+    #[test]
+    fn concat_runs_the_items_together() {
+        let parts = Value::array(vec!["a".into(), Value::Int(1), Value::Float(2.5)]);
+        assert_eq!(ok("concat", &[parts]), "a12.5".into());
+        assert_eq!(ok("concat", &[ints(&[])]), "".into());
+        assert_eq!(
+            err("concat", &[Value::Int(1)]),
+            "concat: Cannot iterate over int"
+        );
+        let mut ctx = ctx();
+        ctx.limits = Limits {
+            fuel: Some(10),
+            ..Limits::default()
+        };
+        let long = Value::array(vec!["abcdefghijk".into()]);
+        assert_eq!(
+            call(&mut ctx, "concat", &[long]),
+            Some(Err(Error::OutOfFuel))
+        );
+    }
+
+    #[test]
+    fn map_calls_the_function_on_each_item() {
+        let (mut ctx, double) = function("fn(x) { x * 2 }");
+        assert_eq!(
+            call(&mut ctx, "map", &[ints(&[1, 2, 3]), double]),
+            Some(Ok(ints(&[2, 4, 6])))
+        );
+        assert_eq!(err("map", &[ints(&[1]), Value::Int(1)]), "Cannot call int");
+    }
+
+    #[test]
+    fn filter_keeps_items_the_predicate_accepts() {
+        let (mut ctx, odd) = function("fn(x) { x % 2 == 1 }");
+        assert_eq!(
+            call(&mut ctx, "filter", &[Value::Range(0, 6), odd]),
+            Some(Ok(ints(&[1, 3, 5])))
+        );
+        let (mut ctx, int) = function("fn(x) { x }");
+        assert_eq!(
+            call(&mut ctx, "filter", &[ints(&[1]), int]),
+            Some(Err(Error::Runtime(
+                "filter: Expected bool from the predicate, got int".to_string()
+            )))
+        );
+    }
+
+    #[test]
+    fn reduce_folds_from_the_left() {
+        let (mut ctx, digits) = function("fn(total, d) { total * 10 + d }");
+        assert_eq!(
+            call(
+                &mut ctx,
+                "reduce",
+                &[ints(&[1, 2, 3]), digits.clone(), Value::Int(0)]
+            ),
+            Some(Ok(Value::Int(123)))
+        );
+        assert_eq!(
+            call(&mut ctx, "reduce", &[ints(&[]), digits, Value::Int(7)]),
+            Some(Ok(Value::Int(7)))
+        );
+    }
+}
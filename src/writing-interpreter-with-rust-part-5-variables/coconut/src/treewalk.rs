@@ -0,0 +1,419 @@
+use std::{collections::BTreeMap, rc::Rc};
+
+use crate::{
+    assigned,
+    ast::{Arm, Node, Operator, Pattern},
+    constant,
+    context::Context,
+    error::Error,
+    function::{self, Code, Prototype},
+    module, stack,
+    value::Value,
+};
+
+/// Why evaluation stopped before the end of a statement: an error, or a
+/// `return` on its way out to the call it returns from.
+enum Unwind {
+    Error(Error),
+    Return(Value),
+}
+
+impl From<Error> for Unwind {
+    fn from(error: Error) -> Self {
+        Unwind::Error(error)
+    }
+}
+
+impl From<String> for Unwind {
+    fn from(msg: String) -> Self {
+        Unwind::Error(msg.into())
+    }
+}
+
+/// Evaluates `ast` by walking it recursively, the way part 2 did before
+/// bytecode was introduced. The result is the value of the last statement
+/// that produced one, which matches what the bytecode VM leaves on its stack.
+/// A `return` outside of any function ends the program with its value.
+pub fn eval(mut ast: Vec<Node>, ctx: &mut Context) -> Result<Option<Value>, Error> {
+    ctx.types.check(&ast)?;
+    module::exports(&ast)?;
+    assigned::check(&ast)?;
+    constant::check(&mut ast)?;
+    let mut result = None;
+    for node in &ast {
+        match eval_exp(node, ctx) {
+            Ok(Some(value)) => result = Some(value),
+            Ok(None) => {}
+            Err(Unwind::Return(value)) => return Ok(Some(value)),
+            Err(Unwind::Error(error)) => return Err(error),
+        }
+    }
+    Ok(result)
+}
+
+/// Runs the body of a function whose frame `ctx` has already entered. A
+/// body that ends without `return` returns nil.
+pub fn call_body(body: &[Node], ctx: &mut Context) -> Result<Value, Error> {
+    for node in body {
+        match eval_exp(node, ctx) {
+            Ok(_) => {}
+            Err(Unwind::Return(value)) => return Ok(value),
+            Err(Unwind::Error(error)) => return Err(error),
+        }
+    }
+    Ok(Value::Nil)
+}
+
+/// Function calls nest native calls, so deep recursion in a program would
+/// overflow the thread's stack without `stack::protect`.
+fn eval_exp(exp: &Node, ctx: &mut Context) -> Result<Option<Value>, Unwind> {
+    stack::protect(|| eval_node(exp, ctx))
+}
+
+fn eval_node(exp: &Node, ctx: &mut Context) -> Result<Option<Value>, Unwind> {
+    // Line markers cost no fuel, just as the VM compiles them to no ops.
+    if let Node::Line { line } = exp {
+        ctx.line = *line;
+        return Ok(None);
+    }
+    ctx.limits.tick()?;
+    match exp {
+        Node::Add { lhs, rhs } => binary(lhs, rhs, ctx, Value::add),
+        Node::Sub { lhs, rhs } => binary(lhs, rhs, ctx, Value::sub),
+        Node::Mul { lhs, rhs } => binary(lhs, rhs, ctx, Value::mul),
+        Node::Div { lhs, rhs } => binary(lhs, rhs, ctx, Value::div),
+        Node::Rem { lhs, rhs } => binary(lhs, rhs, ctx, Value::rem),
+        Node::Eq { lhs, rhs } => binary(lhs, rhs, ctx, |l, r| Ok(l.equals(r))),
+        Node::Ne { lhs, rhs } => binary(lhs, rhs, ctx, |l, r| Ok(l.not_equals(r))),
+        Node::Lt { lhs, rhs } => binary(lhs, rhs, ctx, Value::lt),
+        Node::Le { lhs, rhs } => binary(lhs, rhs, ctx, Value::le),
+        Node::Gt { lhs, rhs } => binary(lhs, rhs, ctx, Value::gt),
+        Node::Ge { lhs, rhs } => binary(lhs, rhs, ctx, Value::ge),
+        Node::In { lhs, rhs } => binary(lhs, rhs, ctx, |l, r| r.has(l)),
+        Node::Range { lhs, rhs } => binary(lhs, rhs, ctx, Value::range),
+        Node::Number { value } => Ok(Some(Value::Int(*value))),
+        #[cfg(feature = "bigint")]
+        Node::BigNumber { value } => Ok(Some(Value::from(value.clone()))),
+        Node::Float { value } => Ok(Some(Value::Float(*value))),
+        Node::Str { value } => Ok(Some(Value::Str(value.clone()))),
+        Node::Bool { value } => Ok(Some(Value::Bool(*value))),
+        Node::Nil => Ok(Some(Value::Nil)),
+        Node::Id { value, .. } => match ctx.scope.get_var(value) {
+            Some(v) => Ok(Some(v)),
+            None => Err(format!("Variable '{}' not found", value).into()),
+        },
+        Node::Call { id, args, .. } => {
+            let values = eval_all(args, ctx)?;
+            Ok(Some(ctx.call(id, &values)?))
+        }
+        Node::Apply { callee, args } => {
+            let callee = eval_value(callee, ctx)?;
+            let values = eval_all(args, ctx)?;
+            Ok(Some(ctx.call_value(&callee, &values)?))
+        }
+        Node::Function { name, params, body } => {
+            if let Some(name) = name {
+                // Declared before the closure is made, so it can capture itself.
+                ctx.dec_var(name.clone(), Value::Nil)?;
+            }
+            let proto = Prototype {
+                name: name.clone(),
+                params: params.clone(),
+                captures: function::captures(params, body),
+                code: Code::Ast(body.clone()),
+            };
+            let closure = function::close(Rc::new(proto), ctx);
+            match name {
+                Some(name) => {
+                    ctx.set_var(name.clone(), closure)?;
+                    Ok(None)
+                }
+                None => Ok(Some(closure)),
+            }
+        }
+        Node::Return { rhs } => {
+            let value = match rhs {
+                Some(rhs) => eval_value(rhs, ctx)?,
+                None => Value::Nil,
+            };
+            Err(Unwind::Return(value))
+        }
+        Node::For { id, iterable, body } => {
+            let iterable = eval_value(iterable, ctx)?.iterable()?;
+            let mut n = 0;
+            while let Some(item) = iterable.nth(n) {
+                n += 1;
+                ctx.scope.push_block();
+                let result = eval_block(id, item, body, ctx);
+                ctx.scope.pop_block()?;
+                result?;
+            }
+            Ok(None)
+        }
+        Node::Throw { rhs } => {
+            let value = eval_value(rhs, ctx)?;
+            Err(Error::Thrown { value }.into())
+        }
+        Node::Try { body, id, handler } => {
+            let depth = ctx.scope.depth();
+            ctx.scope.push_block();
+            let result = eval_statements(body, ctx);
+            // An error may have left blocks of the body open.
+            ctx.scope.unwind_to(depth);
+            match result {
+                Err(Unwind::Error(error)) => match error.catch_value() {
+                    Some(value) => {
+                        ctx.scope.push_block();
+                        let result = ctx
+                            .dec_var(id.clone(), value)
+                            .map_err(Unwind::from)
+                            .and_then(|()| eval_statements(handler, ctx));
+                        ctx.scope.pop_block()?;
+                        result?;
+                    }
+                    None => return Err(Unwind::Error(error)),
+                },
+                other => other?,
+            }
+            Ok(None)
+        }
+        Node::Struct { .. } | Node::Enum { .. } => Ok(None),
+        Node::Variant {
+            ty, variant, args, ..
+        } => {
+            let values = eval_all(args, ctx)?;
+            Ok(Some(ctx.types.variant(ty, variant, values)?))
+        }
+        Node::Match {
+            scrutinee, arms, ..
+        } => {
+            let value = eval_value(scrutinee, ctx)?;
+            for arm in arms {
+                ctx.scope.push_block();
+                let result = eval_arm(arm, &value, ctx);
+                ctx.scope.pop_block()?;
+                if let Some(result) = result? {
+                    return Ok(Some(result));
+                }
+            }
+            // Exhaustiveness is only checked for the types the patterns name.
+            Err(format!("No match arm for {}", value).into())
+        }
+        Node::StructLiteral { name, fields, .. } => {
+            let mut names = Vec::with_capacity(fields.len());
+            let mut values = Vec::with_capacity(fields.len());
+            for field in fields {
+                names.push(field.name.clone());
+                values.push(eval_value(&field.value, ctx)?);
+            }
+            Ok(Some(ctx.types.construct(name, &names, values)?))
+        }
+        Node::Field {
+            target,
+            field,
+            span,
+        } => Ok(Some(eval_value(target, ctx)?.field(field, *span)?)),
+        Node::SetField { .. }
+        | Node::SetIndex { .. }
+        | Node::Assign { .. }
+        | Node::Declare { .. } => {
+            store(exp, ctx)?;
+            Ok(None)
+        }
+        Node::Array { items } => Ok(Some(Value::array(eval_all(items, ctx)?))),
+        Node::Interpolate { parts } => {
+            let text = eval_all(parts, ctx)?.iter().map(Value::to_string).collect();
+            Ok(Some(Value::Str(text)))
+        }
+        Node::Map { entries } => {
+            // Evaluate every entry before inserting any, as the VM does.
+            let mut pairs = Vec::with_capacity(entries.len());
+            for (key, value) in entries {
+                pairs.push((eval_value(key, ctx)?, eval_value(value, ctx)?));
+            }
+            let map = Value::map(BTreeMap::new());
+            for (key, value) in pairs {
+                map.set_index(&key, value)?;
+            }
+            Ok(Some(map))
+        }
+        Node::Index { target, index } => {
+            let target = eval_value(target, ctx)?;
+            let index = eval_value(index, ctx)?;
+            Ok(Some(target.index(&index)?))
+        }
+        Node::PrintLn { rhs } => {
+            let value = eval_value(rhs, ctx)?;
+            writeln!(ctx.out, "{}", value).map_err(|e| e.to_string())?;
+            Ok(None)
+        }
+        Node::Const { id, rhs, .. } => {
+            let value = eval_value(rhs, ctx)?;
+            ctx.dec_const(id.clone(), value)?;
+            Ok(None)
+        }
+        Node::Import { path, search, name } => {
+            let module = module::import(ctx, path, *search, eval)?;
+            ctx.dec_var(name.clone(), module)?;
+            Ok(None)
+        }
+        Node::Export { decl, .. } => eval_exp(decl, ctx),
+        Node::Empty | Node::Line { .. } => Ok(None),
+    }
+}
+
+fn eval_statements(body: &[Node], ctx: &mut Context) -> Result<(), Unwind> {
+    for node in body {
+        eval_exp(node, ctx)?;
+    }
+    Ok(())
+}
+
+/// Runs one iteration of a `for` loop in the block opened for it.
+fn eval_block(id: &str, item: Value, body: &[Node], ctx: &mut Context) -> Result<(), Unwind> {
+    ctx.dec_var(id.to_string(), item)?;
+    eval_statements(body, ctx)
+}
+
+/// Runs `arm` in the block opened for it if its pattern matches `value`.
+/// An arm whose body doesn't produce a value gives nil.
+fn eval_arm(arm: &Arm, value: &Value, ctx: &mut Context) -> Result<Option<Value>, Unwind> {
+    if !bind(&arm.pattern, value, ctx)? {
+        return Ok(None);
+    }
+    eval_statements(&arm.body, ctx)?;
+    let result = match &arm.tail {
+        Some(tail) => eval_exp(tail, ctx)?,
+        None => None,
+    };
+    Ok(Some(result.unwrap_or(Value::Nil)))
+}
+
+/// Whether `value` matches `pattern`, declaring its bindings as they are
+/// reached, left to right.
+fn bind(pattern: &Pattern, value: &Value, ctx: &mut Context) -> Result<bool, Unwind> {
+    match pattern {
+        Pattern::Wildcard => Ok(true),
+        Pattern::Bind(name) => {
+            ctx.dec_var(name.clone(), value.clone())?;
+            Ok(true)
+        }
+        Pattern::Literal(literal) => Ok(value.equals(literal) == Value::Bool(true)),
+        Pattern::Variant {
+            ty, variant, args, ..
+        } => {
+            let Some(values) = value.variant_values(ty, variant) else {
+                return Ok(false);
+            };
+            for (arg, value) in args.iter().zip(values) {
+                if !bind(arg, value, ctx)? {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        }
+    }
+}
+
+/// Evaluates both operands, left first, and combines them with `op`.
+fn binary(
+    lhs: &Node,
+    rhs: &Node,
+    ctx: &mut Context,
+    op: fn(&Value, &Value) -> Result<Value, String>,
+) -> Result<Option<Value>, Unwind> {
+    let lhs = eval_value(lhs, ctx)?;
+    let rhs = eval_value(rhs, ctx)?;
+    ctx.limits.spend(lhs.cost(&rhs))?;
+    Ok(Some(op(&lhs, &rhs)?))
+}
+
+/// Runs the assignment `exp` and returns the value it stored.
+fn store(exp: &Node, ctx: &mut Context) -> Result<Value, Unwind> {
+    let value = match exp {
+        Node::SetField {
+            target,
+            field,
+            op,
+            rhs,
+            span,
+        } => {
+            let target = eval_value(target, ctx)?;
+            let value = updated(*op, |_| Ok(target.field(field, *span)?), rhs, ctx)?;
+            target.set_field(field, value.clone(), *span)?;
+            value
+        }
+        Node::SetIndex {
+            target,
+            index,
+            op,
+            rhs,
+        } => {
+            let target = eval_value(target, ctx)?;
+            let index = eval_value(index, ctx)?;
+            let value = updated(*op, |_| Ok(target.index(&index)?), rhs, ctx)?;
+            target.set_index(&index, value.clone())?;
+            value
+        }
+        Node::Assign { id, op, rhs, .. } => {
+            let current = |ctx: &mut Context| {
+                let value = ctx.scope.get_var(id);
+                value.ok_or(format!("Variable '{}' not found", id).into())
+            };
+            let value = updated(*op, current, rhs, ctx)?;
+            ctx.set_var(id.clone(), value.clone())?;
+            value
+        }
+        Node::Declare { id, rhs } => {
+            let value = match rhs {
+                Some(rhs) => eval_value(rhs, ctx)?,
+                None => Value::Nil,
+            };
+            ctx.dec_var(id.clone(), value.clone())?;
+            value
+        }
+        _ => eval_value(exp, ctx)?,
+    };
+    Ok(value)
+}
+
+/// What an assignment stores: `rhs`, or `op` applied to the `current` value and `rhs`.
+fn updated(
+    op: Option<Operator>,
+    current: impl FnOnce(&mut Context) -> Result<Value, Unwind>,
+    rhs: &Node,
+    ctx: &mut Context,
+) -> Result<Value, Unwind> {
+    match op {
+        Some(op) => {
+            let lhs = current(ctx)?;
+            let rhs = eval_value(rhs, ctx)?;
+            ctx.limits.spend(lhs.cost(&rhs))?;
+            Ok(op.apply(&lhs, &rhs)?)
+        }
+        None => eval_value(rhs, ctx),
+    }
+}
+
+fn eval_all(exps: &[Node], ctx: &mut Context) -> Result<Vec<Value>, Unwind> {
+    let mut values = Vec::with_capacity(exps.len());
+    for exp in exps {
+        values.push(eval_value(exp, ctx)?);
+    }
+    Ok(values)
+}
+
+/// Evaluates `exp` where its value is used. An assignment there produces
+/// the value it stores, which as a statement it doesn't.
+fn eval_value(exp: &Node, ctx: &mut Context) -> Result<Value, Unwind> {
+    match exp {
+        Node::SetField { .. }
+        | Node::SetIndex { .. }
+        | Node::Assign { .. }
+        | Node::Declare { .. } => {
+            ctx.limits.tick()?;
+            store(exp, ctx)
+        }
+        _ => Ok(eval_exp(exp, ctx)?.ok_or("Expression does not produce a value".to_string())?),
+    }
+}
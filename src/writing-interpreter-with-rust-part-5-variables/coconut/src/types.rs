@@ -0,0 +1,658 @@
+//! Struct and enum types: the table of declarations that literals and
+//! match patterns are checked against before a program runs, and the values
+//! built from them.
+
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::{
+    ast::{Node, Pattern, Span},
+    error::Error,
+    value::Value,
+};
+
+/// A `struct` declaration.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct StructType {
+    pub name: String,
+    pub fields: Vec<String>,
+}
+
+impl StructType {
+    pub fn field(&self, name: &str) -> Option<usize> {
+        self.fields.iter().position(|field| field == name)
+    }
+}
+
+/// An instance of a struct, with one value per field in declaration order.
+/// Shared and mutable like arrays.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct Record {
+    pub ty: Rc<StructType>,
+    pub values: RefCell<Vec<Value>>,
+}
+
+/// An `enum` declaration: its variants and how many values each carries.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct EnumType {
+    pub name: String,
+    pub variants: Vec<(String, usize)>,
+}
+
+impl EnumType {
+    pub fn variant(&self, name: &str) -> Option<usize> {
+        self.variants
+            .iter()
+            .position(|(variant, _)| variant == name)
+    }
+}
+
+/// An instance of an enum: which variant it is and the values it carries.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct Variant {
+    pub ty: Rc<EnumType>,
+    pub variant: usize,
+    pub values: Vec<Value>,
+}
+
+impl Variant {
+    pub fn name(&self) -> &str {
+        self.ty
+            .variants
+            .get(self.variant)
+            .map_or("?", |(name, _)| name)
+    }
+}
+
+/// The structs and enums declared so far. It outlives a single program, so
+/// a REPL line can use a type declared on an earlier one.
+#[derive(Default)]
+pub struct Types {
+    structs: HashMap<String, Rc<StructType>>,
+    enums: HashMap<String, Rc<EnumType>>,
+}
+
+/// The declarations a check works on, committed only if it passes.
+struct Declared {
+    structs: HashMap<String, Rc<StructType>>,
+    enums: HashMap<String, Rc<EnumType>>,
+}
+
+impl Types {
+    pub fn get(&self, name: &str) -> Option<&Rc<StructType>> {
+        self.structs.get(name)
+    }
+
+    pub fn get_enum(&self, name: &str) -> Option<&Rc<EnumType>> {
+        self.enums.get(name)
+    }
+
+    /// Declares the structs and enums in `ast`, wherever they are, and
+    /// checks every literal and match against them. Nothing is declared if a
+    /// check fails.
+    pub fn check(&mut self, ast: &[Node]) -> Result<(), Error> {
+        let mut declared = Declared {
+            structs: self.structs.clone(),
+            enums: self.enums.clone(),
+        };
+        walk(ast, &mut |node| declare(&mut declared, node))?;
+        walk(ast, &mut |node| check_literal(&declared.structs, node))?;
+        walk(ast, &mut |node| check_variants(&declared.enums, node))?;
+        self.structs = declared.structs;
+        self.enums = declared.enums;
+        Ok(())
+    }
+
+    /// Builds the variant `ty::variant` carrying `values`.
+    pub fn variant(&self, ty: &str, variant: &str, values: Vec<Value>) -> Result<Value, Error> {
+        let enum_type = self.get_enum(ty).ok_or(format!("Unknown enum '{}'", ty))?;
+        let i = enum_type
+            .variant(variant)
+            .filter(|&i| enum_type.variants[i].1 == values.len())
+            .ok_or(format!(
+                "{}::{} does not take {} value(s)",
+                ty,
+                variant,
+                values.len()
+            ))?;
+        Ok(Value::Enum(Rc::new(Variant {
+            ty: enum_type.clone(),
+            variant: i,
+            values,
+        })))
+    }
+
+    /// Builds a `name` record from `values` for the fields `names`, which a
+    /// struct literal may list in any order.
+    pub fn construct(
+        &self,
+        name: &str,
+        names: &[String],
+        values: Vec<Value>,
+    ) -> Result<Value, Error> {
+        let ty = self.get(name).ok_or(format!("Unknown struct '{}'", name))?;
+        let mut fields = vec![None; ty.fields.len()];
+        for (field, value) in names.iter().zip(values) {
+            if let Some(i) = ty.field(field) {
+                fields[i] = Some(value);
+            }
+        }
+        let values = fields
+            .into_iter()
+            .collect::<Option<Vec<_>>>()
+            .filter(|_| names.len() == ty.fields.len())
+            .ok_or(format!("Fields {:?} do not match struct {}", names, name))?;
+        Ok(Value::Struct(Rc::new(Record {
+            ty: ty.clone(),
+            values: RefCell::new(values),
+        })))
+    }
+}
+
+/// Calls `f` on every node of `ast`, parents before their children. The
+/// nodes still to visit are kept on a heap stack of their own, since the
+/// AST nests as deeply as the program does.
+fn walk(ast: &[Node], f: &mut impl FnMut(&Node) -> Result<(), Error>) -> Result<(), Error> {
+    let mut pending: Vec<&Node> = ast.iter().rev().collect();
+    while let Some(node) = pending.pop() {
+        f(node)?;
+        pending.extend(node.children().into_iter().rev());
+    }
+    Ok(())
+}
+
+/// Redeclaring a type is allowed as long as it stays the same, so that a
+/// script can be run twice against the same context.
+fn declare(declared: &mut Declared, node: &Node) -> Result<(), Error> {
+    match node {
+        Node::Struct { name, fields, span } => {
+            if declared.enums.contains_key(name) {
+                return Err(compile_error(*span, format!("{} is already an enum", name)));
+            }
+            declare_struct(&mut declared.structs, name, fields, *span)
+        }
+        Node::Enum {
+            name,
+            variants,
+            span,
+        } => {
+            if declared.structs.contains_key(name) {
+                return Err(compile_error(
+                    *span,
+                    format!("{} is already a struct", name),
+                ));
+            }
+            declare_enum(&mut declared.enums, name, variants, *span)
+        }
+        _ => Ok(()),
+    }
+}
+
+fn declare_struct(
+    structs: &mut HashMap<String, Rc<StructType>>,
+    name: &str,
+    fields: &[String],
+    span: Span,
+) -> Result<(), Error> {
+    if let Some(i) = (1..fields.len()).find(|&i| fields[..i].contains(&fields[i])) {
+        return Err(compile_error(
+            span,
+            format!("Field '{}' of {} is declared twice", fields[i], name),
+        ));
+    }
+    let ty = StructType {
+        name: name.to_string(),
+        fields: fields.to_vec(),
+    };
+    match structs.get(name) {
+        Some(existing) if **existing != ty => Err(compile_error(
+            span,
+            format!("Struct {} is already declared with other fields", name),
+        )),
+        Some(_) => Ok(()),
+        None => {
+            structs.insert(name.to_string(), Rc::new(ty));
+            Ok(())
+        }
+    }
+}
+
+fn declare_enum(
+    enums: &mut HashMap<String, Rc<EnumType>>,
+    name: &str,
+    variants: &[(String, usize)],
+    span: Span,
+) -> Result<(), Error> {
+    if let Some(i) =
+        (1..variants.len()).find(|&i| variants[..i].iter().any(|v| v.0 == variants[i].0))
+    {
+        return Err(compile_error(
+            span,
+            format!("Variant {} of {} is declared twice", variants[i].0, name),
+        ));
+    }
+    let ty = EnumType {
+        name: name.to_string(),
+        variants: variants.to_vec(),
+    };
+    match enums.get(name) {
+        Some(existing) if **existing != ty => Err(compile_error(
+            span,
+            format!("Enum {} is already declared with other variants", name),
+        )),
+        Some(_) => Ok(()),
+        None => {
+            enums.insert(name.to_string(), Rc::new(ty));
+            Ok(())
+        }
+    }
+}
+
+/// A literal has to set every field of a declared struct exactly once.
+fn check_literal(structs: &HashMap<String, Rc<StructType>>, node: &Node) -> Result<(), Error> {
+    let Node::StructLiteral { name, fields, span } = node else {
+        return Ok(());
+    };
+    let ty = structs
+        .get(name)
+        .ok_or_else(|| compile_error(*span, format!("Unknown struct '{}'", name)))?;
+    for (i, field) in fields.iter().enumerate() {
+        if ty.field(&field.name).is_none() {
+            return Err(Error::UnknownField {
+                ty: name.clone(),
+                field: field.name.clone(),
+                span: field.span,
+            });
+        }
+        if fields[..i].iter().any(|other| other.name == field.name) {
+            return Err(compile_error(
+                field.span,
+                format!("Field '{}' is set twice", field.name),
+            ));
+        }
+    }
+    match ty
+        .fields
+        .iter()
+        .find(|f| !fields.iter().any(|field| &field.name == *f))
+    {
+        Some(missing) => Err(compile_error(
+            *span,
+            format!("Missing field '{}' of {}", missing, name),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Variants have to be declared and given as many values as they carry,
+/// and a match has to cover every value its patterns can tell apart.
+fn check_variants(enums: &HashMap<String, Rc<EnumType>>, node: &Node) -> Result<(), Error> {
+    match node {
+        Node::Variant {
+            ty,
+            variant,
+            args,
+            span,
+        } => check_arity(enums, ty, variant, args.len(), *span),
+        Node::Match { arms, span, .. } => {
+            let mut rows = vec![];
+            for arm in arms {
+                check_pattern(enums, &arm.pattern)?;
+                let bindings = arm.pattern.bindings();
+                if let Some(i) = (1..bindings.len()).find(|&i| bindings[..i].contains(&bindings[i]))
+                {
+                    return Err(compile_error(
+                        *span,
+                        format!("'{}' is bound twice in one pattern", bindings[i]),
+                    ));
+                }
+                rows.push(vec![Pat::from(&arm.pattern)]);
+            }
+            match missing(enums, &rows, 1, *span)? {
+                Some(witness) => Err(compile_error(
+                    *span,
+                    format!("Match does not cover {}", witness[0]),
+                )),
+                None => Ok(()),
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+fn check_pattern(enums: &HashMap<String, Rc<EnumType>>, pattern: &Pattern) -> Result<(), Error> {
+    if let Pattern::Variant {
+        ty,
+        variant,
+        args,
+        span,
+    } = pattern
+    {
+        check_arity(enums, ty, variant, args.len(), *span)?;
+        for arg in args {
+            check_pattern(enums, arg)?;
+        }
+    }
+    Ok(())
+}
+
+fn check_arity(
+    enums: &HashMap<String, Rc<EnumType>>,
+    ty: &str,
+    variant: &str,
+    count: usize,
+    span: Span,
+) -> Result<(), Error> {
+    let enum_type = enums
+        .get(ty)
+        .ok_or_else(|| compile_error(span, format!("Unknown enum '{}'", ty)))?;
+    let (_, arity) = enum_type
+        .variants
+        .iter()
+        .find(|(name, _)| name == variant)
+        .ok_or_else(|| compile_error(span, format!("{} has no variant {}", ty, variant)))?;
+    if *arity != count {
+        return Err(compile_error(
+            span,
+            format!(
+                "{}::{} takes {} value(s), got {}",
+                ty, variant, arity, count
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// A pattern as far as exhaustiveness goes: bindings are wildcards, and
+/// only bools among the literals have a finite set of values.
+#[derive(Clone)]
+enum Pat<'a> {
+    Any,
+    Bool(bool),
+    Other,
+    Variant(&'a str, &'a str, Vec<Pat<'a>>),
+}
+
+impl<'a> From<&'a Pattern> for Pat<'a> {
+    fn from(pattern: &'a Pattern) -> Self {
+        match pattern {
+            Pattern::Wildcard | Pattern::Bind(_) => Pat::Any,
+            Pattern::Literal(Value::Bool(b)) => Pat::Bool(*b),
+            Pattern::Literal(_) => Pat::Other,
+            Pattern::Variant {
+                ty, variant, args, ..
+            } => Pat::Variant(ty, variant, args.iter().map(Pat::from).collect()),
+        }
+    }
+}
+
+/// One way a column of patterns can be matched: a variant, or a bool.
+enum Constructor<'a> {
+    Variant(&'a str, &'a str, usize),
+    Bool(bool),
+}
+
+impl Constructor<'_> {
+    fn arity(&self) -> usize {
+        match self {
+            Constructor::Variant(_, _, arity) => *arity,
+            Constructor::Bool(_) => 0,
+        }
+    }
+
+    /// The rows that still apply once the first value is known to be built
+    /// by `self`, with that value replaced by the ones it carries.
+    fn specialize<'a>(&self, rows: &[Vec<Pat<'a>>]) -> Vec<Vec<Pat<'a>>> {
+        rows.iter()
+            .filter_map(|row| {
+                let head = match (&row[0], self) {
+                    (Pat::Any, _) => vec![Pat::Any; self.arity()],
+                    (Pat::Variant(_, v, args), Constructor::Variant(_, variant, _))
+                        if v == variant =>
+                    {
+                        args.clone()
+                    }
+                    (Pat::Bool(b), Constructor::Bool(bool)) if b == bool => vec![],
+                    _ => return None,
+                };
+                Some(head.into_iter().chain(row[1..].iter().cloned()).collect())
+            })
+            .collect()
+    }
+
+    /// Shows a value built by `self` from values shown as `args`.
+    fn show(&self, args: &[String]) -> String {
+        match self {
+            Constructor::Bool(b) => b.to_string(),
+            Constructor::Variant(ty, variant, _) if args.is_empty() => {
+                format!("{}::{}", ty, variant)
+            }
+            Constructor::Variant(ty, variant, _) => {
+                format!("{}::{}({})", ty, variant, args.join(", "))
+            }
+        }
+    }
+}
+
+/// A row of `width` values that none of `rows` matches, shown as patterns,
+/// or `None` if the rows cover everything. Columns are taken one at a time:
+/// if the first column names every constructor of its type, each one is
+/// tried in turn, otherwise a value none of them builds is.
+fn missing(
+    enums: &HashMap<String, Rc<EnumType>>,
+    rows: &[Vec<Pat>],
+    width: usize,
+    span: Span,
+) -> Result<Option<Vec<String>>, Error> {
+    if width == 0 {
+        return Ok(rows.is_empty().then(Vec::new));
+    }
+    let mut all = vec![];
+    let mut seen = vec![];
+    for row in rows {
+        match &row[0] {
+            Pat::Variant(ty, variant, _) => {
+                if let Some(Constructor::Variant(other, ..)) = all.first() {
+                    if other != ty {
+                        return Err(compile_error(
+                            span,
+                            format!("Patterns of {} and {} cannot be mixed", other, ty),
+                        ));
+                    }
+                }
+                if all.is_empty() {
+                    all = enums[*ty]
+                        .variants
+                        .iter()
+                        .map(|(name, arity)| Constructor::Variant(ty, name, *arity))
+                        .collect();
+                }
+                seen.push(*variant);
+            }
+            Pat::Bool(b) => seen.push(if *b { "true" } else { "false" }),
+            Pat::Any | Pat::Other => {}
+        }
+    }
+    if all.is_empty() && !seen.is_empty() {
+        all = vec![Constructor::Bool(true), Constructor::Bool(false)];
+    }
+    let name = |constructor: &Constructor| match constructor {
+        Constructor::Variant(_, variant, _) => variant.to_string(),
+        Constructor::Bool(b) => b.to_string(),
+    };
+    let absent = all.iter().find(|c| !seen.contains(&name(c).as_str()));
+    match absent {
+        None if !all.is_empty() => {
+            for constructor in &all {
+                let arity = constructor.arity();
+                let rows = constructor.specialize(rows);
+                if let Some(mut witness) = missing(enums, &rows, arity + width - 1, span)? {
+                    let rest = witness.split_off(arity);
+                    let head = constructor.show(&witness);
+                    return Ok(Some(std::iter::once(head).chain(rest).collect()));
+                }
+            }
+            Ok(None)
+        }
+        _ => {
+            let rows: Vec<_> = rows
+                .iter()
+                .filter(|row| matches!(row[0], Pat::Any))
+                .map(|row| row[1..].to_vec())
+                .collect();
+            let Some(witness) = missing(enums, &rows, width - 1, span)? else {
+                return Ok(None);
+            };
+            let head = match absent {
+                Some(constructor) => constructor.show(&vec!["_".to_string(); constructor.arity()]),
+                None => "_".to_string(),
+            };
+            Ok(Some(std::iter::once(head).chain(witness).collect()))
+        }
+    }
+}
+
+fn compile_error(span: Span, message: String) -> Error {
+    Error::Compile { message, span }
+}
+
+#[cfg(test)]
+mod types_tests {
+    use super::*;
+    use crate::parser::parse_str_strict;
+
+    fn check(types: &mut Types, source: &str) -> Result<(), Error> {
+        types.check(&parse_str_strict(source).unwrap())
+    }
+
+    fn compile_error(message: &str, line: usize, column: usize) -> Result<(), Error> {
+        Err(Error::Compile {
+            message: message.to_string(),
+            span: Span { line, column },
+        })
+    }
+
+    #[test]
+    fn declarations_persist_between_checks() {
+        let mut types = Types::default();
+        assert_eq!(check(&mut types, "struct P { x, y }"), Ok(()));
+        assert_eq!(check(&mut types, "P { y: 1, x: 2 };"), Ok(()));
+        assert_eq!(check(&mut types, "struct P { x, y }"), Ok(()));
+        assert_eq!(
+            check(&mut types, "\nstruct P { y }"),
+            compile_error("Struct P is already declared with other fields", 2, 8)
+        );
+    }
+
+    #[test]
+    fn literals_must_set_each_declared_field_once() {
+        let mut types = Types::default();
+        assert_eq!(
+            check(&mut types, "struct P { x } fn f() { P { x: 1, y: 2 } }"),
+            Err(Error::UnknownField {
+                ty: "P".to_string(),
+                field: "y".to_string(),
+                span: Span {
+                    line: 1,
+                    column: 35
+                }
+            })
+        );
+        assert_eq!(
+            check(&mut types, "struct P { x } P { x: 1, x: 2 };"),
+            compile_error("Field 'x' is set twice", 1, 26)
+        );
+        assert_eq!(
+            check(&mut types, "struct P { x } P {};"),
+            compile_error("Missing field 'x' of P", 1, 16)
+        );
+        assert_eq!(
+            check(&mut types, "Q {};"),
+            compile_error("Unknown struct 'Q'", 1, 1)
+        );
+    }
+
+    #[test]
+    fn failed_checks_declare_nothing() {
+        let mut types = Types::default();
+        assert!(check(&mut types, "struct P { x } Q {};").is_err());
+        assert!(types.get("P").is_none());
+    }
+
+    #[test]
+    fn enums_and_structs_share_one_namespace() {
+        let mut types = Types::default();
+        assert_eq!(
+            check(&mut types, "enum E { A, B(x) } enum E { A, B(y) }"),
+            Ok(())
+        );
+        assert_eq!(
+            check(&mut types, "enum E { A }"),
+            compile_error("Enum E is already declared with other variants", 1, 6)
+        );
+        assert_eq!(
+            check(&mut types, "struct E { x }"),
+            compile_error("E is already an enum", 1, 8)
+        );
+        assert_eq!(
+            check(&mut types, "enum F { A, A(x) }"),
+            compile_error("Variant A of F is declared twice", 1, 6)
+        );
+        assert_eq!(
+            check(&mut types, "E::C;"),
+            compile_error("E has no variant C", 1, 1)
+        );
+    }
+
+    #[test]
+    fn matches_must_cover_every_value() {
+        let mut types = Types::default();
+        check(&mut types, "enum O { S(v), N } enum T { A, B, C }").unwrap();
+        let covered = [
+            "match x { _ => 1 };",
+            "match x { O::S(_) => 1, O::N => 2 };",
+            "match x { O::S(O::S(_)) => 1, O::S(O::N) => 2, O::N => 3 };",
+            "match x { true => 1, false => 2 };",
+            "match x { O::S(true) => 1, O::S(false) => 2, O::S(y) => y, O::N => 3 };",
+            "match x { 1 => 1, n => n };",
+        ];
+        for source in covered {
+            assert_eq!(check(&mut types, source), Ok(()), "{}", source);
+        }
+        let uncovered = [
+            ("match x { O::S(_) => 1 };", "O::N"),
+            ("match x { O::S(O::N) => 1, O::N => 2 };", "O::S(O::S(_))"),
+            ("match x { T::A => 1, T::C => 2 };", "T::B"),
+            ("match x { true => 1 };", "false"),
+            ("match x { 1 => 1, \"a\" => 2 };", "_"),
+        ];
+        for (source, missing) in uncovered {
+            assert_eq!(
+                check(&mut types, source),
+                compile_error(&format!("Match does not cover {}", missing), 1, 1)
+            );
+        }
+        assert_eq!(
+            check(&mut types, "match x { O::N => 1, T::A => 2, _ => 3 };"),
+            compile_error("Patterns of O and T cannot be mixed", 1, 1)
+        );
+    }
+
+    #[test]
+    fn construct_orders_fields_by_declaration() {
+        let mut types = Types::default();
+        check(&mut types, "struct P { x, y }").unwrap();
+        let names = ["y".to_string(), "x".to_string()];
+        let p = types
+            .construct("P", &names, vec![Value::Int(2), Value::Int(1)])
+            .unwrap();
+        assert_eq!(p.to_string(), "P { x: 1, y: 2 }");
+        assert_eq!(p.field("y", Span::default()), Ok(Value::Int(2)));
+        assert!(types
+            .construct("P", &names[..1], vec![Value::Int(2)])
+            .is_err());
+    }
+}
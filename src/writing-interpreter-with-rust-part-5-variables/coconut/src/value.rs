@@ -0,0 +1,886 @@
+use std::{cell::RefCell, cmp::Ordering, collections::BTreeMap, fmt, rc::Rc};
+
+#[cfg(feature = "bigint")]
+use crate::bigint::BigUint;
+use crate::{
+    ast::{Operator, Span},
+    error::Error,
+    function::Closure,
+    module::Module,
+    types::{Record, Variant},
+};
+
+/// A runtime value, shared by the scope, the VM stack and host functions.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum Value {
+    Int(u64),
+    /// An int too large for a `u64`, from arithmetic that overflowed one
+    /// or from a literal.
+    #[cfg(feature = "bigint")]
+    Big(BigUint),
+    Float(f64),
+    Str(String),
+    Bool(bool),
+    /// Shared and mutable: copies of an array value all see `push`, `pop`
+    /// and index assignments made through any of them.
+    Array(Rc<RefCell<Vec<Value>>>),
+    /// Shared like arrays. Keys are kept sorted, so iterating a map is
+    /// deterministic and doesn't depend on the backend.
+    Map(Rc<RefCell<BTreeMap<Key, Value>>>),
+    /// `start..end`, the ints from `start` up to but excluding `end`.
+    Range(u64, u64),
+    Struct(Rc<Record>),
+    /// A variant of an `enum`. Immutable, unlike structs.
+    Enum(Rc<Variant>),
+    Function(Rc<Closure>),
+    Module(Rc<Module>),
+    /// The result of a function that doesn't return anything.
+    Nil,
+}
+
+/// The values that can be map keys: floats can't, having no total order,
+/// and neither can the mutable collections.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum Key {
+    Int(u64),
+    #[cfg(feature = "bigint")]
+    Big(BigUint),
+    Str(String),
+    Bool(bool),
+}
+
+impl From<Key> for Value {
+    fn from(key: Key) -> Self {
+        match key {
+            Key::Int(n) => Value::Int(n),
+            #[cfg(feature = "bigint")]
+            Key::Big(n) => Value::Big(n),
+            Key::Str(s) => Value::Str(s),
+            Key::Bool(b) => Value::Bool(b),
+        }
+    }
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "int",
+            #[cfg(feature = "bigint")]
+            Value::Big(_) => "int",
+            Value::Float(_) => "float",
+            Value::Str(_) => "string",
+            Value::Bool(_) => "bool",
+            Value::Array(_) => "array",
+            Value::Map(_) => "map",
+            Value::Range(..) => "range",
+            Value::Struct(_) => "struct",
+            Value::Enum(_) => "enum",
+            Value::Function(_) => "function",
+            Value::Module(_) => "module",
+            Value::Nil => "nil",
+        }
+    }
+
+    /// Whether this is an int, however large.
+    pub fn is_int(&self) -> bool {
+        match self {
+            Value::Int(_) => true,
+            #[cfg(feature = "bigint")]
+            Value::Big(_) => true,
+            _ => false,
+        }
+    }
+
+    pub fn as_int(&self) -> Result<u64, String> {
+        match self {
+            Value::Int(n) => Ok(*n),
+            #[cfg(feature = "bigint")]
+            Value::Big(n) => Err(format!("Integer {} is too large", n)),
+            _ => Err(format!("Expected int, got {}", self.type_name())),
+        }
+    }
+
+    pub fn as_str(&self) -> Result<&str, String> {
+        match self {
+            Value::Str(s) => Ok(s),
+            _ => Err(format!("Expected string, got {}", self.type_name())),
+        }
+    }
+
+    pub fn array(items: Vec<Value>) -> Value {
+        Value::Array(Rc::new(RefCell::new(items)))
+    }
+
+    pub fn map(entries: BTreeMap<Key, Value>) -> Value {
+        Value::Map(Rc::new(RefCell::new(entries)))
+    }
+
+    /// `self..end`.
+    pub fn range(&self, end: &Value) -> Result<Value, String> {
+        match (self, end) {
+            (Value::Int(start), Value::Int(end)) => Ok(Value::Range(*start, *end)),
+            _ => Err(self.binary_error("make a range of", end)),
+        }
+    }
+
+    pub fn key(&self) -> Result<Key, String> {
+        match self {
+            Value::Int(n) => Ok(Key::Int(*n)),
+            #[cfg(feature = "bigint")]
+            Value::Big(n) => Ok(Key::Big(n.clone())),
+            Value::Str(s) => Ok(Key::Str(s.clone())),
+            Value::Bool(b) => Ok(Key::Bool(*b)),
+            _ => Err(format!("Cannot use {} as a map key", self.type_name())),
+        }
+    }
+
+    /// `target[index]`: arrays and strings (by character) take an int
+    /// index, maps a key that must be present.
+    pub fn index(&self, index: &Value) -> Result<Value, Error> {
+        match self {
+            Value::Array(items) => {
+                let items = items.borrow();
+                let i = position(index.as_int()?, items.len())?;
+                Ok(items[i].clone())
+            }
+            Value::Str(s) => {
+                let i = position(index.as_int()?, s.chars().count())?;
+                Ok(Value::Str(s.chars().nth(i).into_iter().collect()))
+            }
+            Value::Map(entries) => match entries.borrow().get(&index.key()?) {
+                Some(value) => Ok(value.clone()),
+                None => Err(format!("Key {} not found", Nested(index)).into()),
+            },
+            Value::Range(start, end) => {
+                let i = position(index.as_int()?, end.saturating_sub(*start) as usize)?;
+                Ok(Value::Int(start + i as u64))
+            }
+            _ => Err(format!("Cannot index {}", self.type_name()).into()),
+        }
+    }
+
+    /// `self.field`; `span` is where the field is named.
+    pub fn field(&self, field: &str, span: Span) -> Result<Value, Error> {
+        if let Value::Module(module) = self {
+            return module.get(field, span);
+        }
+        let record = self.record(field)?;
+        let i = field_index(record, field, span)?;
+        let value = record.values.borrow()[i].clone();
+        Ok(value)
+    }
+
+    /// `self.field = value`.
+    pub fn set_field(&self, field: &str, value: Value, span: Span) -> Result<(), Error> {
+        if let Value::Module(module) = self {
+            return Err(format!("Cannot assign to {}.{} outside of it", module.name, field).into());
+        }
+        let record = self.record(field)?;
+        let i = field_index(record, field, span)?;
+        value.check_insertable(self)?;
+        record.values.borrow_mut()[i] = value;
+        Ok(())
+    }
+
+    /// The values `self` carries if it is the variant `ty::variant`.
+    pub fn variant_values(&self, ty: &str, variant: &str) -> Option<&[Value]> {
+        match self {
+            Value::Enum(value) if value.ty.name == ty && value.name() == variant => {
+                Some(&value.values)
+            }
+            _ => None,
+        }
+    }
+
+    fn record(&self, field: &str) -> Result<&Record, Error> {
+        match self {
+            Value::Struct(record) => Ok(record),
+            _ => Err(format!("Cannot access field '{}' of {}", field, self.type_name()).into()),
+        }
+    }
+
+    /// `target[index] = value`; on a map this inserts or replaces the key.
+    pub fn set_index(&self, index: &Value, value: Value) -> Result<(), Error> {
+        match self {
+            Value::Array(items) => {
+                let i = position(index.as_int()?, items.borrow().len())?;
+                value.check_insertable(self)?;
+                items.borrow_mut()[i] = value;
+            }
+            Value::Map(entries) => {
+                let key = index.key()?;
+                value.check_insertable(self)?;
+                entries.borrow_mut().insert(key, value);
+            }
+            _ => return Err(format!("Cannot assign to an index of {}", self.type_name()).into()),
+        }
+        Ok(())
+    }
+
+    /// `item in self`: a key of a map, an element of an array or a
+    /// substring of a string.
+    pub fn has(&self, item: &Value) -> Result<Value, String> {
+        let found = match self {
+            Value::Map(entries) => entries.borrow().contains_key(&item.key()?),
+            Value::Array(items) => items.borrow().iter().any(|v| v.is_equal(item)),
+            Value::Str(s) => s.contains(item.as_str()?),
+            Value::Range(start, end) => match item {
+                Value::Int(n) => (*start..*end).contains(n),
+                Value::Float(x) => x.fract() == 0.0 && *x >= *start as f64 && *x < *end as f64,
+                _ => false,
+            },
+            _ => return Err(format!("Cannot search in {}", self.type_name())),
+        };
+        Ok(Value::Bool(found))
+    }
+
+    /// What a `for` loop over `self` steps through with `nth`: ranges and
+    /// arrays themselves, so an array growing inside the loop is iterated
+    /// to its new end, and a snapshot of a string's characters or a map's
+    /// keys.
+    pub fn iterable(&self) -> Result<Value, Error> {
+        match self {
+            Value::Range(..) | Value::Array(_) => Ok(self.clone()),
+            Value::Str(s) => Ok(Value::array(
+                s.chars().map(|c| c.to_string().into()).collect(),
+            )),
+            Value::Map(_) => self.keys(),
+            _ => Err(format!("Cannot iterate over {}", self.type_name()).into()),
+        }
+    }
+
+    /// The `n`th item of an `iterable`, `None` past its end.
+    pub fn nth(&self, n: u64) -> Option<Value> {
+        match self {
+            Value::Range(start, end) => start.checked_add(n).filter(|i| i < end).map(Value::Int),
+            Value::Array(items) => items.borrow().get(usize::try_from(n).ok()?).cloned(),
+            _ => None,
+        }
+    }
+
+    /// `m[k]` if `k` is in the map, otherwise `default`.
+    pub fn get(&self, key: &Value, default: Value) -> Result<Value, Error> {
+        let Value::Map(entries) = self else {
+            return Err(format!("get: Expected map, got {}", self.type_name()).into());
+        };
+        Ok(entries
+            .borrow()
+            .get(&key.key()?)
+            .cloned()
+            .unwrap_or(default))
+    }
+
+    /// A map's keys in order, as an array.
+    pub fn keys(&self) -> Result<Value, Error> {
+        let Value::Map(entries) = self else {
+            return Err(format!("keys: Expected map, got {}", self.type_name()).into());
+        };
+        let keys = entries.borrow().keys().cloned().map(Value::from).collect();
+        Ok(Value::array(keys))
+    }
+
+    /// Appends to an array and returns its new length.
+    pub fn push(&self, value: Value) -> Result<Value, Error> {
+        let Value::Array(items) = self else {
+            return Err(format!("push: Expected array, got {}", self.type_name()).into());
+        };
+        value.check_insertable(self)?;
+        items.borrow_mut().push(value);
+        Ok(Value::Int(items.borrow().len() as u64))
+    }
+
+    pub fn pop(&self) -> Result<Value, Error> {
+        match self {
+            Value::Array(items) => Ok(items
+                .borrow_mut()
+                .pop()
+                .ok_or("pop: empty array".to_string())?),
+            _ => Err(format!("pop: Expected array, got {}", self.type_name()).into()),
+        }
+    }
+
+    /// Collections may not end up inside themselves: printing, comparing
+    /// or dropping a cyclic one would never finish.
+    fn check_insertable(&self, collection: &Value) -> Result<(), Error> {
+        if self.contains(collection) {
+            let article = if let Value::Array(_) = collection {
+                "an"
+            } else {
+                "a"
+            };
+            return Err(format!(
+                "Cannot insert {} {} into itself",
+                article,
+                collection.type_name()
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Whether `collection` is `self` or nested anywhere inside it.
+    fn contains(&self, collection: &Value) -> bool {
+        match (self, collection) {
+            (Value::Array(items), Value::Array(other)) if Rc::ptr_eq(items, other) => true,
+            (Value::Map(entries), Value::Map(other)) if Rc::ptr_eq(entries, other) => true,
+            (Value::Struct(record), Value::Struct(other)) if Rc::ptr_eq(record, other) => true,
+            (Value::Array(items), _) => items.borrow().iter().any(|v| v.contains(collection)),
+            (Value::Map(entries), _) => entries.borrow().values().any(|v| v.contains(collection)),
+            (Value::Enum(value), _) => value.values.iter().any(|v| v.contains(collection)),
+            (Value::Struct(record), _) => record
+                .values
+                .borrow()
+                .iter()
+                .any(|v| v.contains(collection)),
+            _ => false,
+        }
+    }
+
+    /// Ints are promoted to floats when mixed with them.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(n) => Some(*n as f64),
+            #[cfg(feature = "bigint")]
+            Value::Big(n) => Some(n.to_f64()),
+            Value::Float(f) => Some(*f),
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "bigint")]
+    fn as_big(&self) -> Option<BigUint> {
+        match self {
+            Value::Int(n) => Some(BigUint::from(*n)),
+            Value::Big(n) => Some(n.clone()),
+            _ => None,
+        }
+    }
+
+    /// `+`: addition or string concatenation.
+    pub fn add(&self, rhs: &Value) -> Result<Value, String> {
+        match (self, rhs) {
+            (Value::Str(l), Value::Str(r)) => Ok(Value::Str(format!("{}{}", l, r))),
+            _ => self
+                .int_op(rhs, Operator::Add, u64::checked_add)
+                .unwrap_or_else(|| self.float_op(rhs, "add", |l, r| l + r)),
+        }
+    }
+
+    /// `-`: ints are unsigned, so going below zero overflows.
+    pub fn sub(&self, rhs: &Value) -> Result<Value, String> {
+        self.int_op(rhs, Operator::Sub, u64::checked_sub)
+            .unwrap_or_else(|| self.float_op(rhs, "subtract", |l, r| l - r))
+    }
+
+    pub fn mul(&self, rhs: &Value) -> Result<Value, String> {
+        self.int_op(rhs, Operator::Mul, u64::checked_mul)
+            .unwrap_or_else(|| self.float_op(rhs, "multiply", |l, r| l * r))
+    }
+
+    /// `/`: integer division truncates, float division follows IEEE 754.
+    pub fn div(&self, rhs: &Value) -> Result<Value, String> {
+        if self.is_int() && rhs == &Value::Int(0) {
+            return Err("Division by zero".to_string());
+        }
+        self.int_op(rhs, Operator::Div, u64::checked_div)
+            .unwrap_or_else(|| self.float_op(rhs, "divide", |l, r| l / r))
+    }
+
+    pub fn rem(&self, rhs: &Value) -> Result<Value, String> {
+        if self.is_int() && rhs == &Value::Int(0) {
+            return Err("Division by zero".to_string());
+        }
+        self.int_op(rhs, Operator::Rem, u64::checked_rem)
+            .unwrap_or_else(|| self.float_op(rhs, "take the remainder of", |l, r| l % r))
+    }
+
+    /// Fuel an operator on `self` and `rhs` costs beyond its tick. Work on
+    /// two strings grows with their combined length, which `+` copies into
+    /// a new string, so they cost a unit per byte.
+    pub fn cost(&self, rhs: &Value) -> u64 {
+        match (self, rhs) {
+            (Value::Str(l), Value::Str(r)) => (l.len() + r.len()) as u64,
+            _ => self.big_cost(rhs),
+        }
+    }
+
+    /// Work on big ints grows with their length in limbs, up to the product
+    /// of both lengths for multiplication and division, so that is what it
+    /// costs.
+    #[cfg(feature = "bigint")]
+    fn big_cost(&self, rhs: &Value) -> u64 {
+        let limbs = |value: &Value| match value {
+            Value::Big(n) => n.limbs() as u64,
+            _ => 1,
+        };
+        match (self, rhs) {
+            (Value::Big(_), _) | (_, Value::Big(_)) => limbs(self) * limbs(rhs),
+            _ => 0,
+        }
+    }
+
+    #[cfg(not(feature = "bigint"))]
+    fn big_cost(&self, _: &Value) -> u64 {
+        0
+    }
+
+    /// Applies `int` when both operands are ints. When that overflows, or
+    /// when an operand is already big, `op` is done on big ints with the
+    /// `bigint` feature; without it the result is an overflow error. `None`
+    /// if either operand isn't an int.
+    fn int_op(
+        &self,
+        rhs: &Value,
+        op: Operator,
+        int: fn(u64, u64) -> Option<u64>,
+    ) -> Option<Result<Value, String>> {
+        if !self.is_int() || !rhs.is_int() {
+            return None;
+        }
+        let result = match (self, rhs) {
+            (Value::Int(l), Value::Int(r)) => int(*l, *r).map(Value::Int),
+            _ => None,
+        };
+        let result = result.or_else(|| self.big_op(op, rhs));
+        Some(result.ok_or("overflowed".to_string()))
+    }
+
+    #[cfg(feature = "bigint")]
+    fn big_op(&self, op: Operator, rhs: &Value) -> Option<Value> {
+        let (l, r) = (self.as_big()?, rhs.as_big()?);
+        let result = match op {
+            Operator::Add => Some(l.add(&r)),
+            Operator::Sub => l.checked_sub(&r),
+            Operator::Mul => Some(l.mul(&r)),
+            Operator::Div => l.checked_div_rem(&r).map(|(q, _)| q),
+            Operator::Rem => l.checked_div_rem(&r).map(|(_, r)| r),
+        };
+        result.map(Value::from)
+    }
+
+    #[cfg(not(feature = "bigint"))]
+    fn big_op(&self, _: Operator, _: &Value) -> Option<Value> {
+        None
+    }
+
+    fn float_op(&self, rhs: &Value, op: &str, f: fn(f64, f64) -> f64) -> Result<Value, String> {
+        match (self.as_f64(), rhs.as_f64()) {
+            (Some(l), Some(r)) => Ok(Value::Float(f(l, r))),
+            _ => Err(self.binary_error(op, rhs)),
+        }
+    }
+
+    /// `==`: numbers compare by value, other values of different types are
+    /// never equal.
+    pub fn equals(&self, rhs: &Value) -> Value {
+        Value::Bool(self.is_equal(rhs))
+    }
+
+    pub fn not_equals(&self, rhs: &Value) -> Value {
+        Value::Bool(!self.is_equal(rhs))
+    }
+
+    /// Ints and floats are equal only if they are exactly the same number,
+    /// not if the int merely rounds to the float.
+    fn is_equal(&self, rhs: &Value) -> bool {
+        match (self, rhs) {
+            (Value::Float(l), Value::Float(r)) => l == r,
+            (Value::Int(n), Value::Float(x)) | (Value::Float(x), Value::Int(n)) => {
+                x.fract() == 0.0 && *x >= 0.0 && *x < 18446744073709551616.0 && *x as u64 == *n
+            }
+            #[cfg(feature = "bigint")]
+            (Value::Big(n), Value::Float(x)) | (Value::Float(x), Value::Big(n)) => {
+                BigUint::from_f64(*x).as_ref() == Some(n)
+            }
+            (Value::Float(_), _) | (_, Value::Float(_)) => false,
+            _ => self == rhs,
+        }
+    }
+
+    pub fn lt(&self, rhs: &Value) -> Result<Value, String> {
+        Ok(Value::Bool(self.compare(rhs)? == Some(Ordering::Less)))
+    }
+
+    pub fn le(&self, rhs: &Value) -> Result<Value, String> {
+        let ordering = self.compare(rhs)?;
+        Ok(Value::Bool(matches!(
+            ordering,
+            Some(Ordering::Less | Ordering::Equal)
+        )))
+    }
+
+    pub fn gt(&self, rhs: &Value) -> Result<Value, String> {
+        Ok(Value::Bool(self.compare(rhs)? == Some(Ordering::Greater)))
+    }
+
+    pub fn ge(&self, rhs: &Value) -> Result<Value, String> {
+        let ordering = self.compare(rhs)?;
+        Ok(Value::Bool(matches!(
+            ordering,
+            Some(Ordering::Greater | Ordering::Equal)
+        )))
+    }
+
+    /// Numbers compare numerically and strings lexicographically. `None`
+    /// means unordered, which only happens with NaN.
+    pub fn compare(&self, rhs: &Value) -> Result<Option<Ordering>, String> {
+        match (self, rhs) {
+            (Value::Int(l), Value::Int(r)) => Ok(Some(l.cmp(r))),
+            (Value::Str(l), Value::Str(r)) => Ok(Some(l.cmp(r))),
+            #[cfg(feature = "bigint")]
+            (Value::Int(_) | Value::Big(_), Value::Int(_) | Value::Big(_)) => {
+                Ok(self.as_big().zip(rhs.as_big()).map(|(l, r)| l.cmp(&r)))
+            }
+            _ => match (self.as_f64(), rhs.as_f64()) {
+                (Some(l), Some(r)) => Ok(l.partial_cmp(&r)),
+                _ => Err(self.binary_error("compare", rhs)),
+            },
+        }
+    }
+
+    fn binary_error(&self, op: &str, rhs: &Value) -> String {
+        format!("Cannot {} {} and {}", op, self.type_name(), rhs.type_name())
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{}", n),
+            #[cfg(feature = "bigint")]
+            Value::Big(n) => write!(f, "{}", n),
+            // Debug keeps the `.0` on whole floats, so 2.0 doesn't print as 2.
+            Value::Float(x) => write!(f, "{:?}", x),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", Nested(item))?;
+                }
+                write!(f, "]")
+            }
+            Value::Range(start, end) => write!(f, "{}..{}", start, end),
+            Value::Struct(record) => {
+                write!(f, "{} {{", record.ty.name)?;
+                let values = record.values.borrow();
+                for (i, (field, value)) in record.ty.fields.iter().zip(values.iter()).enumerate() {
+                    let separator = if i > 0 { "," } else { "" };
+                    write!(f, "{} {}: {}", separator, field, Nested(value))?;
+                }
+                if values.is_empty() {
+                    write!(f, "}}")
+                } else {
+                    write!(f, " }}")
+                }
+            }
+            Value::Enum(value) => {
+                write!(f, "{}::{}", value.ty.name, value.name())?;
+                if value.values.is_empty() {
+                    return Ok(());
+                }
+                write!(f, "(")?;
+                for (i, item) in value.values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", Nested(item))?;
+                }
+                write!(f, ")")
+            }
+            Value::Function(closure) => write!(f, "<fn {}>", closure.name()),
+            Value::Module(module) => write!(f, "<module {}>", module.name),
+            Value::Nil => write!(f, "nil"),
+            Value::Map(entries) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in entries.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    let key = Value::from(key.clone());
+                    write!(f, "{}: {}", Nested(&key), Nested(value))?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+impl From<u64> for Value {
+    fn from(n: u64) -> Self {
+        Value::Int(n)
+    }
+}
+
+/// Displays a value inside a collection or an error message, where strings
+/// are quoted so that `["1"]` and `[1]` differ.
+struct Nested<'a>(&'a Value);
+
+impl fmt::Display for Nested<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Value::Str(s) => write!(f, "{:?}", s),
+            value => write!(f, "{}", value),
+        }
+    }
+}
+
+fn field_index(record: &Record, field: &str, span: Span) -> Result<usize, Error> {
+    record.ty.field(field).ok_or_else(|| Error::UnknownField {
+        ty: record.ty.name.clone(),
+        field: field.to_string(),
+        span,
+    })
+}
+
+/// Bounds-checks an index into something of length `len`.
+fn position(index: u64, len: usize) -> Result<usize, Error> {
+    match usize::try_from(index) {
+        Ok(i) if i < len => Ok(i),
+        _ => Err(Error::IndexOutOfBounds { index, len }),
+    }
+}
+
+/// Big values that fit in a `u64` become plain ints again.
+#[cfg(feature = "bigint")]
+impl From<BigUint> for Value {
+    fn from(n: BigUint) -> Self {
+        match n.to_u64() {
+            Some(n) => Value::Int(n),
+            None => Value::Big(n),
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(f: f64) -> Self {
+        Value::Float(f)
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::Str(s)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::Str(s.to_string())
+    }
+}
+
+#[cfg(test)]
+mod value_tests {
+    use super::*;
+
+    #[test]
+    fn add_concatenates_strings() {
+        assert_eq!(Value::from("ab").add(&"c".into()), Ok("abc".into()));
+        assert_eq!(
+            Value::from("a").add(&Value::Int(1)),
+            Err("Cannot add string and int".to_string())
+        );
+    }
+
+    #[test]
+    fn equals_compares_across_types() {
+        assert_eq!(Value::Int(1).equals(&Value::Int(1)), Value::Bool(true));
+        assert_eq!(Value::Int(1).equals(&"1".into()), Value::Bool(false));
+        assert_eq!(Value::Int(1).equals(&Value::Float(1.0)), Value::Bool(true));
+        assert_eq!(Value::Float(0.5).equals(&Value::Float(0.5)), Value::Bool(true));
+        assert_eq!(
+            Value::Int(9007199254740993).equals(&Value::Float(9007199254740992.0)),
+            Value::Bool(false)
+        );
+        assert_eq!(
+            Value::Int(u64::MAX).equals(&Value::Float(2f64.powi(64))),
+            Value::Bool(false)
+        );
+        assert_eq!(
+            Value::Float(f64::NAN).not_equals(&Value::Float(f64::NAN)),
+            Value::Bool(true)
+        );
+    }
+
+    #[test]
+    fn mixed_arithmetic_promotes_to_float() {
+        assert_eq!(Value::Int(1).add(&Value::Float(0.5)), Ok(Value::Float(1.5)));
+        assert_eq!(Value::Float(3.0).div(&Value::Int(2)), Ok(Value::Float(1.5)));
+        assert_eq!(Value::Int(3).div(&Value::Int(2)), Ok(Value::Int(1)));
+        assert_eq!(
+            Value::Float(1.0).div(&Value::Int(0)),
+            Ok(Value::Float(f64::INFINITY))
+        );
+    }
+
+    #[test]
+    fn int_arithmetic_reports_errors() {
+        assert_eq!(
+            Value::Int(1).sub(&Value::Int(2)),
+            Err("overflowed".to_string())
+        );
+        assert_eq!(
+            Value::Int(1).rem(&Value::Int(0)),
+            Err("Division by zero".to_string())
+        );
+        assert_eq!(
+            Value::from("a").sub(&Value::Float(1.0)),
+            Err("Cannot subtract string and float".to_string())
+        );
+        assert_eq!(
+            Value::from("a").rem(&Value::Int(2)),
+            Err("Cannot take the remainder of string and int".to_string())
+        );
+    }
+
+    #[cfg(not(feature = "bigint"))]
+    #[test]
+    fn int_overflow_is_an_error() {
+        assert_eq!(
+            Value::Int(u64::MAX).add(&Value::Int(1)),
+            Err("overflowed".to_string())
+        );
+    }
+
+    #[cfg(feature = "bigint")]
+    #[test]
+    fn int_overflow_promotes_to_big() {
+        let big = Value::Int(u64::MAX).add(&Value::Int(1)).unwrap();
+        assert_eq!(big.to_string(), "18446744073709551616");
+        assert_eq!(big.type_name(), "int");
+        assert_eq!(big.sub(&Value::Int(1)), Ok(Value::Int(u64::MAX)));
+        assert_eq!(big.div(&Value::Int(2)), Ok(Value::Int(1 << 63)));
+        assert_eq!(big.rem(&Value::Int(0)), Err("Division by zero".to_string()));
+        assert_eq!(big.gt(&Value::Int(u64::MAX)), Ok(Value::Bool(true)));
+        assert_eq!(big.equals(&Value::Float(2f64.powi(64))), Value::Bool(true));
+        assert_eq!(Value::Int(0).sub(&big), Err("overflowed".to_string()));
+    }
+
+    #[test]
+    fn comparisons_order_numbers_and_strings() {
+        assert_eq!(Value::Int(1).lt(&Value::Float(1.5)), Ok(Value::Bool(true)));
+        assert_eq!(Value::Int(2).ge(&Value::Int(2)), Ok(Value::Bool(true)));
+        assert_eq!(Value::from("b").gt(&"a".into()), Ok(Value::Bool(true)));
+        assert_eq!(
+            Value::Float(f64::NAN).le(&Value::Float(f64::NAN)),
+            Ok(Value::Bool(false))
+        );
+        assert_eq!(
+            Value::Int(1).lt(&"a".into()),
+            Err("Cannot compare int and string".to_string())
+        );
+    }
+
+    #[test]
+    fn arrays_index_and_mutate() {
+        let array = Value::array(vec![Value::Int(1), "a".into()]);
+        assert_eq!(array.index(&Value::Int(1)), Ok("a".into()));
+        assert_eq!(
+            array.index(&Value::Int(2)),
+            Err(Error::IndexOutOfBounds { index: 2, len: 2 })
+        );
+        assert_eq!(array.push(Value::Float(2.5)), Ok(Value::Int(3)));
+        array.set_index(&Value::Int(0), Value::Bool(true)).unwrap();
+        assert_eq!(array.to_string(), "[true, \"a\", 2.5]");
+        assert_eq!(array.pop(), Ok(Value::Float(2.5)));
+        assert_eq!(Value::from("héllo").index(&Value::Int(1)), Ok("é".into()));
+    }
+
+    #[test]
+    fn arrays_are_shared_but_never_cyclic() {
+        let array = Value::array(vec![]);
+        let alias = array.clone();
+        alias.push(Value::Int(1)).unwrap();
+        assert_eq!(array.to_string(), "[1]");
+        let outer = Value::array(vec![array.clone()]);
+        assert_eq!(
+            array.push(outer),
+            Err(Error::Runtime(
+                "Cannot insert an array into itself".to_string()
+            ))
+        );
+        assert_eq!(
+            array.set_index(&Value::Int(0), alias),
+            Err(Error::Runtime(
+                "Cannot insert an array into itself".to_string()
+            ))
+        );
+        assert_eq!(
+            Value::array(vec![]).pop(),
+            Err(Error::Runtime("pop: empty array".to_string()))
+        );
+    }
+
+    #[test]
+    fn maps_look_up_insert_and_search() {
+        let map = Value::map(BTreeMap::new());
+        map.set_index(&"b".into(), Value::Int(2)).unwrap();
+        map.set_index(&"a".into(), Value::Int(1)).unwrap();
+        map.set_index(&Value::Int(3), Value::array(vec![])).unwrap();
+        assert_eq!(map.to_string(), "{3: [], \"a\": 1, \"b\": 2}");
+        assert_eq!(map.index(&"a".into()), Ok(Value::Int(1)));
+        assert_eq!(
+            map.index(&"c".into()),
+            Err(Error::Runtime("Key \"c\" not found".to_string()))
+        );
+        assert_eq!(map.get(&"c".into(), Value::Int(0)), Ok(Value::Int(0)));
+        assert_eq!(map.has(&"b".into()), Ok(Value::Bool(true)));
+        assert_eq!(
+            map.has(&Value::Float(1.0)),
+            Err("Cannot use float as a map key".to_string())
+        );
+        assert_eq!(map.keys().unwrap().to_string(), "[3, \"a\", \"b\"]");
+        assert_eq!(
+            map.set_index(&"self".into(), Value::array(vec![map.clone()])),
+            Err(Error::Runtime(
+                "Cannot insert a map into itself".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn ranges_index_and_iterate() {
+        let range = Value::Int(2).range(&Value::Int(5)).unwrap();
+        assert_eq!(range.to_string(), "2..5");
+        assert_eq!(range.index(&Value::Int(1)), Ok(Value::Int(3)));
+        assert_eq!(
+            range.index(&Value::Int(3)),
+            Err(Error::IndexOutOfBounds { index: 3, len: 3 })
+        );
+        assert_eq!(range.has(&Value::Float(4.0)), Ok(Value::Bool(true)));
+        assert_eq!(range.has(&Value::Int(5)), Ok(Value::Bool(false)));
+        assert_eq!(range.nth(2), Some(Value::Int(4)));
+        assert_eq!(range.nth(3), None);
+        assert_eq!(Value::Range(5, 2).nth(0), None);
+        assert_eq!(
+            Value::Int(1).range(&Value::Float(2.0)),
+            Err("Cannot make a range of int and float".to_string())
+        );
+    }
+
+    #[test]
+    fn iterables_snapshot_strings_and_map_keys() {
+        assert_eq!(
+            Value::from("ab").iterable().unwrap().to_string(),
+            "[\"a\", \"b\"]"
+        );
+        let map = Value::map(BTreeMap::from([(Key::Int(1), Value::Nil)]));
+        assert_eq!(map.iterable().unwrap().nth(0), Some(Value::Int(1)));
+        assert_eq!(
+            Value::Bool(true).iterable(),
+            Err(Error::Runtime("Cannot iterate over bool".to_string()))
+        );
+    }
+
+    #[test]
+    fn floats_display_with_a_fraction() {
+        assert_eq!(Value::Float(2.0).to_string(), "2.0");
+        assert_eq!(Value::Float(1.5).to_string(), "1.5");
+    }
+}